@@ -0,0 +1,84 @@
+use anyhow::{bail, Context, Result};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Reads the commands of a --batch file: one per line, for the shell to
+/// run, skipping blank lines and lines starting with `#`.
+pub fn load(path: &Path) -> Result<Vec<String>> {
+    let text = fs::read_to_string(path).with_context(|| format!("Unable to read batch file {:?}", path))?;
+    let commands: Vec<String> = text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).map(String::from).collect();
+
+    if commands.is_empty() {
+        bail!("Batch file {:?} has no commands", path);
+    }
+
+    Ok(commands)
+}
+
+/// The shell running one command of a batch.
+pub fn command(line: &str) -> Command {
+    #[cfg(unix)] {
+        let mut command = Command::new("/bin/sh");
+        command.arg("-c").arg(line);
+        command
+    }
+
+    #[cfg(windows)] {
+        use std::os::windows::process::CommandExt;
+
+        let mut command = Command::new("cmd");
+        command.arg("/C").raw_arg(line);
+        command
+    }
+}
+
+/// How one command of a batch ended.
+pub enum Outcome {
+    NotRun,
+    Exited(i32),
+    /// It couldn't be started, or waited on.
+    Failed(String),
+}
+
+/// How each command of a batch ended, in the order they were listed.
+pub struct Summary {
+    outcomes: Vec<(String, Outcome)>,
+}
+
+impl Summary {
+    pub fn new(commands: &[String]) -> Summary {
+        Summary { outcomes: commands.iter().map(|command| (command.clone(), Outcome::NotRun)).collect() }
+    }
+
+    pub fn record(&mut self, index: usize, outcome: Outcome) {
+        self.outcomes[index].1 = outcome;
+    }
+
+    /// Whether every command ran and exited with 0.
+    pub fn succeeded(&self) -> bool {
+        self.outcomes.iter().all(|(_, outcome)| matches!(outcome, Outcome::Exited(0)))
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = |wanted: fn(&Outcome) -> bool| self.outcomes.iter().filter(|(_, outcome)| wanted(outcome)).count();
+        let succeeded = count(|outcome| matches!(outcome, Outcome::Exited(0)));
+        let not_run = count(|outcome| matches!(outcome, Outcome::NotRun));
+
+        write!(f, "nicer: batch of {}: {} succeeded, {} failed, {} not run", self.outcomes.len(), succeeded, self.outcomes.len() - succeeded - not_run, not_run)?;
+
+        for (command, outcome) in &self.outcomes {
+            match outcome {
+                Outcome::Exited(0) => {}
+                Outcome::Exited(code) => write!(f, "\nnicer:   exited with {}: {}", code, command)?,
+                Outcome::Failed(error) => write!(f, "\nnicer:   failed ({}): {}", error, command)?,
+                Outcome::NotRun => write!(f, "\nnicer:   not run: {}", command)?,
+            }
+        }
+
+        Ok(())
+    }
+}
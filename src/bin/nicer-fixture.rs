@@ -0,0 +1,172 @@
+//! A stand-in program for nicer's integration tests. It runs its arguments
+//! as a little script of commands, in order:
+//!
+//! - `nice`: print its niceness
+//! - `ioprio`: print its I/O priority class (Linux only)
+//! - `sched`: print its scheduling policy (Linux only)
+//! - `oom`: print its oom_score_adj (Linux only)
+//! - `affinity`: print the CPUs it may run on, e.g. `0,1` (Linux only)
+//! - `nproc`: print its RLIMIT_NPROC soft limit (Unix only)
+//! - `echo WORD...`: print the rest of its arguments, one per line
+//! - `env NAME`: print an environment variable, or nothing if it isn't set
+//! - `cwd`: print its working directory
+//! - `argv0`: print the name it was started under
+//! - `ready`: print "ready", so tests know it has started
+//! - `sleep SECONDS`: sleep
+//! - `exit CODE`: exit with this code
+//! - `raise SIGNAL`: die of this signal, without leaving a core dump (Unix
+//!   only)
+//! - `count-interrupts`: count SIGINTs from now on rather than die of one
+//!   (Unix only)
+//! - `interrupts`: print how many SIGINTs it has counted
+//!
+//! Not meant to be run by hand.
+
+use std::env;
+use std::io::{self, Write};
+use std::process;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(unix)]
+fn nice() -> i32 {
+    unsafe { nix::libc::getpriority(nix::libc::PRIO_PROCESS as _, 0) }
+}
+
+#[cfg(windows)]
+fn nice() -> i32 {
+    0
+}
+
+#[cfg(unix)]
+fn raise(signal: i32) {
+    use nix::libc;
+
+    let none = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+
+    unsafe {
+        libc::setrlimit(libc::RLIMIT_CORE, &none);
+        // The Rust runtime has a SIGSEGV handler of its own.
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+#[cfg(windows)]
+fn raise(_: i32) {
+    panic!("raise needs Unix signals");
+}
+
+#[cfg(unix)]
+fn nproc() -> nix::libc::rlim_t {
+    use nix::libc;
+
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+
+    unsafe {
+        libc::getrlimit(libc::RLIMIT_NPROC, &mut limit);
+    }
+
+    limit.rlim_cur
+}
+
+static INTERRUPTS: AtomicU32 = AtomicU32::new(0);
+
+#[cfg(unix)]
+extern "C" fn interrupted(_: nix::libc::c_int) {
+    INTERRUPTS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+fn count_interrupts() {
+    unsafe {
+        nix::libc::signal(nix::libc::SIGINT, interrupted as extern "C" fn(nix::libc::c_int) as nix::libc::sighandler_t);
+    }
+}
+
+#[cfg(windows)]
+fn count_interrupts() {
+    panic!("count-interrupts needs Unix signals");
+}
+
+#[cfg(windows)]
+fn nproc() -> u64 {
+    0
+}
+
+#[cfg(target_os = "linux")]
+fn ioprio() -> i64 {
+    const IOPRIO_WHO_PROCESS: i64 = 1;
+    const IOPRIO_CLASS_SHIFT: i64 = 13;
+
+    unsafe { nix::libc::syscall(nix::libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, 0) >> IOPRIO_CLASS_SHIFT }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn ioprio() -> i64 {
+    0
+}
+
+#[cfg(target_os = "linux")]
+fn affinity() -> String {
+    use nix::libc;
+
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) };
+
+    (0..libc::CPU_SETSIZE as usize).filter(|cpu| unsafe { libc::CPU_ISSET(*cpu, &set) }).map(|cpu| cpu.to_string()).collect::<Vec<_>>().join(",")
+}
+
+#[cfg(not(target_os = "linux"))]
+fn affinity() -> String {
+    String::new()
+}
+
+#[cfg(target_os = "linux")]
+fn oom() -> String {
+    std::fs::read_to_string("/proc/self/oom_score_adj").unwrap_or_default().trim().to_string()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn oom() -> String {
+    String::new()
+}
+
+#[cfg(target_os = "linux")]
+fn sched() -> i32 {
+    unsafe { nix::libc::sched_getscheduler(0) }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sched() -> i32 {
+    0
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    while let Some(command) = args.next() {
+        match command.as_str() {
+            "nice" => println!("{}", nice()),
+            "ioprio" => println!("{}", ioprio()),
+            "sched" => println!("{}", sched()),
+            "oom" => println!("{}", oom()),
+            "affinity" => println!("{}", affinity()),
+            "nproc" => println!("{}", nproc()),
+            "echo" => args.by_ref().for_each(|word| println!("{}", word)),
+            "env" => println!("{}", env::var(args.next().expect("env needs a name")).unwrap_or_default()),
+            "cwd" => println!("{}", env::current_dir().unwrap().display()),
+            "argv0" => println!("{}", env::args().next().unwrap_or_default()),
+            "ready" => println!("ready"),
+            "sleep" => thread::sleep(Duration::from_secs_f64(args.next().expect("sleep needs seconds").parse().unwrap())),
+            "raise" => raise(args.next().expect("raise needs a signal").parse().unwrap()),
+            "count-interrupts" => count_interrupts(),
+            "interrupts" => println!("{}", INTERRUPTS.load(Ordering::SeqCst)),
+            "exit" => process::exit(args.next().expect("exit needs a code").parse().unwrap()),
+            other => panic!("unknown fixture command {:?}", other),
+        }
+
+        io::stdout().flush().unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use anyhow::{anyhow, bail, Context, Result};
+use nix::libc;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+/// Where the cgroup v2 hierarchy is mounted, if it is.
+fn mount_point() -> Option<PathBuf> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+
+    mountinfo.lines().find_map(|line| {
+        let (mount, source) = line.split_once(" - ")?;
+
+        match source.split(' ').next()? {
+            "cgroup2" => mount.split(' ').nth(4).map(PathBuf::from),
+            _ => None,
+        }
+    })
+}
+
+/// Our own cgroup, relative to the cgroup v2 mount point.
+fn own_cgroup() -> Option<PathBuf> {
+    let cgroup = fs::read_to_string("/proc/self/cgroup").ok()?;
+
+    cgroup.lines().find_map(|line| line.strip_prefix("0::")).map(|path| PathBuf::from(path.trim_start_matches('/')))
+}
+
+fn writable(path: &Path) -> bool {
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// The cpu.weight that stands for `nice`, weighed the way the kernel weighs
+/// niceness: each step is worth 25%.
+pub fn cpu_weight(nice: i32) -> u64 {
+    (100.0 * 1.25f64.powi(-nice)).round().clamp(1.0, 10_000.0) as u64
+}
+
+/// A transient cgroup holding the program and everything it spawns, removed
+/// again when dropped.
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Creates a cgroup with all of `controllers` available.
+    ///
+    /// cgroup v2 won't enable controllers below a group that still has
+    /// processes of its own (we are one of them), so the new group is made a
+    /// sibling of ours instead, in the closest ancestor that has the
+    /// controllers enabled and lets us move processes into it. On a systemd
+    /// machine that is usually the delegated `user@.service` subtree.
+    pub fn create(controllers: &[&str]) -> Result<Cgroup> {
+        let mount = mount_point().ok_or_else(|| anyhow!("cgroup v2 is not mounted"))?;
+        let own = own_cgroup().ok_or_else(|| anyhow!("Unable to find our own cgroup"))?;
+        let name = format!("nicer-{}", process::id());
+
+        // The root group is the one exception to the rule above.
+        let skip = if own.as_os_str().is_empty() { 0 } else { 1 };
+
+        for parent in own.ancestors().skip(skip) {
+            let parent = mount.join(parent);
+            let enabled = fs::read_to_string(parent.join("cgroup.subtree_control")).unwrap_or_default();
+
+            if !controllers.iter().all(|controller| enabled.split_whitespace().any(|name| name == *controller)) {
+                continue;
+            }
+
+            // Moving the program in needs write access to the common ancestor too.
+            if !writable(&parent.join("cgroup.procs")) {
+                continue;
+            }
+
+            let path = parent.join(&name);
+
+            if fs::create_dir(&path).is_ok() {
+                return Ok(Cgroup { path });
+            }
+        }
+
+        bail!("No cgroup we can write to has {} enabled", controllers.join(", "))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(&self, file: &str, value: &str) -> Result<()> {
+        fs::write(self.path.join(file), value).with_context(|| format!("Unable to write {:?} to {}", value, file))
+    }
+
+    pub fn read(&self, file: &str) -> Result<String> {
+        fs::read_to_string(self.path.join(file)).with_context(|| format!("Unable to read {}", file))
+    }
+
+    /// Freezes everything in the cgroup at once, or thaws it again.
+    pub fn freeze(&self, freeze: bool) -> Result<()> {
+        self.write("cgroup.freeze", if freeze { "1" } else { "0" })
+    }
+
+    pub fn frozen(&self) -> bool {
+        self.read("cgroup.freeze").is_ok_and(|freeze| freeze.trim() == "1")
+    }
+
+    /// Reads a flat-keyed file like `memory.events` as `(key, value)` pairs.
+    pub fn counters(&self, file: &str) -> Result<Vec<(String, u64)>> {
+        Ok(self
+            .read(file)?
+            .lines()
+            .filter_map(|line| {
+                let (key, value) = line.split_once(' ')?;
+                Some((key.to_string(), value.trim().parse().ok()?))
+            })
+            .collect())
+    }
+
+    /// Has the program move itself into the cgroup before it starts, so it
+    /// can't fork anything that escapes.
+    pub fn attach(&self, command: &mut Command) {
+        let procs = CString::new(self.path.join("cgroup.procs").as_os_str().as_bytes()).unwrap();
+
+        unsafe {
+            command.pre_exec(move || {
+                let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                // "0" stands for whichever process does the writing.
+                let written = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+                let error = io::Error::last_os_error();
+                libc::close(fd);
+
+                match written {
+                    1 => Ok(()),
+                    _ => Err(error),
+                }
+            });
+        }
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // Whatever is left in there shouldn't be stuck frozen either.
+        if self.frozen() {
+            let _ = self.freeze(false);
+        }
+
+        // This only fails if something the program left behind is still
+        // running in there; it's gone the moment that exits too.
+        if let Err(error) = fs::remove_dir(&self.path) {
+            verbose!("unable to remove cgroup {:?}: {}", self.path, error);
+        }
+    }
+}
+
+/// Surfaces memory pressure from `memory.events` as it happens.
+pub struct MemoryEvents {
+    high: u64,
+    max: u64,
+    oom_kill: u64,
+}
+
+impl MemoryEvents {
+    pub fn new() -> MemoryEvents {
+        MemoryEvents { high: 0, max: 0, oom_kill: 0 }
+    }
+
+    pub fn check(&mut self, cgroup: &Cgroup) {
+        let counters = match cgroup.counters("memory.events") {
+            Ok(counters) => counters,
+            Err(_) => return,
+        };
+
+        for (key, value) in counters {
+            let (last, what) = match key.as_str() {
+                "high" => (&mut self.high, "was throttled for going over its memory limit"),
+                "max" => (&mut self.max, "hit its hard memory limit"),
+                "oom_kill" => (&mut self.oom_kill, "had a process killed for running out of memory"),
+                _ => continue,
+            };
+
+            if value > *last {
+                verbose!("the program {} ({} times so far)", what, value);
+                *last = value;
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use anyhow::Result;
+use std::fmt;
+
+/// How one requirement of a run fared under --check.
+enum Outcome {
+    Met,
+    /// Not met, but the run would carry on without it, as described.
+    Fallback(String),
+    Failed(anyhow::Error),
+}
+
+/// What --check found, one requirement at a time.
+#[derive(Default)]
+pub struct Report {
+    checks: Vec<(String, Outcome)>,
+}
+
+impl Report {
+    pub fn add(&mut self, what: &str, result: Result<()>) {
+        let outcome = match result {
+            Ok(()) => Outcome::Met,
+            Err(error) => Outcome::Failed(error),
+        };
+
+        self.checks.push((what.to_string(), outcome));
+    }
+
+    /// Records a requirement that isn't met but that the run can do without.
+    pub fn fallback(&mut self, what: &str, note: String) {
+        self.checks.push((what.to_string(), Outcome::Fallback(note)));
+    }
+
+    /// Whether the run would start as asked.
+    pub fn passed(&self) -> bool {
+        !self.checks.iter().any(|(_, outcome)| matches!(outcome, Outcome::Failed(_)))
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (what, outcome)) in self.checks.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+
+            match outcome {
+                Outcome::Met => write!(f, "ok: {}", what)?,
+                Outcome::Fallback(note) => write!(f, "fallback: {}: {}", what, note)?,
+                Outcome::Failed(error) => write!(f, "failed: {}: {:#}", what, error)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs `probe` in a throwaway child process, so that whatever it changes
+/// (say, a priority we couldn't take back) goes away with it.
+#[cfg(unix)]
+pub fn in_child(probe: impl FnOnce() -> Result<()>) -> Result<()> {
+    use anyhow::{anyhow, Context};
+    use nix::libc;
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{close, fork, pipe, ForkResult};
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+
+    let (reader, writer) = pipe().context("Unable to make a pipe for the probe")?;
+
+    match fork().context("Unable to fork a probe")? {
+        ForkResult::Child => {
+            let _ = close(reader);
+
+            let code = match probe() {
+                Ok(()) => 0,
+                Err(error) => {
+                    let message = format!("{:#}", error);
+
+                    unsafe {
+                        libc::write(writer, message.as_ptr() as _, message.len());
+                    }
+
+                    1
+                }
+            };
+
+            unsafe { libc::_exit(code) }
+        }
+        ForkResult::Parent { child } => {
+            let _ = close(writer);
+
+            let mut message = String::new();
+            let mut reader = unsafe { File::from_raw_fd(reader) };
+            let _ = reader.read_to_string(&mut message);
+
+            match waitpid(child, None) {
+                Ok(WaitStatus::Exited(_, 0)) => Ok(()),
+                _ if !message.is_empty() => Err(anyhow!(message)),
+                _ => Err(anyhow!("The probe crashed")),
+            }
+        }
+    }
+}
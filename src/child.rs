@@ -0,0 +1,164 @@
+use anyhow::{Context, Result};
+use nix::libc;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+/// Has the program start with `mask` as its file creation mask.
+pub fn umask(command: &mut Command, mask: u32) {
+    unsafe {
+        command.pre_exec(move || {
+            libc::umask(mask as libc::mode_t);
+            Ok(())
+        });
+    }
+}
+
+/// Has the program start confined to `root`, in `cwd` (inside `root`) or at
+/// its top.
+///
+/// This has to be the last step that touches our own file system: anything
+/// after it (e.g. joining a cgroup through /sys) would resolve paths inside
+/// `root` instead. Privileges, on the other hand, may only be dropped after
+/// it, since chroot() takes root.
+pub fn chroot(command: &mut Command, root: &Path, cwd: Option<&Path>) -> Result<()> {
+    let root = CString::new(root.as_os_str().as_bytes()).context("The chroot directory contains a NUL byte")?;
+    let cwd = CString::new(cwd.unwrap_or_else(|| Path::new("/")).as_os_str().as_bytes()).context("The working directory contains a NUL byte")?;
+
+    unsafe {
+        command.pre_exec(move || {
+            if libc::chroot(root.as_ptr()) != 0 || libc::chdir(cwd.as_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+
+    Ok(())
+}
+
+/// Writes `contents` to the NUL-terminated `path`, without allocating, as
+/// between fork and exec.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn write_file(path: &[u8], contents: &[u8]) -> io::Result<()> {
+    unsafe {
+        let fd = libc::open(path.as_ptr() as *const libc::c_char, libc::O_WRONLY | libc::O_CLOEXEC);
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let written = libc::write(fd, contents.as_ptr() as *const libc::c_void, contents.len());
+        let error = io::Error::last_os_error();
+        libc::close(fd);
+
+        match written {
+            -1 => Err(error),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Brings up the loopback interface of the network namespace we're in,
+/// which starts out down.
+#[cfg(target_os = "linux")]
+fn loopback_up() -> io::Result<()> {
+    #[repr(C)]
+    struct IfReq {
+        name: [u8; 16],
+        flags: libc::c_short,
+        padding: [u8; 22],
+    }
+
+    unsafe {
+        let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+
+        if socket < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut request = IfReq { name: [0; 16], flags: 0, padding: [0; 22] };
+        request.name[..2].copy_from_slice(b"lo");
+
+        let mut status = libc::ioctl(socket, libc::SIOCGIFFLAGS as _, &mut request);
+
+        if status == 0 {
+            request.flags |= libc::IFF_UP as libc::c_short;
+            status = libc::ioctl(socket, libc::SIOCSIFFLAGS as _, &mut request);
+        }
+
+        let error = io::Error::last_os_error();
+        libc::close(socket);
+
+        match status {
+            0 => Ok(()),
+            _ => Err(error),
+        }
+    }
+}
+
+/// How to get the program a network namespace of its own: root can just
+/// make one, anyone else needs a user namespace to make it in.
+#[cfg(target_os = "linux")]
+#[derive(Clone)]
+pub struct NetworkIsolation {
+    /// The uid and gid maps keeping our ids, if we need a user namespace.
+    maps: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+#[cfg(target_os = "linux")]
+impl NetworkIsolation {
+    pub fn new() -> NetworkIsolation {
+        let uid = nix::unistd::geteuid();
+        let gid = nix::unistd::getegid();
+
+        let maps = match uid.is_root() {
+            true => None,
+            false => Some((format!("{} {} 1", uid, uid).into_bytes(), format!("{} {} 1", gid, gid).into_bytes())),
+        };
+
+        NetworkIsolation { maps }
+    }
+
+    /// Moves the calling process into a new network namespace, with only a
+    /// loopback interface.
+    pub fn enter(&self) -> io::Result<()> {
+        let flags = match self.maps {
+            Some(_) => libc::CLONE_NEWUSER | libc::CLONE_NEWNET,
+            None => libc::CLONE_NEWNET,
+        };
+
+        if unsafe { libc::unshare(flags) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if let Some((uid_map, gid_map)) = &self.maps {
+            // Unprivileged gid maps are only allowed once setgroups() is off.
+            write_file(b"/proc/self/setgroups\0", b"deny")?;
+            write_file(b"/proc/self/uid_map\0", uid_map)?;
+            write_file(b"/proc/self/gid_map\0", gid_map)?;
+        }
+
+        loopback_up()
+    }
+
+    /// Whether this needs a user namespace, i.e. we aren't root.
+    pub fn needs_user_namespace(&self) -> bool {
+        self.maps.is_some()
+    }
+}
+
+/// Has the program start in a network namespace of its own, with only a
+/// loopback interface.
+///
+/// This needs /proc, so it has to come before a chroot.
+#[cfg(target_os = "linux")]
+pub fn isolate_network(command: &mut Command, isolation: NetworkIsolation) {
+    unsafe {
+        command.pre_exec(move || isolation.enter());
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+
+/// Where a setting's value came from, lowest precedence first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    /// A flag on the command line.
+    Flag(&'static str),
+    JobFile,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => f.write_str("default"),
+            Source::Flag(flag) => f.write_str(flag),
+            Source::JobFile => f.write_str("job file"),
+        }
+    }
+}
+
+impl serde::Serialize for Source {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Setting {
+    name: &'static str,
+    value: String,
+    source: Source,
+}
+
+/// The settings a run would use, each with where it came from, for
+/// --print-config.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Settings {
+    settings: Vec<Setting>,
+}
+
+impl Settings {
+    pub fn add(&mut self, name: &'static str, value: impl fmt::Display, source: Source) {
+        self.settings.push(Setting { name, value: value.to_string(), source });
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name_width = self.settings.iter().map(|setting| setting.name.len()).max().unwrap_or(0);
+        let value_width = self.settings.iter().map(|setting| setting.value.chars().count()).max().unwrap_or(0);
+
+        for (i, setting) in self.settings.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+
+            write!(f, "{:name_width$}  {:value_width$}  {}", setting.name, setting.value, setting.source, name_width = name_width, value_width = value_width)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_settings_up() {
+        let mut settings = Settings::default();
+        settings.add("io", false, Source::Flag("--no-ioprio"));
+        settings.add("nice", 19, Source::Default);
+        settings.add("caffeinate", true, Source::JobFile);
+
+        assert_eq!(settings.to_string(), "io          false  --no-ioprio\nnice        19     default\ncaffeinate  true   job file");
+        assert_eq!(
+            serde_json::to_string(&settings).unwrap(),
+            r#"{"settings":[{"name":"io","value":"false","source":"--no-ioprio"},{"name":"nice","value":"19","source":"default"},{"name":"caffeinate","value":"true","source":"job file"}]}"#
+        );
+    }
+}
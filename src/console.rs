@@ -0,0 +1,65 @@
+use anyhow::{Context, Result};
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
+use winapi::um::consoleapi::SetConsoleCtrlHandler;
+use winapi::um::wincon::{GenerateConsoleCtrlEvent, CTRL_BREAK_EVENT, CTRL_C_EVENT};
+
+/// PID of the program Ctrl-C and Ctrl-Break are relayed to; zero until it
+/// has been spawned.
+static CHILD: AtomicU32 = AtomicU32::new(0);
+
+/// Whether the program runs in a console process group of its own, which
+/// the console doesn't pass Ctrl-C and Ctrl-Break on to.
+static OWN_GROUP: AtomicBool = AtomicBool::new(false);
+
+/// Set once the handler is in place; restarts only change the program.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Sends Ctrl-Break to the console process group `pid` leads.
+pub fn ctrl_break(pid: u32) -> io::Result<()> {
+    match unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid) } {
+        FALSE => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Keeps us alive through Ctrl-C and Ctrl-Break, so that we're still there
+/// to report how the program took them. The console already hands them to
+/// a program in our group; its own group only takes Ctrl-Break, and Ctrl-C
+/// can't be aimed at one, so it gets Ctrl-Break for either, and is killed if
+/// even that can't be sent. Closing the console, logging off and shutting
+/// down still end us as usual.
+unsafe extern "system" fn handler(event: DWORD) -> BOOL {
+    let pid = CHILD.load(Ordering::SeqCst);
+
+    match event {
+        CTRL_C_EVENT | CTRL_BREAK_EVENT if pid != 0 => {
+            if OWN_GROUP.load(Ordering::SeqCst) {
+                crate::interrupt(pid);
+            }
+
+            TRUE
+        }
+        _ => FALSE,
+    }
+}
+
+/// Relays Ctrl-C and Ctrl-Break to `pid`, which leads a console process
+/// group of its own with `own_group`, for as long as we live.
+pub fn forward_ctrl(pid: u32, own_group: bool) -> Result<()> {
+    CHILD.store(pid, Ordering::SeqCst);
+    OWN_GROUP.store(own_group, Ordering::SeqCst);
+
+    if INSTALLED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    match unsafe { SetConsoleCtrlHandler(Some(handler), TRUE) } {
+        FALSE => Err(io::Error::last_os_error()).context("Unable to set the console control handler"),
+        _ => {
+            INSTALLED.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use crate::signals;
+use anyhow::{anyhow, bail, Context, Result};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+/// PID of the current run of the program; zero until it has been spawned.
+static PID: AtomicU32 = AtomicU32::new(0);
+
+/// How many times the program has been started.
+static RUNS: AtomicU32 = AtomicU32::new(0);
+
+/// When the current run started.
+static STARTED: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Whether the program is waiting, --start-suspended, to be resumed.
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+
+/// The cgroup the program runs in, if it can be frozen.
+static CGROUP: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Something asked of us over the control socket.
+#[derive(Debug, PartialEq)]
+enum Request {
+    /// Send the program a signal.
+    Signal(Signal),
+    /// Send the program SIGHUP, which daemons take as "reload".
+    Reload,
+    /// Tell how the program is doing.
+    Status,
+    /// Freeze the program's cgroup, or thaw it.
+    Freeze(bool),
+    /// Let the --start-suspended program start.
+    Resume,
+}
+
+fn parse(line: &str) -> Result<Request> {
+    let mut words = line.split_whitespace();
+
+    let request = match words.next() {
+        Some("signal") => Request::Signal(signals::signal_named(words.next().ok_or_else(|| anyhow!("signal needs a signal name"))?)?),
+        Some("reload") => Request::Reload,
+        Some("status") => Request::Status,
+        Some("freeze") => Request::Freeze(true),
+        Some("thaw") => Request::Freeze(false),
+        Some("resume") => Request::Resume,
+        Some(other) => bail!("Unknown command {:?}, expected signal, reload, status, freeze, thaw or resume", other),
+        None => bail!("Empty command"),
+    };
+
+    match words.next() {
+        Some(extra) => bail!("Unexpected {:?} after the command", extra),
+        None => Ok(request),
+    }
+}
+
+fn respond(program: &str, line: &str) -> Result<String> {
+    let pid = PID.load(Ordering::SeqCst);
+
+    let signal = match parse(line)? {
+        Request::Signal(signal) => signal,
+        Request::Reload => Signal::SIGHUP,
+        Request::Status => {
+            let running = STARTED.lock().unwrap().map(|started| started.elapsed()).unwrap_or_default();
+            let state = match (SUSPENDED.load(Ordering::SeqCst), CGROUP.lock().unwrap().as_deref().is_some_and(frozen)) {
+                (true, _) => ", suspended",
+                (false, true) => ", frozen",
+                (false, false) => "",
+            };
+            return Ok(format!("{:?} (pid {}) running for {:.2?}, run {}{}", program, pid, running, RUNS.load(Ordering::SeqCst), state));
+        }
+        Request::Freeze(freeze) => {
+            let cgroup = CGROUP.lock().unwrap().clone().ok_or_else(|| anyhow!("The program can't be frozen without --cgroup-freeze"))?;
+            fs::write(cgroup.join("cgroup.freeze"), if freeze { "1" } else { "0" }).context("Unable to write cgroup.freeze")?;
+            return Ok(format!("{} pid {} and its descendants", if freeze { "froze" } else { "thawed" }, pid));
+        }
+        Request::Resume => {
+            if !SUSPENDED.load(Ordering::SeqCst) {
+                bail!("The program isn't waiting to be resumed");
+            }
+
+            signals::request_resume();
+            return Ok(format!("resuming pid {}", pid));
+        }
+    };
+
+    if pid == 0 {
+        bail!("The program hasn't started yet");
+    }
+
+    kill(Pid::from_raw(pid as i32), signal).with_context(|| format!("Unable to send {} to pid {}", signal, pid))?;
+    Ok(format!("sent {} to pid {}", signal, pid))
+}
+
+fn serve(program: &str, stream: UnixStream) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        verbose!("control socket: {}", line);
+
+        let response = match respond(program, &line) {
+            Ok(response) => format!("ok: {}\n", response),
+            Err(error) => format!("error: {:#}\n", error),
+        };
+
+        writer.write_all(response.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Our end of the control socket, removed when dropped.
+pub struct Control {
+    path: PathBuf,
+}
+
+/// Listens for commands on a Unix socket at `path`, one per line:
+/// `signal NAME`, `reload` (SIGHUP), `status`, `freeze`, `thaw` or `resume`.
+pub fn listen(path: &Path, program: String) -> Result<Control> {
+    // A socket nobody answers on is left over from a nicer that died.
+    if path.exists() && UnixStream::connect(path).is_err() {
+        fs::remove_file(path).with_context(|| format!("Unable to remove the stale control socket {:?}", path))?;
+    }
+
+    let listener = UnixListener::bind(path).with_context(|| format!("Unable to listen on {:?}", path))?;
+    verbose!("listening for commands on {:?}", path);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(error) = serve(&program, stream) {
+                verbose!("control socket: {}", error);
+            }
+        }
+    });
+
+    Ok(Control { path: path.to_path_buf() })
+}
+
+/// Lets `freeze` and `thaw` act on the program's cgroup at `path`.
+#[cfg(target_os = "linux")]
+pub fn freezable(path: &Path) {
+    *CGROUP.lock().unwrap() = Some(path.to_path_buf());
+}
+
+/// Whether the cgroup at `path` is frozen.
+fn frozen(path: &Path) -> bool {
+    fs::read_to_string(path.join("cgroup.freeze")).is_ok_and(|freeze| freeze.trim() == "1")
+}
+
+/// Tells `resume` and `status` whether the program is waiting to be resumed.
+pub fn suspended(suspended: bool) {
+    SUSPENDED.store(suspended, Ordering::SeqCst);
+}
+
+/// Points the control socket at a new run of the program.
+pub fn started(pid: u32) {
+    PID.store(pid, Ordering::SeqCst);
+    RUNS.fetch_add(1, Ordering::SeqCst);
+    *STARTED.lock().unwrap() = Some(Instant::now());
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests() {
+        assert_eq!(parse("signal TERM").unwrap(), Request::Signal(Signal::SIGTERM));
+        assert_eq!(parse("signal kill\n").unwrap(), Request::Signal(Signal::SIGKILL));
+        assert_eq!(parse("reload").unwrap(), Request::Reload);
+        assert_eq!(parse(" status ").unwrap(), Request::Status);
+        assert_eq!(parse("freeze").unwrap(), Request::Freeze(true));
+        assert_eq!(parse("thaw").unwrap(), Request::Freeze(false));
+        assert_eq!(parse("resume").unwrap(), Request::Resume);
+        assert!(parse("signal").is_err());
+        assert!(parse("signal NOPE").is_err());
+        assert!(parse("status now").is_err());
+        assert!(parse("").is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::Result;
+
+/// CPUs by number; an alias so that structopt takes --cpus as one value.
+pub type CpuList = Vec<usize>;
+
+/// One past the highest CPU a cpu_set_t has room for.
+#[cfg(target_os = "linux")]
+const MAX_CPUS: usize = nix::libc::CPU_SETSIZE as usize;
+
+/// One past the highest CPU in processor group 0, the one Windows pins to.
+#[cfg(not(target_os = "linux"))]
+const MAX_CPUS: usize = 64;
+
+/// Parses a CPU list in the kernel's format, e.g. `0-3,8,10-11`.
+pub fn parse_cpu_list(text: &str) -> Result<CpuList> {
+    use anyhow::anyhow;
+
+    let mut cpus = Vec::new();
+
+    for range in text.trim().split(',').filter(|range| !range.is_empty()) {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let first: usize = first.trim().parse().map_err(|_| anyhow!("Invalid CPU list {:?}", text))?;
+        let last: usize = last.trim().parse().map_err(|_| anyhow!("Invalid CPU list {:?}", text))?;
+
+        if last < first {
+            return Err(anyhow!("Invalid CPU range {:?}", range));
+        }
+
+        if last >= MAX_CPUS {
+            return Err(anyhow!("CPU {} is out of range; CPUs go up to {}", last, MAX_CPUS - 1));
+        }
+
+        cpus.extend(first..=last);
+    }
+
+    Ok(cpus)
+}
+
+/// The efficiency cores of a hybrid CPU, if this is one and we can tell.
+///
+/// Intel hybrid parts list their E-cores under the `cpu_atom` PMU; on ARM
+/// big.LITTLE systems they are the cores with the lowest `cpu_capacity`.
+#[cfg(target_os = "linux")]
+pub fn efficiency_cores() -> Option<Vec<usize>> {
+    use std::fs;
+
+    if let Ok(atom) = fs::read_to_string("/sys/devices/cpu_atom/cpus") {
+        return parse_cpu_list(&atom).ok().filter(|cpus| !cpus.is_empty());
+    }
+
+    let online = parse_cpu_list(&fs::read_to_string("/sys/devices/system/cpu/online").ok()?).ok()?;
+    let mut capacities = Vec::new();
+
+    for cpu in online {
+        let capacity: u64 = fs::read_to_string(format!("/sys/devices/system/cpu/cpu{}/cpu_capacity", cpu)).ok()?.trim().parse().ok()?;
+        capacities.push((cpu, capacity));
+    }
+
+    let lowest = capacities.iter().map(|(_, capacity)| *capacity).min()?;
+    let highest = capacities.iter().map(|(_, capacity)| *capacity).max()?;
+
+    match lowest < highest {
+        true => Some(capacities.into_iter().filter(|(_, capacity)| *capacity == lowest).map(|(cpu, _)| cpu).collect()),
+        false => None,
+    }
+}
+
+/// Has the program start pinned to `cpus`.
+#[cfg(target_os = "linux")]
+pub fn set_affinity(command: &mut std::process::Command, cpus: &[usize]) {
+    use nix::libc;
+    use std::os::unix::process::CommandExt;
+
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+
+    for cpu in cpus {
+        unsafe { libc::CPU_SET(*cpu, &mut set) };
+    }
+
+    unsafe {
+        command.pre_exec(move || match libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error()),
+        });
+    }
+}
+
+/// The efficiency cores in processor group 0, as reported by the scheduler's
+/// CPU sets, if they differ from the rest.
+#[cfg(windows)]
+pub fn efficiency_cores() -> Option<Vec<usize>> {
+    use winapi::shared::minwindef::{BOOL, PULONG, ULONG};
+    use winapi::um::winnt::{HANDLE, PSYSTEM_CPU_SET_INFORMATION, SYSTEM_CPU_SET_INFORMATION};
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetSystemCpuSetInformation(Information: PSYSTEM_CPU_SET_INFORMATION, BufferLength: ULONG, ReturnedLength: PULONG, Process: HANDLE, Flags: ULONG) -> BOOL;
+    }
+
+    unsafe {
+        let mut length: ULONG = 0;
+        GetSystemCpuSetInformation(std::ptr::null_mut(), 0, &mut length, std::ptr::null_mut(), 0);
+
+        if length == 0 {
+            return None;
+        }
+
+        // u64s keep the buffer aligned for the structs inside it.
+        let mut buffer = vec![0u64; (length as usize).div_ceil(8)];
+
+        if GetSystemCpuSetInformation(buffer.as_mut_ptr() as PSYSTEM_CPU_SET_INFORMATION, length, &mut length, std::ptr::null_mut(), 0) == 0 {
+            return None;
+        }
+
+        let mut cores = Vec::new();
+        let mut offset = 0;
+
+        while offset + std::mem::size_of::<SYSTEM_CPU_SET_INFORMATION>() <= length as usize {
+            let entry = &*((buffer.as_ptr() as *const u8).add(offset) as *const SYSTEM_CPU_SET_INFORMATION);
+
+            if entry.CpuSet.Group == 0 {
+                cores.push((entry.CpuSet.LogicalProcessorIndex as usize, entry.CpuSet.EfficiencyClass));
+            }
+
+            offset += entry.Size as usize;
+
+            if entry.Size == 0 {
+                break;
+            }
+        }
+
+        // Lower efficiency classes are the more efficient cores.
+        let lowest = cores.iter().map(|(_, class)| *class).min()?;
+        let highest = cores.iter().map(|(_, class)| *class).max()?;
+
+        match lowest < highest {
+            true => Some(cores.into_iter().filter(|(_, class)| *class == lowest).map(|(cpu, _)| cpu).collect()),
+            false => None,
+        }
+    }
+}
+
+/// Pins a running process to `cpus` (in processor group 0).
+#[cfg(windows)]
+pub fn set_affinity(process: winapi::um::winnt::HANDLE, cpus: &[usize]) -> Result<()> {
+    use winapi::shared::basetsd::DWORD_PTR;
+    use winapi::shared::minwindef::BOOL;
+    use winapi::um::winnt::HANDLE;
+
+    // winapi declares the mask as a DWORD, which is too narrow on 64-bit.
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetProcessAffinityMask(hProcess: HANDLE, dwProcessAffinityMask: DWORD_PTR) -> BOOL;
+    }
+
+    let mask = cpus.iter().filter(|cpu| **cpu < usize::BITS as usize).fold(0usize, |mask, cpu| mask | (1 << cpu));
+
+    match unsafe { SetProcessAffinityMask(process, mask) } {
+        0 => Err(anyhow::Error::new(std::io::Error::last_os_error())),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_lists() {
+        assert_eq!(parse_cpu_list("0-3,8,10-11\n").unwrap(), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_cpu_list("5").unwrap(), vec![5]);
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+        assert_eq!(parse_cpu_list(&(MAX_CPUS - 1).to_string()).unwrap(), vec![MAX_CPUS - 1]);
+        assert!(parse_cpu_list(&MAX_CPUS.to_string()).is_err());
+        assert!(parse_cpu_list("2000").is_err());
+        assert!(parse_cpu_list("0-4000000000").is_err());
+    }
+}
@@ -0,0 +1,257 @@
+use anyhow::{bail, Result};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// An absolute time to stop the program at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deadline {
+    /// Year, month and day; without one, the next time the clock reads
+    /// `time`.
+    date: Option<(i64, u32, u32)>,
+    /// Hour, minute and second.
+    time: (u32, u32, u32),
+    /// UTC rather than local time.
+    utc: bool,
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The date `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    (days_from_civil(year + i64::from(month == 12), month % 12 + 1, 1) - days_from_civil(year, month, 1)) as u32
+}
+
+fn epoch(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    }
+}
+
+fn number(text: &str, what: &str, deadline: &str) -> Result<u32> {
+    match text.len() {
+        1..=4 if text.bytes().all(|c| c.is_ascii_digit()) => Ok(text.parse()?),
+        _ => bail!("Invalid {} {:?} in deadline {:?}", what, text, deadline),
+    }
+}
+
+impl std::str::FromStr for Deadline {
+    type Err = anyhow::Error;
+
+    /// Parses `HH:MM[:SS]` or `YYYY-MM-DD HH:MM[:SS]` (or with a `T` in
+    /// between), in local time unless it ends in `Z`.
+    fn from_str(text: &str) -> Result<Deadline> {
+        let trimmed = text.trim();
+        let (trimmed, utc) = match trimmed.strip_suffix('Z').or_else(|| trimmed.strip_suffix('z')) {
+            Some(rest) => (rest, true),
+            None => (trimmed, false),
+        };
+
+        let (date, time) = match trimmed.split_once(['T', 't', ' ']) {
+            Some((date, time)) => (Some(date), time),
+            None => (None, trimmed),
+        };
+
+        let date = match date {
+            Some(date) => {
+                let parts: Vec<&str> = date.split('-').collect();
+
+                if parts.len() != 3 || parts[0].len() != 4 {
+                    bail!("Invalid date {:?} in deadline {:?}, expected YYYY-MM-DD", date, text);
+                }
+
+                let (year, month, day) = (number(parts[0], "year", text)? as i64, number(parts[1], "month", text)?, number(parts[2], "day", text)?);
+
+                if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+                    bail!("There is no {} in the calendar", date);
+                }
+
+                Some((year, month, day))
+            }
+            None => None,
+        };
+
+        let parts: Vec<&str> = time.split(':').collect();
+
+        if !(2..=3).contains(&parts.len()) {
+            bail!("Invalid time {:?} in deadline {:?}, expected HH:MM or HH:MM:SS", time, text);
+        }
+
+        let hour = number(parts[0], "hour", text)?;
+        let minute = number(parts[1], "minute", text)?;
+        let second = parts.get(2).map(|second| number(second, "second", text)).transpose()?.unwrap_or(0);
+
+        if hour > 23 || minute > 59 || second > 59 {
+            bail!("Invalid time {:?} in deadline {:?}", time, text);
+        }
+
+        Ok(Deadline { date, time: (hour, minute, second), utc })
+    }
+}
+
+impl fmt::Display for Deadline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((year, month, day)) = self.date {
+            write!(f, "{:04}-{:02}-{:02} ", year, month, day)?;
+        }
+
+        write!(f, "{:02}:{:02}:{:02}", self.time.0, self.time.1, self.time.2)?;
+
+        match self.utc {
+            true => write!(f, " UTC"),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Seconds since the epoch of `time` on `date` (today when `None`), local
+/// time.
+#[cfg(unix)]
+fn local(date: Option<(i64, u32, u32)>, time: (u32, u32, u32), now: i64) -> Result<i64> {
+    use nix::libc;
+
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        let now = now as libc::time_t;
+
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            bail!("Unable to tell the local time");
+        }
+
+        if let Some((year, month, day)) = date {
+            tm.tm_year = (year - 1900) as libc::c_int;
+            tm.tm_mon = month as libc::c_int - 1;
+            tm.tm_mday = day as libc::c_int;
+        }
+
+        tm.tm_hour = time.0 as libc::c_int;
+        tm.tm_min = time.1 as libc::c_int;
+        tm.tm_sec = time.2 as libc::c_int;
+        // Let mktime work out whether daylight saving time applies.
+        tm.tm_isdst = -1;
+
+        match libc::mktime(&mut tm) {
+            -1 => Err(anyhow::anyhow!("Unable to convert the deadline from local time")),
+            // time_t is only 32 bits on some platforms.
+            #[allow(clippy::useless_conversion)]
+            at => Ok(i64::from(at)),
+        }
+    }
+}
+
+/// Seconds since the epoch of `time` on `date` (today when `None`), local
+/// time.
+#[cfg(windows)]
+fn local(date: Option<(i64, u32, u32)>, time: (u32, u32, u32), _now: i64) -> Result<i64> {
+    use winapi::shared::minwindef::{FALSE, WORD};
+    use winapi::um::minwinbase::SYSTEMTIME;
+    use winapi::um::sysinfoapi::GetLocalTime;
+    use winapi::um::timezoneapi::TzSpecificLocalTimeToSystemTime;
+
+    unsafe {
+        let mut local: SYSTEMTIME = std::mem::zeroed();
+        GetLocalTime(&mut local);
+
+        if let Some((year, month, day)) = date {
+            local.wYear = year as WORD;
+            local.wMonth = month as WORD;
+            local.wDay = day as WORD;
+        }
+
+        local.wHour = time.0 as WORD;
+        local.wMinute = time.1 as WORD;
+        local.wSecond = time.2 as WORD;
+        local.wMilliseconds = 0;
+
+        let mut utc: SYSTEMTIME = std::mem::zeroed();
+
+        if TzSpecificLocalTimeToSystemTime(std::ptr::null(), &local, &mut utc) == FALSE {
+            return Err(anyhow::Error::new(std::io::Error::last_os_error()).context("Unable to convert the deadline from local time"));
+        }
+
+        let days = days_from_civil(utc.wYear as i64, utc.wMonth as u32, utc.wDay as u32);
+        Ok(days * 86_400 + utc.wHour as i64 * 3600 + utc.wMinute as i64 * 60 + utc.wSecond as i64)
+    }
+}
+
+impl Deadline {
+    /// Seconds since the epoch the deadline falls on, as seen at `now`.
+    fn resolve(&self, now: SystemTime) -> Result<i64> {
+        let now = epoch(now);
+        let (hour, minute, second) = self.time;
+
+        let at = match self.utc {
+            true => {
+                let (year, month, day) = self.date.unwrap_or_else(|| civil_from_days(now.div_euclid(86_400)));
+                days_from_civil(year, month, day) * 86_400 + (hour * 3600 + minute * 60 + second) as i64
+            }
+            false => local(self.date, self.time, now)?,
+        };
+
+        // A bare time that has gone by today means tomorrow.
+        match self.date {
+            None if at <= now => Ok(at + 86_400),
+            _ => Ok(at),
+        }
+    }
+
+    /// How long until the deadline; zero if it has passed and `allow_past`.
+    pub fn remaining(&self, allow_past: bool) -> Result<Duration> {
+        let now = SystemTime::now();
+        let left = self.resolve(now)? - epoch(now);
+
+        match left {
+            left if left > 0 => Ok(Duration::from_secs(left as u64)),
+            _ if allow_past => Ok(Duration::ZERO),
+            _ => bail!("The deadline {} has already passed", self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadlines() {
+        assert_eq!("06:00".parse::<Deadline>().unwrap(), Deadline { date: None, time: (6, 0, 0), utc: false });
+        assert_eq!("2026-03-01T23:59:30Z".parse::<Deadline>().unwrap(), Deadline { date: Some((2026, 3, 1)), time: (23, 59, 30), utc: true });
+        assert_eq!("2024-02-29 12:00".parse::<Deadline>().unwrap().to_string(), "2024-02-29 12:00:00");
+        assert!("2023-02-29 12:00".parse::<Deadline>().is_err());
+        assert!("24:00".parse::<Deadline>().is_err());
+        assert!("6".parse::<Deadline>().is_err());
+        assert!("tomorrow".parse::<Deadline>().is_err());
+    }
+
+    #[test]
+    fn utc_deadlines() {
+        let noon = UNIX_EPOCH + Duration::from_secs(days_from_civil(2026, 10, 14) as u64 * 86_400 + 12 * 3600);
+
+        assert_eq!(civil_from_days(days_from_civil(2026, 10, 14)), (2026, 10, 14));
+        assert_eq!("13:00Z".parse::<Deadline>().unwrap().resolve(noon).unwrap(), epoch(noon) + 3600);
+        assert_eq!("11:00Z".parse::<Deadline>().unwrap().resolve(noon).unwrap(), epoch(noon) + 23 * 3600);
+        assert_eq!("2026-10-14 11:00Z".parse::<Deadline>().unwrap().resolve(noon).unwrap(), epoch(noon) - 3600);
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::{anyhow, bail, Result};
+use std::time::Duration;
+
+/// Parses a duration such as `90`, `30s`, `5m`, `2h`, `1h30m` or `250ms`.
+///
+/// A bare number counts seconds. Units may be chained from largest to
+/// smallest, and each one may only appear once.
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let text = text.trim();
+
+    if text.is_empty() {
+        bail!("Empty duration");
+    }
+
+    if text.starts_with('-') {
+        bail!("Duration {:?} cannot be negative", text);
+    }
+
+    if text.bytes().all(|c| c.is_ascii_digit()) {
+        return Ok(Duration::from_secs(number(text, text)?));
+    }
+
+    const UNITS: &[(&str, u64)] = &[("d", 86_400_000), ("h", 3_600_000), ("m", 60_000), ("s", 1_000), ("ms", 1)];
+
+    let mut millis: u64 = 0;
+    let mut rest = text;
+    let mut smallest = None;
+
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+
+        if digits == 0 {
+            bail!("Expected a number in duration {:?}", text);
+        }
+
+        let value = number(&rest[..digits], text)?;
+        rest = &rest[digits..];
+
+        let unit = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let index = UNITS
+            .iter()
+            .position(|(name, _)| *name == &rest[..unit])
+            .ok_or_else(|| anyhow!("Unknown unit {:?} in duration {:?} (use d, h, m, s or ms)", &rest[..unit], text))?;
+
+        if smallest.is_some_and(|smallest| index <= smallest) {
+            bail!("Units in duration {:?} must go from largest to smallest, each used once", text);
+        }
+
+        smallest = Some(index);
+        millis = value
+            .checked_mul(UNITS[index].1)
+            .and_then(|value| millis.checked_add(value))
+            .ok_or_else(|| anyhow!("Duration {:?} is too long", text))?;
+        rest = &rest[unit..];
+    }
+
+    Ok(Duration::from_millis(millis))
+}
+
+/// Parses a duration like `parse_duration`, for the flags where none at all
+/// would make no sense: a timeout that has always passed, or an interval to
+/// spin on.
+pub fn parse_positive_duration(text: &str) -> Result<Duration> {
+    match parse_duration(text)? {
+        duration if duration.is_zero() => bail!("Duration {:?} must be more than zero", text.trim()),
+        duration => Ok(duration),
+    }
+}
+
+fn number(digits: &str, text: &str) -> Result<u64> {
+    digits.parse().map_err(|_| anyhow!("Duration {:?} is too long", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_numbers_are_seconds() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("0").unwrap(), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn single_units() {
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn combined_units() {
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse_duration("1m30s500ms").unwrap(), Duration::from_millis(90_500));
+        assert_eq!(parse_duration(" 2h5s ").unwrap(), Duration::from_secs(7205));
+    }
+
+    #[test]
+    fn invalid_inputs() {
+        for text in &["", "-5", "-5s", "5x", "s", "1h1h", "30s1m", "1.5h", "5 m", "99999999999999999999"] {
+            assert!(parse_duration(text).is_err(), "{:?} should not parse", text);
+        }
+    }
+
+    #[test]
+    fn positive_durations() {
+        assert_eq!(parse_positive_duration("1ms").unwrap(), Duration::from_millis(1));
+        assert_eq!(parse_positive_duration("30s").unwrap(), Duration::from_secs(30));
+
+        for text in &["0", "0s", "0h0m", "-5", "5x"] {
+            assert!(parse_positive_duration(text).is_err(), "{:?} should not parse", text);
+        }
+    }
+}
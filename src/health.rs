@@ -0,0 +1,75 @@
+use crate::hooks;
+use std::process::{Child, Stdio};
+use std::time::{Duration, Instant};
+
+/// A shell command run every `interval` while the program is alive, to catch
+/// it wedging without crashing.
+pub struct HealthCheck<'a> {
+    line: &'a str,
+    interval: Duration,
+    threshold: u32,
+    failures: u32,
+    next: Instant,
+    running: Option<(Child, Instant)>,
+}
+
+impl<'a> HealthCheck<'a> {
+    /// The first check runs one `interval` in, giving the program time to
+    /// start up.
+    pub fn new(line: &'a str, interval: Duration, threshold: u32) -> HealthCheck<'a> {
+        HealthCheck { line, interval, threshold, failures: 0, next: Instant::now() + interval, running: None }
+    }
+
+    fn record(&mut self, outcome: Result<(), String>) {
+        match outcome {
+            Ok(()) if self.failures > 0 => {
+                verbose!("the health check passed again after {} failures", self.failures);
+                self.failures = 0;
+            }
+            Ok(()) => {}
+            Err(reason) => {
+                self.failures += 1;
+                warning!("the health check failed ({}/{}): {}", self.failures, self.threshold, reason);
+            }
+        }
+    }
+
+    /// The check running now, if one is.
+    #[cfg(target_os = "linux")]
+    pub fn pid(&self) -> Option<u32> {
+        self.running.as_ref().map(|(child, _)| child.id())
+    }
+
+    /// Looks in on the check, starting the next one when it is due. True
+    /// once it has failed `threshold` times in a row. A check still running
+    /// when the next is due counts as failed.
+    pub fn unhealthy(&mut self, pid: u32) -> bool {
+        if let Some((child, started)) = &mut self.running {
+            let outcome = match child.try_wait() {
+                Ok(Some(status)) if status.success() => Ok(()),
+                Ok(Some(status)) => Err(status.to_string()),
+                Ok(None) if started.elapsed() < self.interval => return false,
+                Ok(None) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    Err(format!("still running after {:?}", self.interval))
+                }
+                Err(error) => Err(error.to_string()),
+            };
+
+            self.running = None;
+            self.record(outcome);
+        }
+
+        if self.failures < self.threshold && Instant::now() >= self.next {
+            self.next = Instant::now() + self.interval;
+
+            match hooks::shell(self.line).env("NICER_PID", pid.to_string()).stdin(Stdio::null()).spawn() {
+                Ok(child) => self.running = Some((child, Instant::now())),
+                Err(error) => self.record(Err(format!("unable to run it: {}", error))),
+            }
+        }
+
+        self.failures >= self.threshold
+    }
+}
@@ -0,0 +1,32 @@
+use anyhow::{Context, Result};
+use std::process::Command;
+
+/// A command line run through the platform's shell.
+pub fn shell(line: &str) -> Command {
+    #[cfg(unix)]
+    let mut command = Command::new("sh");
+    #[cfg(unix)]
+    command.arg("-c");
+
+    #[cfg(windows)]
+    let mut command = Command::new("cmd");
+    #[cfg(windows)]
+    command.arg("/C");
+
+    command.arg(line);
+    command
+}
+
+/// Runs the `name` hook to completion, with `NICER_PID` set to the program's
+/// pid. A hook that fails is reported, but doesn't stop the run.
+pub fn run(name: &str, line: &str, pid: u32) -> Result<()> {
+    verbose!("running the {} hook: {}", name, line);
+
+    let status = shell(line).env("NICER_PID", pid.to_string()).status().with_context(|| format!("Unable to run the {} hook", name))?;
+
+    if !status.success() {
+        warning!("the {} hook failed ({})", name, status);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use crate::priority::PriorityConfig;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A job spec, for schedulers that would rather write a file than a command
+/// line. Anything it sets takes precedence over our flags.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    pub program: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub cwd: Option<PathBuf>,
+    pub caffeinate: Option<bool>,
+    pub priority: Option<PriorityConfig>,
+}
+
+/// Reads a job spec, as JSON if the file name ends in `.json` and as TOML
+/// otherwise.
+pub fn load(path: &Path) -> Result<Job> {
+    let text = fs::read_to_string(path).with_context(|| format!("Unable to read job file {:?}", path))?;
+    let json = path.extension().is_some_and(|extension| extension == "json");
+
+    parse(&text, json).with_context(|| format!("Invalid job file {:?}", path))
+}
+
+fn parse(text: &str, json: bool) -> Result<Job> {
+    let job: Job = match json {
+        true => serde_json::from_str(text)?,
+        false => toml::from_str(text)?,
+    };
+
+    if job.program.as_os_str().is_empty() {
+        bail!("program must not be empty");
+    }
+
+    if let Some(cwd) = &job.cwd {
+        if !cwd.is_dir() {
+            bail!("cwd {:?} is not a directory", cwd);
+        }
+    }
+
+    Ok(job)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toml_jobs() {
+        let job = parse("program = \"gzip\"\nargs = [\"-9\", \"big.tar\"]\n\n[env]\nLANG = \"C\"\n\n[priority]\nio = false\n", false).unwrap();
+
+        assert_eq!(job.program, Path::new("gzip"));
+        assert_eq!(job.args, vec!["-9", "big.tar"]);
+        assert_eq!(job.env["LANG"], "C");
+        assert!(job.priority.is_some_and(|priority| priority.cpu && !priority.io));
+        assert!(job.caffeinate.is_none());
+    }
+
+    #[test]
+    fn json_jobs() {
+        let job = parse(r#"{"program": "make", "cwd": "/", "caffeinate": true}"#, true).unwrap();
+
+        assert_eq!(job.program, Path::new("make"));
+        assert_eq!(job.cwd, Some(PathBuf::from("/")));
+        assert_eq!(job.caffeinate, Some(true));
+    }
+
+    #[test]
+    fn invalid_jobs() {
+        assert!(parse("args = []\n", false).is_err());
+        assert!(parse("program = \"\"\n", false).is_err());
+        assert!(parse("program = \"make\"\nniceness = 3\n", false).is_err());
+        assert!(parse(r#"{"program": "make", "priority": {"disk": true}}"#, true).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use std::io;
+use std::mem;
+use std::ptr;
+use std::time::Duration;
+use winapi::shared::minwindef::{DWORD, FALSE};
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::jobapi2::{AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject};
+use winapi::um::winnt::HANDLE;
+
+/// A job object holding the program and everything it starts, which can't
+/// leave it. Unless the program is to outlive us, whatever is still in it is
+/// killed once it's dropped, or once nicer dies, since the handle goes with
+/// us.
+pub struct Job {
+    handle: HANDLE,
+}
+
+impl Job {
+    /// Creates a job that keeps its processes at `priority_class`, within
+    /// `cpu_quota` percent of one CPU and `memory_limit` bytes of committed
+    /// memory between them, and kills any of them that spends more than
+    /// `user_time` in user mode, for each of those there is.
+    pub fn new(priority_class: Option<u32>, cpu_quota: Option<u32>, memory_limit: Option<u64>, user_time: Option<Duration>, kill_on_close: bool) -> Result<Job> {
+        use winapi::um::winnt::{
+            JobObjectCpuRateControlInformation, JobObjectExtendedLimitInformation, JOBOBJECT_CPU_RATE_CONTROL_INFORMATION, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+            JOB_OBJECT_CPU_RATE_CONTROL_ENABLE, JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP, JOB_OBJECT_LIMIT_JOB_MEMORY, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE, JOB_OBJECT_LIMIT_PRIORITY_CLASS,
+            JOB_OBJECT_LIMIT_PROCESS_TIME,
+        };
+
+        let handle = unsafe { CreateJobObjectW(ptr::null_mut(), ptr::null()) };
+
+        if handle.is_null() {
+            return Err(anyhow::Error::new(io::Error::last_os_error()).context("Unable to create a job object"));
+        }
+
+        let job = Job { handle };
+
+        unsafe {
+            let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = mem::zeroed();
+
+            if kill_on_close {
+                limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            }
+
+            if let Some(class) = priority_class {
+                limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_PRIORITY_CLASS;
+                limits.BasicLimitInformation.PriorityClass = class;
+            }
+
+            if let Some(limit) = memory_limit {
+                limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_JOB_MEMORY;
+                limits.JobMemoryLimit = limit as usize;
+            }
+
+            if let Some(limit) = user_time {
+                // In 100ns ticks; the process exits with ERROR_NOT_ENOUGH_QUOTA.
+                limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_PROCESS_TIME;
+                *limits.BasicLimitInformation.PerProcessUserTimeLimit.QuadPart_mut() = (limit.as_nanos() / 100).min(i64::MAX as u128) as i64;
+            }
+
+            job.set(JobObjectExtendedLimitInformation, &mut limits).context("Unable to set the job object's limits")?;
+
+            if let Some(quota) = cpu_quota {
+                // The rate is in hundredths of a percent of the whole machine.
+                let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get()) as u32;
+                let mut rate: JOBOBJECT_CPU_RATE_CONTROL_INFORMATION = mem::zeroed();
+                rate.ControlFlags = JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP;
+                *rate.u.CpuRate_mut() = (quota * 100 / cpus).clamp(1, 10_000);
+
+                job.set(JobObjectCpuRateControlInformation, &mut rate).context("Unable to cap the job object's CPU rate")?;
+            }
+        }
+
+        Ok(job)
+    }
+
+    unsafe fn set<T>(&self, class: u32, information: &mut T) -> io::Result<()> {
+        match SetInformationJobObject(self.handle, class, information as *mut T as _, mem::size_of::<T>() as DWORD) {
+            FALSE => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Puts `process` in the job; what it starts from then on follows.
+    pub fn assign(&self, process: HANDLE) -> Result<()> {
+        match unsafe { AssignProcessToJobObject(self.handle, process) } {
+            FALSE => Err(anyhow::Error::new(io::Error::last_os_error()).context("Unable to put the program in a job object")),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.handle);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{anyhow, bail, Context, Result};
+use nix::libc;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+pub type Resource = libc::c_int;
+
+/// A resource limit placed on the program (and inherited by its children).
+#[derive(Clone, Copy, Debug)]
+pub struct Limit {
+    pub resource: Resource,
+    pub soft: libc::rlim_t,
+    pub hard: libc::rlim_t,
+}
+
+impl Limit {
+    /// A limit the program cannot raise again.
+    pub fn fixed(resource: Resource, value: u64) -> Limit {
+        Limit { resource, soft: value as libc::rlim_t, hard: value as libc::rlim_t }
+    }
+}
+
+/// The resources --rlimit knows, by the name `ulimit` and `prlimit` use.
+const RESOURCES: &[(&str, Resource)] = &[
+    ("as", libc::RLIMIT_AS),
+    ("core", libc::RLIMIT_CORE),
+    ("cpu", libc::RLIMIT_CPU),
+    ("data", libc::RLIMIT_DATA),
+    ("fsize", libc::RLIMIT_FSIZE),
+    ("memlock", libc::RLIMIT_MEMLOCK),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    ("msgqueue", libc::RLIMIT_MSGQUEUE),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    ("nice", libc::RLIMIT_NICE),
+    ("nofile", libc::RLIMIT_NOFILE),
+    ("nproc", libc::RLIMIT_NPROC),
+    ("rss", libc::RLIMIT_RSS),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    ("rtprio", libc::RLIMIT_RTPRIO),
+    #[cfg(target_os = "linux")]
+    ("rttime", libc::RLIMIT_RTTIME),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    ("sigpending", libc::RLIMIT_SIGPENDING),
+    ("stack", libc::RLIMIT_STACK),
+];
+
+/// The name of `resource`, for messages.
+fn name(resource: Resource) -> String {
+    match RESOURCES.iter().find(|(_, known)| *known == resource) {
+        Some((name, _)) => name.to_string(),
+        None => format!("resource {}", resource),
+    }
+}
+
+/// Parses one side of an --rlimit: a count, a size such as `64M`, or
+/// `unlimited`.
+fn parse_value(text: &str) -> Result<libc::rlim_t> {
+    match text.trim() {
+        "unlimited" | "infinity" => Ok(libc::RLIM_INFINITY),
+        text => Ok(crate::size::parse_size(text)? as libc::rlim_t),
+    }
+}
+
+/// Parses an --rlimit such as `nofile=1024` or `core=0:unlimited`; without a
+/// hard limit the program can't raise the soft one again.
+pub fn parse_rlimit(text: &str) -> Result<Limit> {
+    let (resource, values) = text.split_once('=').ok_or_else(|| anyhow!("Expected RESOURCE=SOFT[:HARD], not {:?}", text))?;
+    let resource = resource.trim().to_ascii_lowercase();
+    let resource = match RESOURCES.iter().find(|(name, _)| *name == resource) {
+        Some((_, resource)) => *resource,
+        None => {
+            let names: Vec<&str> = RESOURCES.iter().map(|(name, _)| *name).collect();
+            bail!("Unknown resource {:?}, expected one of {}", resource, names.join(", "));
+        }
+    };
+
+    let limit = match values.split_once(':') {
+        Some((soft, hard)) => Limit { resource, soft: parse_value(soft)?, hard: parse_value(hard)? },
+        None => {
+            let value = parse_value(values)?;
+            Limit { resource, soft: value, hard: value }
+        }
+    };
+
+    if limit.hard != libc::RLIM_INFINITY && (limit.soft == libc::RLIM_INFINITY || limit.soft > limit.hard) {
+        bail!("The soft limit for {} can't be above its hard limit", name(resource));
+    }
+
+    Ok(limit)
+}
+
+/// Makes sure every one of `limits` can be set, and names the one that can't
+/// before the program is started without it.
+pub fn check(limits: &[Limit]) -> Result<()> {
+    let root = nix::unistd::geteuid().is_root();
+
+    for limit in limits {
+        let mut current = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+
+        if unsafe { libc::getrlimit(limit.resource, &mut current) } != 0 {
+            return Err(io::Error::last_os_error()).with_context(|| format!("Unable to read the {} limit", name(limit.resource)));
+        }
+
+        let raised = current.rlim_max != libc::RLIM_INFINITY && (limit.hard == libc::RLIM_INFINITY || limit.hard > current.rlim_max);
+
+        if raised && !root {
+            bail!("Unable to raise the hard {} limit above {} without root", name(limit.resource), current.rlim_max);
+        }
+
+        // Even root can't have more open files than this.
+        #[cfg(target_os = "linux")]
+        if limit.resource == libc::RLIMIT_NOFILE {
+            let nr_open = std::fs::read_to_string("/proc/sys/fs/nr_open").ok().and_then(|text| text.trim().parse::<libc::rlim_t>().ok());
+
+            match nr_open {
+                Some(nr_open) if limit.hard > nr_open => bail!("Unable to raise the nofile limit above {} (fs.nr_open)", nr_open),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Has the program start with `limits` applied.
+pub fn apply(command: &mut Command, limits: Vec<Limit>) {
+    if limits.is_empty() {
+        return;
+    }
+
+    unsafe {
+        command.pre_exec(move || set(&limits));
+    }
+}
+
+/// Applies `limits` to ourselves.
+pub fn set(limits: &[Limit]) -> io::Result<()> {
+    for limit in limits {
+        let rlimit = libc::rlimit { rlim_cur: limit.soft, rlim_max: limit.hard };
+
+        if unsafe { libc::setrlimit(limit.resource, &rlimit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rlimits() {
+        let limit = parse_rlimit("NOFILE=1024").unwrap();
+        assert_eq!((limit.resource, limit.soft, limit.hard), (libc::RLIMIT_NOFILE, 1024, 1024));
+
+        let limit = parse_rlimit("core=0:unlimited").unwrap();
+        assert_eq!((limit.resource, limit.soft, limit.hard), (libc::RLIMIT_CORE, 0, libc::RLIM_INFINITY));
+
+        assert_eq!(parse_rlimit("fsize=1M").unwrap().soft, 1 << 20);
+        assert!(parse_rlimit("files=10").is_err());
+        assert!(parse_rlimit("nofile").is_err());
+        assert!(parse_rlimit("nofile=20:10").is_err());
+        assert!(parse_rlimit("stack=unlimited:8M").is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::{bail, Result};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often --wait-for-load looks at the load again.
+const POLL: Duration = Duration::from_secs(5);
+
+/// The 1-minute load average.
+#[cfg(all(unix, not(target_os = "android")))]
+pub fn load_average() -> Result<f64> {
+    let mut load = [0.0];
+
+    match unsafe { nix::libc::getloadavg(load.as_mut_ptr(), 1) } {
+        1 => Ok(load[0]),
+        _ => bail!("Unable to read the load average"),
+    }
+}
+
+/// The 1-minute load average; Android's libc has no getloadavg.
+#[cfg(target_os = "android")]
+pub fn load_average() -> Result<f64> {
+    use anyhow::Context;
+
+    let text = std::fs::read_to_string("/proc/loadavg").context("Unable to read the load average")?;
+    text.split_whitespace().next().and_then(|load| load.parse().ok()).context("Unable to make sense of /proc/loadavg")
+}
+
+/// How many threads are waiting for a processor, the closest Windows has to
+/// a load average.
+#[cfg(windows)]
+pub fn load_average() -> Result<f64> {
+    use std::ptr;
+    use winapi::um::pdh::{PdhAddEnglishCounterW, PdhCloseQuery, PdhCollectQueryData, PdhGetFormattedCounterValue, PdhOpenQueryW, PDH_FMT_COUNTERVALUE, PDH_FMT_DOUBLE};
+
+    let path: Vec<u16> = "\\System\\Processor Queue Length".encode_utf16().chain(Some(0)).collect();
+
+    unsafe {
+        let mut query = ptr::null_mut();
+
+        if PdhOpenQueryW(ptr::null(), 0, &mut query) != 0 {
+            bail!("Unable to open a performance counter query");
+        }
+
+        let mut counter = ptr::null_mut();
+        let mut value: PDH_FMT_COUNTERVALUE = std::mem::zeroed();
+        let status = match PdhAddEnglishCounterW(query, path.as_ptr(), 0, &mut counter) {
+            0 => match PdhCollectQueryData(query) {
+                0 => PdhGetFormattedCounterValue(counter, PDH_FMT_DOUBLE, ptr::null_mut(), &mut value),
+                status => status,
+            },
+            status => status,
+        };
+
+        PdhCloseQuery(query);
+
+        match status {
+            0 => Ok(*value.u.doubleValue()),
+            status => bail!("Unable to read the processor queue length (PDH status {:#x})", status),
+        }
+    }
+}
+
+/// Waits until the load drops below `threshold`, giving up after `timeout`.
+pub fn wait_below(threshold: f64, timeout: Option<Duration>) -> Result<()> {
+    let started = Instant::now();
+    let mut load = load_average()?;
+
+    if load >= threshold {
+        verbose!("the load is {:.2}, waiting for it to drop below {}", load, threshold);
+    }
+
+    while load >= threshold {
+        let mut poll = POLL;
+
+        if let Some(timeout) = timeout {
+            match timeout.checked_sub(started.elapsed()) {
+                Some(left) if !left.is_zero() => poll = poll.min(left),
+                _ => bail!("The load stayed at or above {} for {}s, last seen at {:.2}", threshold, timeout.as_secs(), load),
+            }
+        }
+
+        thread::sleep(poll);
+        load = load_average()?;
+    }
+
+    Ok(())
+}
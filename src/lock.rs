@@ -0,0 +1,71 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// An exclusive advisory lock on a --once file, held until dropped. Locks go
+/// with the open file, so they are released even if nicer is killed.
+pub struct Lock {
+    _file: File,
+}
+
+/// Takes the lock on `path`, creating the file if need be, and writes our
+/// pid into it. Returns None if another process already holds it.
+pub fn acquire(path: &Path) -> Result<Option<Lock>> {
+    // Not truncated until we hold the lock, so as not to wipe the holder's pid.
+    let mut file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path).with_context(|| format!("Unable to open lock file {:?}", path))?;
+
+    match lock(&file) {
+        Ok(()) => {}
+        Err(error) if held(&error) => return Ok(None),
+        Err(error) => return Err(anyhow::Error::new(error).context(format!("Unable to lock {:?}", path))),
+    }
+
+    file.set_len(0).and_then(|()| write!(file, "{}", std::process::id())).with_context(|| format!("Unable to write our pid to {:?}", path))?;
+
+    Ok(Some(Lock { _file: file }))
+}
+
+/// The pid a lock file says holds it, if it says one.
+pub fn holder(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(unix)]
+fn lock(file: &File) -> io::Result<()> {
+    use nix::libc;
+    use std::os::unix::io::AsRawFd;
+
+    match unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(unix)]
+fn held(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::WouldBlock
+}
+
+/// Locks a byte well past the pid, so that others can still read it.
+#[cfg(windows)]
+fn lock(file: &File) -> io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::fileapi::LockFileEx;
+    use winapi::um::minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY, OVERLAPPED};
+
+    unsafe {
+        let mut overlapped: OVERLAPPED = std::mem::zeroed();
+        overlapped.u.s_mut().OffsetHigh = 1;
+
+        match LockFileEx(file.as_raw_handle() as _, LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY, 0, 1, 0, &mut overlapped) {
+            0 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(windows)]
+fn held(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(winapi::shared::winerror::ERROR_LOCK_VIOLATION as i32)
+}
@@ -0,0 +1,239 @@
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::sync::{Mutex, OnceLock};
+
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Where our messages go besides stderr, once --log-journal or --log-syslog
+/// picked it.
+#[cfg(unix)]
+static SINK: OnceLock<Sink> = OnceLock::new();
+
+/// The program the messages are about, once it has started.
+#[cfg(unix)]
+static SUBJECT: Mutex<Option<(String, u32)>> = Mutex::new(None);
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// When to color our own output (never the program's).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    /// Unless NO_COLOR is set or stderr isn't a terminal.
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for Color {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Color> {
+        match text {
+            "auto" => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never" => Ok(Color::Never),
+            _ => Err(anyhow::anyhow!("Unknown color choice {:?}", text)),
+        }
+    }
+}
+
+pub fn set_color(color: Color) {
+    let enabled = match color {
+        Color::Always => true,
+        Color::Never => false,
+        // https://no-color.org: any non-empty value turns color off.
+        Color::Auto => std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && std::io::stderr().is_terminal(),
+    };
+
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+/// Renders `text` in `style`, if our output is colored.
+pub fn paint<T: Display>(text: T, style: owo_colors::Style) -> String {
+    use owo_colors::OwoColorize;
+
+    match COLOR.load(Ordering::Relaxed) {
+        true => text.style(style).to_string(),
+        false => text.to_string(),
+    }
+}
+
+/// How much a message matters, as syslog and the journal number it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Error = 3,
+    Warning = 4,
+    Info = 6,
+    Debug = 7,
+}
+
+/// A system log we can copy our messages to.
+#[cfg(unix)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SystemLog {
+    /// journald, through its native protocol (Linux).
+    Journal,
+    Syslog,
+}
+
+#[cfg(unix)]
+enum Sink {
+    #[cfg(target_os = "linux")]
+    Journal(std::os::unix::net::UnixDatagram),
+    Syslog,
+}
+
+#[cfg(target_os = "linux")]
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Copies our messages to `log` from now on.
+#[cfg(unix)]
+pub fn set_system_log(log: SystemLog) -> anyhow::Result<()> {
+    let sink = match log {
+        #[cfg(target_os = "linux")]
+        SystemLog::Journal => {
+            use anyhow::Context;
+
+            let socket = std::os::unix::net::UnixDatagram::unbound().context("Unable to create a socket")?;
+            socket.connect(JOURNAL_SOCKET).with_context(|| format!("Unable to reach journald at {}", JOURNAL_SOCKET))?;
+            Sink::Journal(socket)
+        }
+        #[cfg(not(target_os = "linux"))]
+        SystemLog::Journal => anyhow::bail!("The journal is only supported on Linux"),
+        SystemLog::Syslog => {
+            unsafe {
+                use nix::libc;
+
+                // openlog keeps the pointer, so it has to be static.
+                libc::openlog(b"nicer\0".as_ptr() as *const libc::c_char, libc::LOG_PID, libc::LOG_USER);
+            }
+
+            Sink::Syslog
+        }
+    };
+
+    let _ = SINK.set(sink);
+    Ok(())
+}
+
+/// Tags what goes to the system log from now on with the program and its
+/// pid.
+#[cfg(unix)]
+pub fn set_subject(program: &str, pid: u32) {
+    *SUBJECT.lock().unwrap() = Some((program.to_string(), pid));
+}
+
+#[cfg(windows)]
+pub fn set_subject(_program: &str, _pid: u32) {}
+
+/// A journal entry in the native protocol: one `KEY=value` per line, or for
+/// values spanning lines, the key, a newline, the length as a little-endian
+/// u64 and the value.
+#[cfg(target_os = "linux")]
+fn journal_entry(level: Level, message: &str, subject: Option<&(String, u32)>) -> Vec<u8> {
+    let mut entry = Vec::new();
+    let mut field = |key: &str, value: &str| {
+        entry.extend_from_slice(key.as_bytes());
+
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    };
+
+    field("MESSAGE", message);
+    field("PRIORITY", &(level as u8).to_string());
+    field("SYSLOG_IDENTIFIER", "nicer");
+    field("SYSLOG_PID", &std::process::id().to_string());
+
+    if let Some((program, pid)) = subject {
+        field("NICER_PROGRAM", program);
+        field("NICER_PROGRAM_PID", &pid.to_string());
+    }
+
+    entry
+}
+
+/// Copies `message` to the system log, if we're logging to one.
+#[cfg(unix)]
+pub fn system(level: Level, message: &str) {
+    let sink = match SINK.get() {
+        Some(sink) => sink,
+        None => return,
+    };
+
+    let subject = SUBJECT.lock().unwrap().clone();
+
+    match sink {
+        #[cfg(target_os = "linux")]
+        Sink::Journal(socket) => {
+            let _ = socket.send(&journal_entry(level, message, subject.as_ref()));
+        }
+        Sink::Syslog => {
+            use nix::libc;
+
+            let message = match &subject {
+                Some((program, pid)) => format!("{} (pid {}): {}", program, pid, message),
+                None => message.to_string(),
+            };
+
+            if let Ok(message) = std::ffi::CString::new(message) {
+                unsafe {
+                    libc::syslog(level as libc::c_int, b"%s\0".as_ptr() as *const libc::c_char, message.as_ptr());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+pub fn system(_level: Level, _message: &str) {}
+
+/// Prints a diagnostic about what nicer is doing, when --verbose is on.
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::log::is_verbose() {
+            let message = format!($($arg)*);
+            eprintln!("{} {}", $crate::log::paint("nicer:", owo_colors::Style::new().dimmed()), message);
+            $crate::log::system($crate::log::Level::Debug, &message);
+        }
+    };
+}
+
+/// Reports something that went wrong but doesn't stop the run.
+macro_rules! warning {
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        eprintln!("{} {}", $crate::log::paint("nicer: warning:", owo_colors::Style::new().yellow()), message);
+        $crate::log::system($crate::log::Level::Warning, &message);
+    }};
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal_entries() {
+        let entry = journal_entry(Level::Warning, "two\nlines", Some(&("make".to_string(), 42)));
+        let text = String::from_utf8_lossy(&entry);
+
+        assert!(text.starts_with("MESSAGE\n\x09\0\0\0\0\0\0\0two\nlines\nPRIORITY=4\nSYSLOG_IDENTIFIER=nicer\n"));
+        assert!(text.ends_with("NICER_PROGRAM=make\nNICER_PROGRAM_PID=42\n"));
+    }
+}
@@ -1,188 +1,3161 @@
 use anyhow::{Context, Result};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
 use std::process;
-use std::process::Command;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use structopt::clap::{AppSettings, Shell};
 use structopt::StructOpt;
 
-#[cfg(all(unix, not(target_os = "macos")))]
-fn nice_process() -> Result<()>{
+#[macro_use]
+mod log;
+
+mod batch;
+#[cfg(target_os = "linux")]
+mod cgroup;
+mod check;
+mod config;
+#[cfg(windows)]
+mod console;
+#[cfg(unix)]
+mod child;
+#[cfg(unix)]
+mod control;
+mod cpus;
+mod deadline;
+mod duration;
+mod health;
+mod hooks;
+mod job;
+#[cfg(windows)]
+mod jobobject;
+mod mitigations;
+mod output;
+#[cfg(unix)]
+mod limits;
+mod load;
+mod lock;
+mod paths;
+mod priority;
+mod ramp;
+mod ready;
+#[cfg(target_os = "linux")]
+mod scope;
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod seccomp;
+#[cfg(unix)]
+mod signals;
+mod size;
+mod stats;
+mod suspend;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod tie;
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+mod tree;
+mod wakelock;
+mod watch;
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "Automagically call your tools with background priority", setting = AppSettings::SubcommandRequiredElseHelp)]
+enum Cli {
+    /// Run a program in the background (the default, so `nicer <program>` works too).
+    Run(Box<Opt>),
+
+    /// Background an already running process.
+    Renice {
+        /// Process to background.
+        pid: u32,
+
+        #[structopt(flatten)]
+        tree: TreeOpt,
+    },
+
+    /// Put an already running process back at normal priority.
+    Reset {
+        /// Process to restore.
+        pid: u32,
+
+        #[structopt(flatten)]
+        tree: TreeOpt,
+    },
+
+    /// Show the priority of a running process.
+    Query {
+        /// Process to look at.
+        pid: u32,
+    },
+
+    /// Start a program under Windows mitigation policies, for --win-mitigations.
+    #[structopt(name = "__mitigated", setting = AppSettings::Hidden)]
+    #[cfg_attr(unix, allow(dead_code))]
+    Mitigated {
+        policies: String,
+        #[structopt(long)]
+        low_integrity: bool,
+        #[structopt(required = true, allow_hyphen_values = true, parse(from_os_str))]
+        command: Vec<OsString>,
+    },
+
+    /// Keep the system awake until a process exits, for --caffeinate with --detach.
+    #[structopt(name = "__hold-wakelock", setting = AppSettings::Hidden)]
+    HoldWakeLock {
+        #[structopt(long, use_delimiter = true, require_delimiter = true, default_value = "system", possible_values = wakelock::NAMES)]
+        types: Vec<wakelock::Assertion>,
+        pid: u32,
+        program: String,
+    },
+
+    /// Kill a process once nicer exits before it, for --tie-lifetime on macOS.
+    #[structopt(name = "__tie", setting = AppSettings::Hidden)]
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    Tie { parent: u32, pid: u32 },
+
+    /// Print a completion script for your shell.
+    Completions {
+        #[structopt(possible_values = &Shell::variants())]
+        shell: Shell,
+    },
+}
+
+/// How `renice` and `reset` walk the processes they adjust.
+#[derive(StructOpt, Debug)]
+struct TreeOpt {
+    /// Adjust every descendant of the process too. Linux only.
+    #[structopt(short, long)]
+    recursive: bool,
+
+    /// Print how many processes were adjusted and how many were skipped.
+    #[structopt(long)]
+    count: bool,
+
+    /// Print that summary as JSON, along with how each adjustment of each
+    /// process went (`applied`, `skipped` or `failed`, with the OS error
+    /// code, and on Unix what the niceness and I/O priority read back as
+    /// afterwards). The `version` field changes only when the format does.
+    #[structopt(long)]
+    json: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(setting = AppSettings::TrailingVarArg)]
+struct Opt {
+    /// Keep the system awake (supported on Windows and macOS).
+    #[structopt(short, long)]
+    caffeinate: bool,
+
+    /// Like --caffeinate, but abort the run if the system can't be kept awake.
+    #[structopt(long)]
+    require_caffeinate: bool,
+
+    /// Like --caffeinate, but only keep the system awake while the program
+    /// keeps a CPU at least this busy, in percent (e.g. 5), letting it sleep
+    /// once the program has sat idle for ten seconds, e.g. waiting on a slow
+    /// network. Windows and macOS only.
+    #[structopt(long, conflicts_with = "require-caffeinate", parse(try_from_str = parse_percent))]
+    caffeinate_when_busy: Option<u32>,
+
+    /// Comma-separated list of what --caffeinate keeps awake: system (the
+    /// default), display, full (both) or disk (macOS only).
+    #[structopt(long, use_delimiter = true, require_delimiter = true, default_value = "system", possible_values = wakelock::NAMES)]
+    caffeinate_type: Vec<wakelock::Assertion>,
+
+    /// Comma-separated list of signals to relay to the program; by default
+    /// INT, TERM, HUP, QUIT, USR1, USR2 and WINCH, so that nicer can stand in
+    /// for it under service managers, tmux and terminal resizes. Those nicer
+    /// answers itself (SIGUSR1 with --restart-on-failure, SIGINT and SIGTERM
+    /// with --watch, --resume-on-signal) aren't relayed.
+    #[cfg(unix)]
+    #[structopt(long, use_delimiter = true, require_delimiter = true, parse(try_from_str = signals::parse_signal))]
+    forward_signals: Option<Vec<nix::sys::signal::Signal>>,
+
+    /// Explain what is being done to which process.
+    #[structopt(short, long)]
+    verbose: bool,
+
+    /// Color nicer's own messages: auto (unless NO_COLOR is set or stderr
+    /// isn't a terminal), always or never.
+    #[structopt(long, default_value = "auto", possible_values = &["auto", "always", "never"])]
+    color: log::Color,
+
+    /// Copy nicer's own messages, and a record of the program starting and
+    /// stopping, to the systemd journal, tagged with the program and its
+    /// pid. The program's output is left alone. Linux only.
+    #[structopt(long, conflicts_with = "log-syslog")]
+    log_journal: bool,
+
+    /// Like --log-journal, but to syslog. Unix only.
+    #[structopt(long)]
+    log_syslog: bool,
+
+    /// Background only nicer itself, leaving the program at normal priority.
+    /// Useful when nicer does the heavy lifting, e.g. copying data through a
+    /// pipe on the program's behalf.
+    #[structopt(long)]
+    self_only: bool,
+
+    /// Keep processes the program leaves behind (e.g. daemons that double
+    /// fork) under nicer instead of init, and background any new descendant
+    /// within a second of it showing up, even if it reset its own priority.
+    /// Orphans become our children, so nicer takes over reaping them once
+    /// they exit; it still exits as soon as the program itself does. Linux
+    /// only.
+    #[structopt(long, conflicts_with = "self-only")]
+    inherit_priority_to_descendants: bool,
+
+    /// Every second, look through everything the program started (and the
+    /// program itself) for processes that raised their own priority again,
+    /// as some build tools' workers do, and lower it back. Linux, macOS and
+    /// Windows only.
+    #[structopt(long, conflicts_with_all = &["self-only", "priority-from-pid"])]
+    renice_descendants: bool,
+
+    /// Every second, set each thread of the program (and with
+    /// --inherit-priority-to-descendants, of its descendants) back to the
+    /// niceness it should have, since Linux keeps niceness per thread and a
+    /// thread may raise its own. Threads start at their creator's niceness,
+    /// so one created in between runs at that until the next sweep. Linux
+    /// only.
+    #[structopt(long, conflicts_with = "self-only")]
+    thread_nice: bool,
+
+    /// Scheduling policy for the program: idle to only run when nothing else
+    /// wants the CPU, which even nice 19 doesn't manage under load, batch to
+    /// keep its niceness but never preempt others on waking up, or other to
+    /// leave the default one. --lowest means idle. Linux only.
+    #[structopt(long, possible_values = priority::SCHED_NAMES, conflicts_with_all = &["no-nice", "only-io", "priority-from-pid"])]
+    sched: Option<priority::Sched>,
+
+    /// Make the program a likelier (up to 1000) or unlikelier (down to -1000,
+    /// which needs privileges) pick for the OOM killer; --lowest means 1000.
+    /// Linux only.
+    #[structopt(long, allow_hyphen_values = true, parse(try_from_str = parse_oom_score_adj), conflicts_with = "priority-from-pid")]
+    oom_score_adj: Option<i32>,
+
+    /// How far down macOS's tiers to put the program: background for the
+    /// Darwin background band, which throttles CPU, disk and network alike;
+    /// utility to only mark it as not driving any UI, with niceness and the
+    /// utility I/O policy on top; default for niceness and the I/O policy
+    /// alone. macOS only.
+    #[structopt(long, default_value = "background", possible_values = priority::TIER_NAMES)]
+    tier: priority::Tier,
+
+    /// Only throttle disk I/O (through setiopolicy_np), instead of the full
+    /// Darwin background band that also lowers CPU priority and coalesces
+    /// timers. macOS only.
+    #[structopt(long)]
+    macos_io_throttle: bool,
+
+    /// Background CPU and disk I/O without the timer coalescing that comes
+    /// with the Darwin background band, for latency sensitive work such as
+    /// audio. macOS only.
+    #[structopt(long, conflicts_with = "macos-io-throttle")]
+    no_timer_throttle: bool,
+
+    /// Background as hard as possible on every axis: nice 19, the idle I/O
+    /// class, SCHED_IDLE and first in line for the OOM killer on Linux,
+    /// EcoQoS on Windows, the background band plus an I/O throttle on macOS.
+    /// --no-nice, --no-ioprio, --only-io and --only-cpu still take axes out.
+    #[structopt(long)]
+    lowest: bool,
+
+    /// Leave CPU priority alone.
+    #[structopt(long)]
+    no_nice: bool,
+
+    /// Leave I/O priority alone.
+    #[structopt(long)]
+    no_ioprio: bool,
+
+    /// Leave the I/O priority of nicer's own threads that copy the program's
+    /// output (for --prefix, --timestamps and --output-log) alone. They
+    /// otherwise drop to idle I/O like the program, even where nicer keeps
+    /// its own priority.
+    #[structopt(long)]
+    no_relay_ioprio: bool,
+
+    /// Only lower I/O priority, for jobs that are heavy on the disk but
+    /// light on the CPU. Same as --no-nice.
+    #[structopt(long, conflicts_with = "only-cpu")]
+    only_io: bool,
+
+    /// Only lower CPU priority, leaving I/O alone. Same as --no-ioprio.
+    #[structopt(long)]
+    only_cpu: bool,
+
+    /// Lower I/O priority along with niceness instead of to the idle class,
+    /// so the program still gets a fair, if smaller, share of a busy disk.
+    /// This puts it in the `none` class, which the kernel treats as
+    /// best-effort at level (nice + 20) / 5, 7 for nice 19 (idle under
+    /// SCHED_IDLE with --lowest). Linux only.
+    #[structopt(long, conflicts_with_all = &["no-ioprio", "only-cpu", "priority-from-pid"])]
+    ioprio_from_nice: bool,
+
+    /// Put the program in what Windows calls background mode: very low
+    /// memory priority on top of very low I/O priority, so that its pages,
+    /// page cache included, are the first to go instead of the foreground
+    /// apps'. Windows only.
+    #[structopt(long, conflicts_with_all = &["no-ioprio", "only-cpu", "priority-from-pid"])]
+    background_mode: bool,
+
+    /// Opt the program into EcoQoS, as --lowest does: Windows 11 then prefers
+    /// efficiency cores and lower clock speeds for it, which saves power
+    /// without costing the foreground anything. Windows only.
+    #[structopt(long, conflicts_with = "priority-from-pid")]
+    eco: bool,
+
+    /// I/O class to lower I/O priority to instead of idle: best-effort to
+    /// still get a share of a busy disk by --io-level, or none to go by
+    /// niceness as --ioprio-from-nice does. On macOS idle throttles disk I/O,
+    /// best-effort is the utility policy and none the default one, and either
+    /// goes without the background band. Linux and macOS only.
+    #[structopt(long, possible_values = priority::IO_CLASS_NAMES, conflicts_with_all = &["no-ioprio", "only-cpu", "priority-from-pid", "ioprio-from-nice"])]
+    io_class: Option<priority::IoClass>,
+
+    /// Level within the best-effort class, from 0 (the most of the disk) to
+    /// 7 (the least, and the default). Linux only.
+    #[structopt(long, requires = "io-class", parse(try_from_str = parse_io_level))]
+    io_level: Option<i32>,
+
+    /// Niceness for nicer itself (-20 to 19, lower needs privileges), e.g. 0
+    /// to keep it responsive while the program runs in the background. The
+    /// program then lowers its own priority as it starts instead of
+    /// inheriting ours. By default nicer runs at the program's priority.
+    #[structopt(long, conflicts_with = "self-only", allow_hyphen_values = true, parse(try_from_str = parse_nice))]
+    wrapper_nice: Option<i32>,
+
+    /// Lower the program's priority only, leaving nicer at its own so that
+    /// relaying output and signals stays snappy under load. Like
+    /// --wrapper-nice, the program lowers its own priority as it starts (on
+    /// Windows, while it is held suspended), so it never runs at ours.
+    #[structopt(long, conflicts_with_all = &["self-only", "wrapper-nice"])]
+    child_only: bool,
+
+    /// Lower CPU priority to this niceness instead of all the way to 19, e.g.
+    /// 10 for a milder nudge; from -20 (highest priority) to 19, and below
+    /// nicer's own niceness only with root or CAP_SYS_NICE. On Windows it
+    /// picks the closest priority class: idle from 15 up, below normal from
+    /// 5, normal from -4, above normal from -14 and high below that. On
+    /// macOS anything but 19 goes without the background band.
+    #[structopt(long, allow_hyphen_values = true, parse(try_from_str = parse_nice), conflicts_with_all = &["renice-by", "ramp", "priority-from-pid", "no-nice", "only-io"])]
+    nice: Option<i32>,
+
+    /// Windows priority class to lower CPU priority to instead of idle, e.g.
+    /// below-normal for long encodes, which foreground apps put up with much
+    /// better. Elsewhere it stands for nice 19, 10 and 0 respectively.
+    #[structopt(long = "priority", possible_values = priority::PRIORITY_CLASS_NAMES, conflicts_with_all = &["nice", "renice-by", "ramp", "priority-from-pid", "no-nice", "only-io"])]
+    priority_class: Option<priority::PriorityClass>,
+
+    /// Lower CPU priority relative to the niceness nicer inherited instead
+    /// of all the way to 19, e.g. 5 for five steps lower (clamped to -20 to
+    /// 19). For nesting nicer in an already niced job.
+    #[structopt(long, allow_hyphen_values = true)]
+    renice_by: Option<i32>,
+
+    /// Only change CPU priority if that lowers it: when nicer already runs
+    /// at or past the niceness it would set (say, under another nicer), it
+    /// is left as it is rather than raised back up.
+    #[structopt(long, conflicts_with_all = &["ramp", "priority-from-pid", "no-nice", "only-io"])]
+    preserve_nice: bool,
+
+    /// Give the program the same priority as this running process instead
+    /// of lowering it: its niceness, plus its I/O class and scheduling
+    /// policy on Linux, background band on macOS, or priority class on
+    /// Windows. For jobs that should match, say, a build already running.
+    #[structopt(long, conflicts_with_all = &["renice-by", "self-only", "no-nice", "no-ioprio", "only-io", "only-cpu", "lowest"])]
+    priority_from_pid: Option<u32>,
+
+    /// Start the program at one niceness and lower its priority step by
+    /// step to another, e.g. `from=0,to=19,over=5m` for a job that should
+    /// make quick progress before making way for interactive work (`from`
+    /// defaults to 0 and `to` to 19). nicer keeps normal priority to renice
+    /// it; this is best-effort, and stops once the target is reached.
+    #[structopt(long, conflicts_with_all = &["renice-by", "priority-from-pid", "self-only", "no-nice", "only-io", "detach"])]
+    ramp: Option<ramp::Ramp>,
+
+    /// Comma-separated process mitigation policies to start the program
+    /// under: dep, aslr (rebase every image), no-dynamic-code and
+    /// no-child-processes. Policies this Windows is too old for are skipped
+    /// with a warning. A hidden nicer process sits between us and the
+    /// program to set them up, so --print-pid reports that one. Windows only.
+    #[structopt(long, use_delimiter = true, require_delimiter = true, possible_values = mitigations::NAMES)]
+    win_mitigations: Vec<mitigations::Mitigation>,
+
+    /// Start the program at low integrity, like a browser's sandboxed tabs,
+    /// so it can't write anywhere a normal process owns: most of your
+    /// profile and the registry are off limits, and only folders such as
+    /// %LOCALAPPDATA%Low stay writable. Plenty of tools don't expect that and
+    /// fail, so this is for untrusted ones that only read, or write where
+    /// they're told to. Goes through the same hidden nicer process as
+    /// --win-mitigations. Windows only.
+    #[structopt(long)]
+    win_low_integrity: bool,
+
+    /// Print the nicer command line that spells out the priority settings in
+    /// effect (after --job-file and the presets), then exit without running
+    /// anything.
+    #[structopt(long)]
+    priority_preview: bool,
+
+    /// Work out whether the program could be started as configured, without
+    /// starting it: whether it can be found, and whether the priority,
+    /// resource limits, cgroup and the rest can actually be applied, which
+    /// is probed in a throwaway process where that can't be undone. Prints
+    /// what was checked and exits with 1 if anything would stop the run,
+    /// e.g. to vet a cron entry.
+    #[structopt(long, conflicts_with = "priority-preview")]
+    check: bool,
+
+    /// Print the settings the run would use, each with where it came from
+    /// (the built-in default, a flag or --job-file, which wins over flags),
+    /// then exit without running anything.
+    #[structopt(long, conflicts_with_all = &["priority-preview", "check"])]
+    print_config: bool,
+
+    /// Print the --print-config settings, or the --stats summary, as JSON.
+    #[structopt(long)]
+    json: bool,
+
+    /// Abort if any priority adjustment fails, rather than only when all of
+    /// them do.
+    #[structopt(long)]
+    strict: bool,
+
+    /// Never look the program up in PATH; it must be an absolute path or contain a separator.
+    #[structopt(long)]
+    no_path: bool,
+
+    /// Start programs the system can't execute (scripts without a #! line)
+    /// with /bin/sh instead, the way a shell does. The program then goes
+    /// through `sh -c 'exec "$0" "$@"'`, which replaces itself with the
+    /// program whenever it can be executed directly (Unix).
+    #[structopt(long)]
+    exec_fallback: bool,
+
+    /// Start the program under this name (its argv[0]) instead of its path,
+    /// for multi-call binaries like busybox, or `-bash` for a login shell.
+    /// Unix only; Windows has no argv[0] of its own.
+    #[structopt(long, parse(from_os_str), allow_hyphen_values = true, conflicts_with = "exec-fallback")]
+    argv0: Option<OsString>,
+
+    /// Wait this long before starting the program, e.g. 90, 30s, 5m or 1h30m.
+    #[structopt(long, parse(try_from_str = duration::parse_duration))]
+    delay: Option<Duration>,
+
+    /// Before starting the program (and after --delay), wait for the
+    /// 1-minute load average to drop below this, e.g. 2.5, so maintenance
+    /// jobs hold off until the machine is quiet. On Windows the processor
+    /// queue length stands in for the load average.
+    #[structopt(long)]
+    wait_for_load: Option<f64>,
+
+    /// Give up on --wait-for-load after this long, e.g. 30m, and exit
+    /// without starting the program.
+    #[structopt(long, requires = "wait-for-load", parse(try_from_str = duration::parse_positive_duration))]
+    load_wait_timeout: Option<Duration>,
+
+    /// Cap the memory the program may use, e.g. 512M or 4G. On Linux it is
+    /// throttled as it approaches the limit (through a cgroup's memory.high)
+    /// rather than killed; where cgroup memory control isn't delegated to us,
+    /// and on other Unixes, this falls back to an address space limit
+    /// (RLIMIT_AS), under which allocations past the limit simply fail.
+    #[structopt(long, parse(try_from_str = size::parse_size))]
+    max_memory: Option<u64>,
+
+    /// Hard cap the memory the program may use, e.g. 4G: unlike --max-memory
+    /// it's never let past the limit, and is killed by the OOM killer if it
+    /// can't make do (through a cgroup's memory.max on Linux), or has its
+    /// allocations fail (through a job object on Windows, or RLIMIT_AS on
+    /// other Unixes and where cgroups aren't delegated to us).
+    #[structopt(long, parse(try_from_str = size::parse_size))]
+    memory_limit: Option<u64>,
+
+    /// Hard cap the CPU time the program and all its children may use, as a
+    /// percentage of one CPU (e.g. 20, or 250 for two and a half CPUs). Unlike
+    /// niceness this holds even when the machine is otherwise idle. Through
+    /// a cgroup's cpu.max on Linux, and a job object's CPU rate control on
+    /// Windows (see --job-object).
+    #[structopt(long, parse(try_from_str = parse_percent))]
+    cpu_quota: Option<u32>,
+
+    /// Run the program in a job object, which it and everything it starts
+    /// can't leave: they are all kept at its priority class, even if they
+    /// ask for another, and whatever is still running is killed when the
+    /// program exits or nicer goes away. Windows only.
+    #[structopt(long, conflicts_with_all = &["detach", "ramp", "priority-from-pid"])]
+    job_object: bool,
+
+    /// Kill the program should nicer die before it, even of SIGKILL or a
+    /// crash, rather than leave it running on its own. Through
+    /// PR_SET_PDEATHSIG on Linux, and a watcher process (a second, hidden
+    /// nicer) on macOS, which take the program alone; on Windows through a
+    /// job object (see --job-object), which takes everything it started too.
+    #[structopt(long, conflicts_with = "detach")]
+    tie_lifetime: bool,
+
+    /// Run the program in a cgroup of its own weighed down like its priority:
+    /// a cpu.weight to match its niceness (and cpu.idle with --lowest), and
+    /// the lowest io.weight with the idle I/O class. Unlike niceness and I/O
+    /// priority, which each process can get out of, this covers everything
+    /// the program starts, and I/O priority is all but ignored by the
+    /// schedulers modern kernels default to. The cgroup goes away when the
+    /// program exits. Linux only (cgroup v2).
+    #[structopt(long, conflicts_with_all = &["systemd-scope", "self-only", "priority-from-pid"])]
+    cgroup_weights: bool,
+
+    /// Run the program in a cgroup of its own, so that `freeze` and `thaw`
+    /// on the --control-socket pause and resume it, along with everything it
+    /// started, all at once. Unlike SIGSTOP nothing in the tree can notice or
+    /// get in the way; signals it handles wait until it's thawed. Linux only
+    /// (cgroup v2).
+    #[structopt(long, requires = "control-socket", conflicts_with_all = &["systemd-scope", "detach"])]
+    cgroup_freeze: bool,
+
+    /// Freeze the program, as --cgroup-freeze does, while the 1-minute load
+    /// average is above this, e.g. 4, and thaw it once the load drops back
+    /// below.
+    #[structopt(long, conflicts_with_all = &["systemd-scope", "detach"])]
+    freeze_above_load: Option<f64>,
+
+    /// Run the program in a transient systemd scope, like `systemd-run
+    /// --scope`, so systemd does the resource control and the program shows
+    /// up in `systemctl status`. The scope's CPU and I/O weights follow the
+    /// priority settings, and it takes over --max-memory and --cpu-quota.
+    /// nicer does this anyway wherever systemd is running, unless another
+    /// cgroup option, --self-only or --priority-from-pid is given; this
+    /// insists, and warns when it can't. Without systemd the program is
+    /// started as usual (Linux only).
+    #[structopt(long)]
+    systemd_scope: bool,
+
+    /// Start the program without a systemd scope, even where systemd is
+    /// running.
+    #[structopt(long, conflicts_with = "systemd-scope")]
+    no_systemd_scope: bool,
+
+    /// Kill the program once it has used this much CPU time, e.g. 10m,
+    /// however long it has been running, to catch runaway loops in jobs that
+    /// otherwise sit waiting on I/O. nicer then exits with 152. Through
+    /// RLIMIT_CPU on Unix (SIGXCPU, then SIGKILL five seconds of CPU time
+    /// later), and through a job object's per-process user time limit on
+    /// Windows, where the program's user and kernel time together are also
+    /// checked every second. Either way it applies to each process on its
+    /// own. Also --cpu-time-limit.
+    #[structopt(long, alias = "cpu-time-limit", parse(try_from_str = duration::parse_positive_duration))]
+    max_cpu_time: Option<Duration>,
+
+    /// Keep the program and its descendants from starting more than this
+    /// many processes (threads included), so a runaway script can't
+    /// fork-bomb the machine. Through RLIMIT_NPROC, which counts every
+    /// process of the same user: give it enough headroom for your other
+    /// sessions, as a limit they have already passed keeps the program from
+    /// starting anything at all. It doesn't apply to root. Unix only.
+    #[structopt(long)]
+    max_procs: Option<u64>,
+
+    /// Set any resource limit on the program, as RESOURCE=SOFT[:HARD], e.g.
+    /// nofile=1024 or core=0:unlimited; repeat it for several. Values are
+    /// counts, sizes such as 64M, or unlimited; without a hard limit the
+    /// soft one is fixed. Resources go by their ulimit names: as, core, cpu,
+    /// data, fsize, memlock, nofile, nproc, rss and stack, plus msgqueue,
+    /// nice, rtprio, rttime and sigpending on Linux. These apply after
+    /// --max-memory and --max-cpu-time, so they win over them (Unix).
+    #[cfg(unix)]
+    #[structopt(long, number_of_values = 1, parse(try_from_str = limits::parse_rlimit))]
+    rlimit: Vec<limits::Limit>,
+
+    /// Keep the program on the efficiency cores of a hybrid CPU (Intel P/E
+    /// cores, ARM big.LITTLE). Where those can't be told apart this falls
+    /// back to the OS's own hinting: EcoQoS on Windows, background priority
+    /// elsewhere. On macOS background priority already confines the program
+    /// to the efficiency cores of Apple Silicon.
+    #[structopt(long)]
+    efficiency_cores: bool,
+
+    /// Pin the program to these CPUs, as a list such as `0-3,8`, e.g. to keep
+    /// half the cores free for interactive work while a build runs. On
+    /// Windows only the first 64, in processor group 0, can be picked. Linux
+    /// and Windows only; macOS has no CPU affinity to speak of.
+    #[structopt(long, parse(try_from_str = cpus::parse_cpu_list), conflicts_with = "efficiency-cores")]
+    cpus: Option<cpus::CpuList>,
+
+    /// Print the program's pid as soon as it starts, to stderr or, with
+    /// --print-pid=stdout, to stdout (where it mixes with the program's own
+    /// output).
+    #[structopt(long, require_equals = true, possible_values = &["stdout", "stderr"])]
+    print_pid: Option<Option<String>>,
+
+    /// Exit as soon as the program has started, leaving it running on its
+    /// own; with --print-pid this is how to find it again. With --caffeinate
+    /// a small holder process (a second, hidden nicer) keeps the system awake
+    /// until the program exits, and shows up next to it in the process list.
+    #[structopt(long, conflicts_with_all = &[
+        "restart-on-failure", "health-check", "deadline", "timeout", "kill-after", "wait-for-port", "control-socket", "stats", "prefix", "timestamps", "output-log", "tee", "tee-stderr",
+        "caffeinate-when-busy", "exit-zero", "map-exit", "max-cpu-time", "thread-nice", "inherit-priority-to-descendants", "renice-descendants",
+    ])]
+    detach: bool,
+
+    /// Wait for the program to accept TCP connections on this address (e.g.
+    /// localhost:8080) and announce it, or run --on-ready. If it doesn't
+    /// within --ready-timeout, the program is killed.
+    #[structopt(long)]
+    wait_for_port: Option<String>,
+
+    /// Start the program stopped, with its priority, limits and cgroup in
+    /// place before it runs a single instruction, and only let it go on
+    /// --resume-on-port, --resume-on-signal or `resume` on the
+    /// --control-socket. A signal relayed to the program resumes it too, so
+    /// it can act on it. On Unix the program is started through /bin/sh, as
+    /// with --exec-fallback.
+    #[structopt(long, conflicts_with_all = &["detach", "argv0"])]
+    start_suspended: bool,
+
+    /// Resume the --start-suspended program once something accepts TCP
+    /// connections on this address, e.g. localhost:5432 for the database it
+    /// needs.
+    #[structopt(long, requires = "start-suspended")]
+    resume_on_port: Option<String>,
+
+    /// Resume the --start-suspended program when nicer receives this signal,
+    /// e.g. USR2. Unix only.
+    #[cfg(unix)]
+    #[structopt(long, requires = "start-suspended", parse(try_from_str = signals::parse_signal))]
+    resume_on_signal: Option<nix::sys::signal::Signal>,
+
+    /// How long --wait-for-port waits, e.g. 90s or 2m; 30 seconds by default.
+    #[structopt(long, requires = "wait-for-port", parse(try_from_str = duration::parse_positive_duration))]
+    ready_timeout: Option<Duration>,
+
+    /// Shell command to run once the program is ready, instead of announcing
+    /// it. The program's pid is in NICER_PID.
+    #[structopt(long, requires = "wait-for-port")]
+    on_ready: Option<String>,
+
+    /// Always exit with 0, whatever became of the program. This masks its
+    /// failures, so only use it for best-effort jobs; --verbose and --stats
+    /// still show how it really ended.
+    #[structopt(long)]
+    exit_zero: bool,
+
+    /// Comma-separated exit codes to rewrite, e.g. 4=0,5=0 to treat codes 4
+    /// and 5 as success. Unmapped codes pass through unchanged.
+    #[structopt(long, use_delimiter = true, require_delimiter = true, parse(try_from_str = parse_exit_mapping))]
+    map_exit: Vec<(i32, i32)>,
+
+    /// Stop the program at this time: HH:MM[:SS] for the next time the
+    /// clock reads it, or YYYY-MM-DD HH:MM[:SS]; local time, or UTC with a
+    /// trailing Z. The program gets SIGTERM (it is killed on Windows), then
+    /// SIGKILL if it's still running after --kill-after, and nicer exits
+    /// with 124 as timeout(1) does. A deadline that has passed stops nicer
+    /// from starting the program at all.
+    #[structopt(long)]
+    deadline: Option<deadline::Deadline>,
+
+    /// Start the program even though --deadline has passed, and stop it
+    /// straight away.
+    #[structopt(long, requires = "deadline")]
+    allow_past_deadline: bool,
+
+    /// Stop the program once it has been running this long, e.g. 90s or 2h,
+    /// as timeout(1) does: it gets SIGTERM, then SIGKILL if it's still
+    /// running after --kill-after, and nicer exits with 124. On Windows it
+    /// gets Ctrl-Break and then is killed; it runs in a console process
+    /// group of its own for that, so Ctrl-C no longer reaches it. With
+    /// --restart-on-failure each run gets the whole time again.
+    #[structopt(long, parse(try_from_str = duration::parse_positive_duration))]
+    timeout: Option<Duration>,
+
+    /// How long the program gets to exit once --timeout or --deadline has
+    /// asked it to, before it's killed (10s by default).
+    #[structopt(long, parse(try_from_str = duration::parse_duration))]
+    kill_after: Option<Duration>,
+
+    /// Start the program again whenever it fails (exits non-zero or is
+    /// killed), a second after it does. On Unix, sending nicer SIGUSR1 lets
+    /// the current run finish and then stops restarting it, for rolling
+    /// restarts that don't interrupt a job in progress.
+    #[structopt(long, conflicts_with = "self-only")]
+    restart_on_failure: bool,
+
+    /// Start the program again whenever a file under this path changes
+    /// (repeatable), stopping it first if it's still running, e.g.
+    /// `nicer --watch src/ -- cargo build`. Changes are polled for once a
+    /// second; files and directories starting with a dot are ignored. On
+    /// Unix, Ctrl-C or SIGTERM stops the program and the watching.
+    #[structopt(long, number_of_values = 1, parse(from_os_str = paths::expand_tilde), conflicts_with_all = &["restart-on-failure", "self-only", "detach"])]
+    watch: Vec<PathBuf>,
+
+    /// Take an exclusive lock on this file before starting, and don't start
+    /// at all (exiting with 64) if another run holds it, so that a cron job
+    /// that overruns doesn't get a second copy stacked on top. The lock is
+    /// let go once the program exits; the file stays, with the pid of the
+    /// last run that held it.
+    #[structopt(long, parse(from_os_str = paths::expand_tilde), conflicts_with = "detach")]
+    once: Option<PathBuf>,
+
+    /// Give up after restarting the program this many times, with
+    /// --restart-on-failure or --health-check.
+    #[structopt(long)]
+    max_restarts: Option<u32>,
+
+    /// Forget earlier restarts once the program has stayed up this long,
+    /// e.g. 1h, so a service that crashes once in a while never runs out of
+    /// --max-restarts.
+    #[structopt(long, requires = "max-restarts", parse(try_from_str = duration::parse_positive_duration))]
+    healthy_after: Option<Duration>,
+
+    /// Shell command checking on the program every --health-interval while
+    /// it runs, with its pid in NICER_PID. After --unhealthy-threshold
+    /// failures in a row the program is killed and restarted; nicer exits
+    /// with 125 once --max-restarts runs out. The check runs at nicer's
+    /// normal priority, so the program lowers its own as it starts.
+    #[structopt(long, conflicts_with = "self-only")]
+    health_check: Option<String>,
+
+    /// How often --health-check runs, e.g. 30s or 1m; 10 seconds by default.
+    /// A check still running when the next is due counts as failed.
+    #[structopt(long, requires = "health-check", parse(try_from_str = duration::parse_positive_duration))]
+    health_interval: Option<Duration>,
+
+    /// How many --health-check failures in a row mean the program is
+    /// wedged; 3 by default.
+    #[structopt(long, requires = "health-check")]
+    unhealthy_threshold: Option<u32>,
+
+    /// Take commands while the program runs on a Unix socket at this path,
+    /// one per line: `signal NAME` sends the program a signal, `reload` sends
+    /// it SIGHUP and `status` tells how it is doing; with --cgroup-freeze,
+    /// `freeze` and `thaw` pause and resume it, and with --start-suspended
+    /// `resume` lets it start. Try `socat -
+    /// UNIX-CONNECT:<path>`. Unix only.
+    #[structopt(long)]
+    control_socket: Option<PathBuf>,
+
+    /// Print a summary of the run (time, CPU, memory) when the program exits,
+    /// and how well it kept to its lower priority: how many CPUs it kept
+    /// busy on average, how often it was found in the idle I/O class
+    /// (Linux), and how often it raised its own priority back up and nicer
+    /// lowered it again.
+    #[structopt(long)]
+    stats: bool,
+
+    /// File creation mask for the program, in octal (e.g. 022 or 077). Unix
+    /// only.
+    #[structopt(long, parse(try_from_str = parse_umask))]
+    umask: Option<u32>,
+
+    /// Confine the program to this directory tree. The program and --cwd are
+    /// then looked up inside it. Needs root; Unix only.
+    #[structopt(long, parse(from_os_str = paths::expand_tilde))]
+    chroot: Option<PathBuf>,
+
+    /// Start the program under a seccomp filter: no-network (only local
+    /// sockets), no-ptrace, or a TOML or JSON filter spec file listing the
+    /// syscalls to `allow` (everything else is denied) or to `deny`. Denied
+    /// syscalls fail with EPERM. Setuid programs it runs gain no privileges.
+    /// Linux (x86_64 and aarch64) only.
+    #[structopt(long)]
+    seccomp: Option<String>,
+
+    /// Start the program in a network namespace of its own, with nothing but
+    /// a loopback interface, for jobs that have no business on the network.
+    /// Needs root, or unprivileged user namespaces for one around it. Linux
+    /// only.
+    #[structopt(long)]
+    isolate_network: bool,
+
+    /// Give the program an empty stdin, so tools that would wait for input
+    /// see end of file straight away.
+    #[structopt(long, conflicts_with = "stdin")]
+    close_stdin: bool,
+
+    /// Feed the program this file as its stdin.
+    #[structopt(long, parse(from_os_str = paths::expand_tilde))]
+    stdin: Option<PathBuf>,
+
+    /// Throw away everything the program prints, on stdout and stderr, e.g.
+    /// so cron doesn't mail it. nicer's own messages and --stats still get
+    /// through.
+    #[structopt(long, conflicts_with_all = &["prefix", "timestamps", "output-log", "tee", "tee-stderr"])]
+    quiet_child: bool,
+
+    /// Put this label in front of every line the program writes to stdout
+    /// and stderr, to tell jobs sharing a terminal apart. Output that isn't
+    /// text is passed on untouched.
+    #[structopt(long)]
+    prefix: Option<String>,
+
+    /// Put the time of day in front of every line the program writes.
+    #[structopt(long)]
+    timestamps: bool,
+
+    /// Also write everything the program prints, as it printed it, to this
+    /// file (appending to it).
+    #[structopt(long, parse(from_os_str = paths::expand_tilde))]
+    output_log: Option<PathBuf>,
+
+    /// Also write the program's stdout to this file (appending to it), byte
+    /// for byte, while it still shows up on ours.
+    #[structopt(long, parse(from_os_str = paths::expand_tilde))]
+    tee: Option<PathBuf>,
+
+    /// Like --tee, for the program's stderr.
+    #[structopt(long, parse(from_os_str = paths::expand_tilde))]
+    tee_stderr: Option<PathBuf>,
+
+    /// Directory to run the program in.
+    #[structopt(long, parse(from_os_str = paths::expand_tilde))]
+    cwd: Option<PathBuf>,
+
+    /// Name or path to the program I'll background to, followed by its
+    /// arguments. Bare names (without a path separator) are looked up in
+    /// PATH, like a shell would; relative paths are resolved against --cwd
+    /// when it is given. A leading ~ in the program is expanded to your home
+    /// directory. Everything after the program is passed to it untouched,
+    /// even if it looks like one of our own flags.
+    #[structopt(name = "program", required_unless_one = &["job-file", "batch", "print-config"], parse(from_os_str))]
+    command: Vec<OsString>,
+
+    /// Run the job described in this TOML (or, for .json files, JSON) file:
+    /// its program, args, env, cwd, caffeinate and priority settings take
+    /// precedence over the command line.
+    #[structopt(long, parse(from_os_str = paths::expand_tilde))]
+    job_file: Option<PathBuf>,
+
+    /// Run the commands listed in this file, one per line (through the
+    /// shell; blank lines and lines starting with # are skipped), all in the
+    /// background and under one --caffeinate wakelock, then sum up how they
+    /// exited. nicer exits with 1 if any of them failed. Their stdin is
+    /// empty. On Unix, Ctrl-C or SIGTERM stops the ones still running and
+    /// skips the rest.
+    #[structopt(long, parse(from_os_str = paths::expand_tilde), conflicts_with_all = &[
+        "program", "job-file", "check", "detach", "self-only", "wrapper-nice", "priority-from-pid", "ramp", "health-check", "restart-on-failure",
+        "wait-for-port", "control-socket", "deadline", "timeout", "kill-after", "print-pid", "prefix", "timestamps", "output-log", "tee", "tee-stderr", "stats", "exit-zero",
+        "map-exit", "caffeinate-when-busy", "max-cpu-time", "max-procs", "max-memory", "memory-limit", "cpu-quota", "job-object", "tie-lifetime", "cgroup-weights", "systemd-scope", "no-systemd-scope", "chroot", "seccomp",
+        "isolate-network", "umask", "stdin", "exec-fallback", "argv0", "start-suspended", "watch", "cgroup-freeze", "freeze-above-load", "efficiency-cores", "cpus", "thread-nice", "inherit-priority-to-descendants", "renice-descendants",
+        "win-mitigations", "win-low-integrity",
+    ])]
+    batch: Option<PathBuf>,
+
+    /// How many --batch commands to run at once (1 by default).
+    #[structopt(long, requires = "batch")]
+    concurrency: Option<usize>,
+
+    /// Extra environment for the program; only job files set it.
+    #[structopt(skip)]
+    env: BTreeMap<String, String>,
+
+    /// When to stop the program, once --deadline is worked out.
+    #[structopt(skip)]
+    watchdog: Option<Instant>,
+
+    /// Whether the program runs in a process group of its own, which
+    /// relayed signals go to; on Windows, a console process group, which
+    /// only Ctrl-Break reaches.
+    #[structopt(skip)]
+    own_group: bool,
+
+    /// The priority read for --priority-from-pid.
+    #[structopt(skip)]
+    copied_priority: Option<priority::Priority>,
+
+    /// What the program does to its priority on its way in, for explaining
+    /// why it couldn't be started.
+    #[cfg(unix)]
+    #[structopt(skip)]
+    child_priority: Option<std::sync::Arc<priority::ChildPriority>>,
+}
+
+impl Opt {
+    fn program(&self) -> PathBuf {
+        paths::expand_tilde(&self.command[0])
+    }
+
+    fn args(&self) -> &[OsString] {
+        &self.command[1..]
+    }
+
+    /// Takes over everything `job` sets.
+    fn with_job(mut self, job: job::Job) -> Opt {
+        self.command = std::iter::once(job.program.into_os_string()).chain(job.args.into_iter().map(OsString::from)).collect();
+        self.env = job.env;
+
+        if let Some(cwd) = job.cwd {
+            self.cwd = Some(cwd);
+        }
+
+        if let Some(caffeinate) = job.caffeinate {
+            self.caffeinate = caffeinate;
+        }
+
+        if let Some(priority) = job.priority {
+            self.no_nice = !priority.cpu;
+            self.no_ioprio = !priority.io;
+            self.only_io = false;
+            self.only_cpu = false;
+            self.lowest = priority.lowest;
+        }
+
+        self
+    }
+
+    /// The command line asking for our priority settings explicitly.
+    fn preview(&self) -> String {
+        let mut line = vec!["nicer".to_string()];
+        line.extend(self.priority().flags());
+
+        if self.self_only {
+            line.push("--self-only".to_string());
+        }
+
+        if let Some(nice) = self.wrapper_nice {
+            line.push(format!("--wrapper-nice={}", nice));
+        }
+
+        if self.child_only {
+            line.push("--child-only".to_string());
+        }
+
+        if let Some(nice) = self.nice {
+            line.push(format!("--nice={}", nice));
+        }
+
+        if let Some(class) = self.priority_class {
+            line.push(format!("--priority={}", class.name()));
+        }
+
+        if let Some(delta) = self.renice_by {
+            line.push(format!("--renice-by={}", delta));
+        }
+
+        if let Some(ramp) = self.ramp {
+            line.push(format!("--ramp={}", ramp));
+        }
+
+        if self.strict {
+            line.push("--strict".to_string());
+        }
+
+        line.push("--".to_string());
+        line.extend(self.command.iter().map(|arg| shell_quote(&arg.to_string_lossy())));
+        line.join(" ")
+    }
+
+    /// Whether the program's output goes through us rather than straight to
+    /// our stdout and stderr.
+    fn relays_output(&self) -> bool {
+        self.prefix.is_some() || self.timestamps || self.output_log.is_some() || self.tee.is_some() || self.tee_stderr.is_some()
+    }
+
+    /// Whether the program lowers its own priority as it starts, leaving
+    /// nicer's alone.
+    fn nices_child(&self) -> bool {
+        self.child_only || self.wrapper_nice.is_some() || self.health_check.is_some() || self.priority_from_pid.is_some() || self.ramp.is_some()
+    }
+
+    /// The address space limit standing in for --max-memory and
+    /// --memory-limit without cgroups.
+    #[cfg(unix)]
+    fn address_space_limit(&self) -> Option<u64> {
+        match (self.max_memory, self.memory_limit) {
+            (Some(high), Some(max)) => Some(high.min(max)),
+            (high, max) => high.or(max),
+        }
+    }
+
+    /// The cgroup controllers the program's cgroup needs, if it needs one.
+    #[cfg(target_os = "linux")]
+    fn controllers(&self) -> Vec<&'static str> {
+        let priority = self.priority();
+        let mut controllers = Vec::new();
+
+        if self.max_memory.is_some() || self.memory_limit.is_some() {
+            controllers.push("memory");
+        }
+
+        if self.cpu_quota.is_some() || (self.cgroup_weights && priority.cpu) {
+            controllers.push("cpu");
+        }
+
+        if self.cgroup_weights && priority.io && priority.io_class == priority::IoClass::Idle {
+            controllers.push("io");
+        }
+
+        controllers
+    }
+
+    /// The signals to relay to the program, before leaving out our own.
+    #[cfg(unix)]
+    fn forwarded_signals(&self) -> &[nix::sys::signal::Signal] {
+        self.forward_signals.as_deref().unwrap_or(signals::FORWARDED)
+    }
+
+    /// Whether the program goes in a job object.
+    #[cfg(windows)]
+    fn job_object(&self) -> bool {
+        self.job_object || self.tie_lifetime || self.cpu_quota.is_some() || self.memory_limit.is_some() || self.max_cpu_time.is_some()
+    }
+
+    /// The resource limits the program starts under, bar the RLIMIT_AS that
+    /// stands in for a cgroup on Linux when there's none to be had.
+    #[cfg(unix)]
+    fn limits(&self) -> Vec<limits::Limit> {
+        let mut limits = Vec::new();
+
+        #[cfg(not(target_os = "linux"))]
+        if let Some(limit) = self.address_space_limit() {
+            limits.push(limits::Limit::fixed(nix::libc::RLIMIT_AS, limit));
+        }
+
+        // RLIMIT_CPU counts whole seconds, and SIGKILLs five after SIGXCPU.
+        if let Some(cpu_time) = self.max_cpu_time {
+            let seconds = cpu_time.as_secs() + u64::from(cpu_time.subsec_nanos() > 0);
+            limits.push(limits::Limit { resource: nix::libc::RLIMIT_CPU, soft: seconds as _, hard: (seconds + 5) as _ });
+        }
+
+        if let Some(max_procs) = self.max_procs {
+            limits.push(limits::Limit::fixed(nix::libc::RLIMIT_NPROC, max_procs));
+        }
+
+        limits.extend(&self.rlimit);
+        limits
+    }
+
+    /// Whether the program needs a cgroup it can be frozen in.
+    fn freezes(&self) -> bool {
+        self.cgroup_freeze || self.freeze_above_load.is_some()
+    }
+
+    /// Whether to try a systemd scope for the program: with --systemd-scope,
+    /// or by default unless something needs a cgroup of nicer's own or the
+    /// program isn't to be weighed down as a whole.
+    #[cfg(target_os = "linux")]
+    fn tries_scope(&self) -> bool {
+        self.systemd_scope || !(self.no_systemd_scope || self.cgroup_weights || self.freezes() || self.self_only || self.priority_from_pid.is_some())
+    }
+
+    /// The kinds of priority to lower, with the presets folded in.
+    fn priority(&self) -> priority::PriorityConfig {
+        priority::PriorityConfig {
+            cpu: !(self.no_nice || self.only_io || (cfg!(target_os = "macos") && self.macos_io_throttle)),
+            io: !(self.no_ioprio || self.only_cpu),
+            lowest: self.lowest,
+            timers: !self.no_timer_throttle,
+            memory: self.background_mode,
+            eco: self.eco,
+            tier: self.tier,
+            sched: self.sched,
+            oom_score_adj: self.oom_score_adj,
+            io_class: match (self.ioprio_from_nice, self.io_class) {
+                (true, _) => priority::IoClass::None,
+                (false, Some(priority::IoClass::BestEffort(level))) => priority::IoClass::BestEffort(self.io_level.unwrap_or(level)),
+                (false, None) if cfg!(target_os = "macos") && self.tier == priority::Tier::Utility => priority::IoClass::BestEffort(7),
+                (false, class) => class.unwrap_or(priority::IoClass::Idle),
+            },
+            nice: self.nice.or_else(|| self.priority_class.map(priority::PriorityClass::nice)).unwrap_or(19),
+        }
+    }
+}
+
+/// Our command line, with `run` filled in when no subcommand was given.
+///
+/// This keeps `nicer <program> [args...]` working; a program that happens to
+/// be called like one of the subcommands needs an explicit `nicer run`.
+fn args() -> Vec<OsString> {
+    const SUBCOMMANDS: &[&str] = &["run", "renice", "reset", "query", "completions", "__mitigated", "__hold-wakelock", "__tie", "help", "-h", "--help", "-V", "--version"];
+
+    let mut args: Vec<OsString> = env::args_os().collect();
+
+    match args.get(1) {
+        Some(arg) if SUBCOMMANDS.iter().any(|subcommand| arg == subcommand) => {}
+        Some(_) => args.insert(1, "run".into()),
+        None => {}
+    }
+
+    args
+}
+
+fn main() {
+    // Everything that cleans up after itself (the wakelock, the transient
+    // cgroup) is dropped by the time the subcommand returns, so exiting
+    // right after is safe.
+    let code = match subcommand(Cli::from_iter(args())) {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("Error: {:?}", error);
+            log::system(log::Level::Error, &format!("{:#}", error));
+            error_code(&error)
+        }
+    };
+
+    #[cfg(unix)] {
+        let _ = io::Write::flush(&mut io::stdout());
+        signals::reraise();
+    }
+
+    process::exit(code);
+}
+
+/// What nicer exits with when `error` stopped it, following the shell in
+/// telling programs that can't be executed apart from other failures.
+fn error_code(error: &anyhow::Error) -> i32 {
+    match error.root_cause().downcast_ref::<io::Error>() {
+        Some(error) if not_executable(error) => EXIT_NOT_EXECUTABLE,
+        _ => 1,
+    }
+}
+
+/// Runs a subcommand, returning the exit code nicer should end with.
+fn subcommand(cli: Cli) -> Result<i32> {
+    match cli {
+        Cli::Run(opt) => run(*opt),
+        Cli::Renice { pid, tree } => adjust(pid, &tree, "renice", false, |pid| priority::renice(pid, &Default::default())),
+        Cli::Reset { pid, tree } => adjust(pid, &tree, "reset", true, priority::reset),
+        Cli::Query { pid } => {
+            let priority = priority::query(pid).with_context(|| format!("Unable to query process {}", pid))?;
+            println!("{}", priority);
+            Ok(0)
+        }
+        #[cfg(windows)]
+        Cli::Mitigated { policies, low_integrity, command } => {
+            let mitigations = policies.split(',').filter(|name| !name.is_empty()).map(str::parse).collect::<Result<_>>()?;
+            mitigations::run(mitigations, low_integrity, &command)
+        }
+        #[cfg(unix)]
+        Cli::Mitigated { .. } => anyhow::bail!("Mitigation policies are only supported on Windows"),
+        Cli::HoldWakeLock { types, pid, program } => wakelock::hold(&program, pid, &types),
+        #[cfg(target_os = "macos")]
+        Cli::Tie { parent, pid } => tie::watch(parent, pid),
+        #[cfg(not(target_os = "macos"))]
+        Cli::Tie { .. } => anyhow::bail!("The --tie-lifetime watcher is only needed on macOS"),
+        Cli::Completions { shell } => {
+            Cli::clap().gen_completions_to("nicer", shell, &mut io::stdout());
+            Ok(0)
+        }
+    }
+}
+
+/// Runs `apply` on `pid`, and with --recursive on its descendants, failing
+/// only if no process could be adjusted; with `strict`, a process counts as
+/// adjusted only if every adjustment of it went through.
+fn adjust(pid: u32, tree: &TreeOpt, what: &str, strict: bool, apply: impl Fn(u32) -> Result<priority::ApplyReport>) -> Result<i32> {
+    #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+    let mut pids = vec![pid];
+
+    #[cfg(target_os = "linux")]
+    if tree.recursive {
+        pids.extend(tree::descendants(pid));
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    if tree.recursive {
+        warning!("--recursive is only supported on Linux");
+    }
+
+    if !tree.recursive && !tree.count && !tree.json {
+        return apply(pid).and_then(|applied| applied.check(strict)).with_context(|| format!("Unable to {} process {}", what, pid)).map(|()| 0);
+    }
+
+    let mut report = priority::TreeReport::default();
+
+    for pid in pids {
+        let result = apply(pid).and_then(|applied| {
+            report.adjustments.extend(applied.adjustments(pid));
+            applied.check(strict)
+        });
+
+        if let Err(error) = &result {
+            verbose!("unable to {} process {}: {:#}", what, pid, error);
+        }
+
+        report.add(&result);
+    }
+
+    if tree.json {
+        println!("{}", serde_json::to_string(&report)?);
+    } else if tree.count {
+        println!("{}", report);
+    }
+
+    match (report.adjusted, tree.recursive) {
+        (0, true) => anyhow::bail!("Unable to {} process {} or any of its descendants", what, pid),
+        (0, false) => anyhow::bail!("Unable to {} process {}", what, pid),
+        _ => Ok(0),
+    }
+}
+
+/// The exit code that passes on how the program ended, shell style: its own
+/// code, or 128 plus the signal that killed it.
+fn exit_code(status: ExitStatus) -> i32 {
+    match status.code() {
+        Some(code) => code,
+        None => {
+            // Without an exit code the program was ended by a signal, which
+            // a shell reports as 128 plus its number, core dump or not.
+            #[cfg(unix)] {
+                use std::os::unix::process::ExitStatusExt;
+
+                match status.signal().or_else(|| status.stopped_signal()) {
+                    Some(signal) => 128 + signal,
+                    None => 128,
+                }
+            }
+
+            #[cfg(windows)]
+            127
+        }
+    }
+}
+
+/// How often the supervisor loop wakes up while the program runs.
+const TICK: Duration = Duration::from_secs(1);
+
+/// How long --wait-for-port waits when --ready-timeout isn't given.
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often --health-check runs when --health-interval isn't given.
+const HEALTH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long a program stopped for --watch gets to exit before it's killed.
+const WATCH_GRACE: Duration = Duration::from_secs(5);
+
+/// How long a program stopped for --timeout or --deadline gets to exit
+/// when --kill-after isn't given.
+const KILL_AFTER: Duration = Duration::from_secs(10);
+
+/// How many --health-check failures in a row it takes when
+/// --unhealthy-threshold isn't given.
+const UNHEALTHY_THRESHOLD: u32 = 3;
+
+/// What we exit with once the program is still unhealthy after its last
+/// restart.
+const EXIT_UNHEALTHY: i32 = 125;
+
+/// What we exit with when the program was stopped at --deadline or
+/// --timeout.
+const EXIT_DEADLINE: i32 = 124;
+
+/// What we exit with when the program used up --max-cpu-time, as a shell
+/// reports SIGXCPU.
+const EXIT_CPU_TIME: i32 = 152;
+
+/// What we exit with when --once finds another run holding the lock.
+const EXIT_LOCKED: i32 = 64;
+
+/// What we exit with when the program can't be executed, as a shell does.
+const EXIT_NOT_EXECUTABLE: i32 = 126;
+
+/// Shell builtins people reach for as if they were programs.
+const BUILTINS: &[&str] = &[".", "alias", "bg", "cd", "eval", "exec", "exit", "export", "fg", "history", "jobs", "read", "set", "shift", "source", "trap", "type", "ulimit", "unset", "wait"];
+
+/// Whether `error` is the system refusing to execute a file it doesn't
+/// recognise as a program.
+fn not_executable(error: &io::Error) -> bool {
+    #[cfg(unix)]
+    return error.raw_os_error() == Some(nix::libc::ENOEXEC);
+
+    #[cfg(windows)]
+    return error.raw_os_error() == Some(winapi::shared::winerror::ERROR_BAD_EXE_FORMAT as i32);
+}
+
+/// Explains why `program` couldn't be started, for the failures that have a
+/// likely fix.
+fn spawn_error(program: &Path, error: io::Error) -> anyhow::Error {
+    let name = program.to_string_lossy();
+
+    let hint = if not_executable(&error) {
+        match cfg!(unix) {
+            true => format!("{:?} is neither a binary nor a script with a #! line; run it under a shell (sh {:?}) or pass --exec-fallback", program, program),
+            false => format!("{:?} is not a Windows program; run it through its interpreter", program),
+        }
+    } else if error.kind() == io::ErrorKind::NotFound && BUILTINS.contains(&name.as_ref()) {
+        format!("{} is a shell builtin, not a program; run it under a shell with sh -c {:?}", name, name)
+    } else {
+        return anyhow::Error::new(error).context("Unable to spawn program");
+    };
+
+    anyhow::Error::new(error).context(hint).context("Unable to spawn program")
+}
+
+/// Why we killed the program.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kill {
+    /// It kept failing its health check.
+    Unhealthy,
+    /// It used up --max-cpu-time.
+    CpuTime,
+    /// It ran past --deadline.
+    Deadline,
+    /// It ran longer than --timeout.
+    Timeout,
+    /// A file under --watch changed.
+    Changed,
+}
+
+/// Whom a signal for `pid` goes to: with `group`, the process group it
+/// leads, so the program's children don't outlive it.
+#[cfg(unix)]
+fn signalled(pid: u32, group: bool) -> nix::unistd::Pid {
+    nix::unistd::Pid::from_raw(if group { -(pid as i32) } else { pid as i32 })
+}
+
+/// Kills `pid` outright, for a program too wedged to ask nicely, or with
+/// `group` (on Unix) its whole process group.
+fn kill(pid: u32, group: bool) {
+    #[cfg(unix)] {
+        use nix::sys::signal::{kill, Signal};
+
+        if let Err(error) = kill(signalled(pid, group), Signal::SIGKILL) {
+            warning!("unable to kill pid {}: {}", pid, error);
+        }
+    }
+
+    #[cfg(windows)]
     unsafe {
-        use nix::libc;
+        use winapi::shared::minwindef::FALSE;
+        use winapi::um::handleapi::CloseHandle;
+        use winapi::um::processthreadsapi::{OpenProcess, TerminateProcess};
+        use winapi::um::winnt::PROCESS_TERMINATE;
+
+        let _ = group;
+        let handle = OpenProcess(PROCESS_TERMINATE, FALSE, pid);
+
+        if handle.is_null() || TerminateProcess(handle, 1) == FALSE {
+            warning!("unable to kill pid {}: {}", pid, std::io::Error::last_os_error());
+        }
+
+        if !handle.is_null() {
+            CloseHandle(handle);
+        }
+    }
+}
+
+/// Asks `pid`, or with `group` its process group, to stop with SIGTERM;
+/// Windows has nothing gentler than killing it.
+fn terminate(pid: u32, group: bool) {
+    #[cfg(unix)] {
+        use nix::sys::signal::{kill, Signal};
+
+        if let Err(error) = kill(signalled(pid, group), Signal::SIGTERM) {
+            warning!("unable to stop pid {}: {}", pid, error);
+        }
+    }
+
+    #[cfg(windows)]
+    kill(pid, group);
+}
+
+/// Asks `pid`, started in a console process group of its own, to stop with
+/// Ctrl-Break, and kills it if that can't be sent.
+#[cfg(windows)]
+fn interrupt(pid: u32) {
+    if let Err(error) = console::ctrl_break(pid) {
+        verbose!("unable to send Ctrl-Break to pid {}: {}, killing it", pid, error);
+        kill(pid, true);
+    }
+}
+
+/// Probes whether `probe` would go through in the run, recording under
+/// `what` whether it fails outright or only in part (which the run puts up
+/// with unless --strict).
+#[cfg(unix)]
+fn check_priority(report: &mut check::Report, what: &str, strict: bool, probe: impl Fn() -> priority::ApplyReport) {
+    match check::in_child(|| probe().check(true)) {
+        Err(error) if !strict && check::in_child(|| probe().check(false)).is_ok() => report.fallback(what, format!("{:#}, the rest would still be applied", error)),
+        result => report.add(what, result),
+    }
+}
+
+/// Checks each requirement of the run, for --check.
+fn check(opt: &Opt) -> check::Report {
+    let mut report = check::Report::default();
+    let program = opt.program();
+
+    let found = match &opt.chroot {
+        // Inside a chroot the program only turns up once we're in it.
+        Some(_) => Ok(()),
+        None => paths::find_program(&program, opt.cwd.as_deref()).map(|found| verbose!("the program is {:?}", found)),
+    };
+
+    match opt.no_path {
+        true => report.add("find the program", paths::require_explicit(&program).and(found)),
+        false => report.add("find the program", found),
+    }
+
+    #[cfg(unix)]
+    {
+        let priority = opt.priority();
+
+        match opt.copied_priority {
+            Some(copied) => check_priority(&mut report, "copy the priority", opt.strict, || copied.apply(0)),
+            None => check_priority(&mut report, "lower the priority", opt.strict, || priority::nice_process(&priority)),
+        }
+
+        if let Some(nice) = opt.wrapper_nice {
+            report.add("set nicer's own niceness", check::in_child(|| priority::set_nice(nice)));
+        }
+
+        let limits = opt.limits();
+
+        if !limits.is_empty() {
+            let set = limits::check(&limits).and_then(|()| check::in_child(|| limits::set(&limits).context("Unable to set a resource limit")));
+            report.add("set the resource limits", set);
+        }
+
+        if opt.chroot.is_some() && !nix::unistd::geteuid().is_root() {
+            report.add("change root", Err(anyhow::anyhow!("--chroot needs root")));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let scoped = opt.tries_scope() && scope::available();
+
+        if opt.systemd_scope && !scoped {
+            report.fallback("start a systemd scope", "systemd isn't running, the program would start without one".to_string());
+        }
+
+        let controllers = opt.controllers();
+
+        if !scoped && (!controllers.is_empty() || opt.freezes()) {
+            // Creating one is the only way to be sure; it's gone again once dropped.
+            match cgroup::Cgroup::create(&controllers) {
+                Ok(_) => report.add("create a cgroup", Ok(())),
+                Err(error) if opt.cpu_quota.is_none() && !opt.freezes() => {
+                    let instead = match opt.address_space_limit() {
+                        Some(_) => "memory would be limited through RLIMIT_AS instead",
+                        None => "the program would go without cgroup weights",
+                    };
+
+                    report.fallback("create a cgroup", format!("{:#}, {}", error, instead))
+                }
+                Err(error) => report.add("create a cgroup", Err(error)),
+            }
+        }
+    }
+
+    #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    if let Some(profile) = &opt.seccomp {
+        report.add("load the seccomp filter", seccomp::load(profile).map(drop));
+    }
+
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+    if opt.seccomp.is_some() {
+        report.add("load the seccomp filter", Err(anyhow::anyhow!("--seccomp is only supported on Linux (x86_64 and aarch64)")));
+    }
+
+    #[cfg(target_os = "linux")]
+    if opt.isolate_network {
+        report.add("isolate the network", network_isolation().map(drop));
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    if opt.isolate_network {
+        report.add("isolate the network", Err(anyhow::anyhow!("--isolate-network is only supported on Linux")));
+    }
+
+    #[cfg(any(target_os = "linux", windows))]
+    if opt.efficiency_cores && cpus::efficiency_cores().is_none() {
+        report.fallback("find the efficiency cores", "unable to tell them apart, background priority would have to do".to_string());
+    }
+
+    if opt.caffeinate || opt.require_caffeinate {
+        // Taking the wakelock is harmless, and it's let go of right away.
+        match wakelock::wakelock(&program.to_string_lossy(), process::id(), &opt.caffeinate_type) {
+            Ok(_) => report.add("keep the system awake", Ok(())),
+            Err(error) if opt.require_caffeinate => report.add("keep the system awake", Err(error)),
+            Err(error) => report.fallback("keep the system awake", format!("{:#}, the program would run anyway", error)),
+        }
+    }
+
+    report
+}
+
+/// Moves nicer into a transient systemd scope for the program to inherit,
+/// returning whether it did.
+#[cfg(target_os = "linux")]
+fn systemd_scope(opt: &Opt, program: &Path) -> bool {
+    if !scope::available() {
+        verbose!("systemd isn't running, starting the program without a scope");
+        return false;
+    }
+
+    let scope = scope::Scope { description: format!("nicer: {}", program.display()), priority: opt.priority(), memory_high: opt.max_memory, memory_max: opt.memory_limit, cpu_quota: opt.cpu_quota };
+
+    match scope::enter(&scope) {
+        Ok(name) => {
+            verbose!("running the program in systemd scope {}", name);
+            true
+        }
+        // Only --systemd-scope insists on one.
+        Err(error) if opt.systemd_scope => {
+            warning!("unable to start a systemd scope, starting the program without one: {:#}", error);
+            false
+        }
+        Err(error) => {
+            verbose!("unable to start a systemd scope, starting the program without one: {:#}", error);
+            false
+        }
+    }
+}
+
+/// Gets ready to start the program in a network namespace of its own,
+/// trying it out first: a failure in the program's pre_exec would only come
+/// back as a bare errno.
+#[cfg(target_os = "linux")]
+fn network_isolation() -> Result<child::NetworkIsolation> {
+    let isolation = child::NetworkIsolation::new();
+
+    match check::in_child(|| isolation.enter().map_err(anyhow::Error::new)) {
+        Ok(()) => Ok(isolation),
+        Err(error) if isolation.needs_user_namespace() => {
+            Err(error.context("--isolate-network needs root, or unprivileged user namespaces (see the kernel.unprivileged_userns_clone and user.max_user_namespaces sysctls)"))
+        }
+        Err(error) => Err(error.context("Unable to make a network namespace")),
+    }
+}
+
+/// Creates the transient cgroup the program runs in, if anything asked for
+/// one, falling back to plain resource limits where we can't have it.
+#[cfg(target_os = "linux")]
+fn transient_cgroup(opt: &Opt, limits: &mut Vec<limits::Limit>) -> Result<Option<cgroup::Cgroup>> {
+    let controllers = opt.controllers();
+
+    if controllers.is_empty() && !opt.freezes() {
+        return Ok(None);
+    }
+
+    let cgroup = match cgroup::Cgroup::create(&controllers) {
+        Ok(cgroup) => cgroup,
+        // There's nothing else that can hard cap CPU usage, or freeze a tree.
+        Err(error) if opt.cpu_quota.is_some() => return Err(error.context("Unable to cap the program's CPU usage")),
+        Err(error) if opt.freezes() => return Err(error.context("Unable to make a cgroup to freeze the program in")),
+        Err(error) => {
+            if opt.cgroup_weights {
+                verbose!("no cgroup to weigh the program down in ({}), relying on its priority alone", error);
+            }
+
+            if let Some(limit) = opt.address_space_limit() {
+                verbose!("no cgroup memory control ({}), limiting address space instead", error);
+                limits.push(limits::Limit::fixed(nix::libc::RLIMIT_AS, limit));
+            }
+
+            return Ok(None);
+        }
+    };
+
+    verbose!("running the program in cgroup {:?}", cgroup.path());
+
+    if let Some(max_memory) = opt.max_memory {
+        cgroup.write("memory.high", &max_memory.to_string())?;
+    }
+
+    if let Some(limit) = opt.memory_limit {
+        cgroup.write("memory.max", &limit.to_string())?;
+    }
+
+    if let Some(quota) = opt.cpu_quota {
+        const PERIOD: u64 = 100_000;
+        cgroup.write("cpu.max", &format!("{} {}", quota as u64 * PERIOD / 100, PERIOD))?;
+    }
+
+    if opt.cgroup_weights {
+        let priority = opt.priority();
+
+        if priority.cpu {
+            cgroup.write("cpu.weight", &cgroup::cpu_weight(priority.nice).to_string())?;
+        }
+
+        // cpu.idle is new in Linux 5.15; the weight above does most of it.
+        if priority.cpu && priority.lowest {
+            if let Err(error) = cgroup.write("cpu.idle", "1") {
+                verbose!("unable to make the cgroup idle: {:#}", error);
+            }
+        }
+
+        // io.weight needs the iocost controller; BFQ has one of its own.
+        if controllers.contains(&"io") {
+            if let Err(error) = cgroup.write("io.weight", "default 1").or_else(|_| cgroup.write("io.bfq.weight", "default 1")) {
+                verbose!("unable to lower the cgroup's I/O weight: {:#}", error);
+            }
+        }
+    }
+
+    Ok(Some(cgroup))
+}
+
+/// Parses a CPU share such as `20` or `20%`, where 100 is one whole CPU.
+fn parse_percent(text: &str) -> Result<u32> {
+    let percent: u32 = text.trim().trim_end_matches('%').parse().with_context(|| format!("Invalid percentage {:?}", text))?;
+
+    match percent {
+        0 => anyhow::bail!("The percentage must be more than 0%"),
+        percent => Ok(percent),
+    }
+}
+
+/// Parses an octal file creation mask such as `022` or `0o077`.
+fn parse_umask(text: &str) -> Result<u32> {
+    let digits = text.trim().trim_start_matches("0o");
+    let mask = u32::from_str_radix(digits, 8).with_context(|| format!("Invalid umask {:?}, expected octal digits", text))?;
+
+    match mask {
+        0..=0o777 => Ok(mask),
+        _ => anyhow::bail!("Invalid umask {:?}, it must be at most 0777", text),
+    }
+}
+
+/// Lowers our own priority the way the flags ask for; the program inherits
+/// it unless it has been spawned already.
+fn background_self(opt: &Opt) -> Result<()> {
+    priority::nice_process(&opt.priority()).check(opt.strict).context("Unable to lower the priority")
+}
+
+/// Quotes `word` for a POSIX shell, if it needs it.
+fn shell_quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-~".contains(c);
+
+    match !word.is_empty() && word.chars().all(safe) {
+        true => word.to_string(),
+        false => format!("'{}'", word.replace('\'', "'\\''")),
+    }
+}
+
+/// Parses a niceness, from -20 (highest priority) to 19 (lowest).
+fn parse_nice(text: &str) -> Result<i32> {
+    let nice: i32 = text.trim().parse().with_context(|| format!("Invalid niceness {:?}", text))?;
+
+    match nice {
+        -20..=19 => Ok(nice),
+        _ => anyhow::bail!("Invalid niceness {}, it must be between -20 and 19", nice),
+    }
+}
+
+/// Parses an oom_score_adj, from -1000 (never killed) to 1000 (killed first).
+fn parse_oom_score_adj(text: &str) -> Result<i32> {
+    let adj: i32 = text.trim().parse().with_context(|| format!("Invalid OOM score adjustment {:?}", text))?;
+
+    match adj {
+        -1000..=1000 => Ok(adj),
+        _ => anyhow::bail!("Invalid OOM score adjustment {}, it must be between -1000 and 1000", adj),
+    }
+}
+
+/// Parses a best-effort I/O level, from 0 (most favoured) to 7 (least).
+fn parse_io_level(text: &str) -> Result<i32> {
+    let level: i32 = text.trim().parse().with_context(|| format!("Invalid I/O level {:?}", text))?;
+
+    match level {
+        0..=7 => Ok(level),
+        _ => anyhow::bail!("Invalid I/O level {}, it must be between 0 and 7", level),
+    }
+}
+
+/// Parses one exit code rewrite such as `4=0`.
+fn parse_exit_mapping(text: &str) -> Result<(i32, i32)> {
+    let (from, to) = text.split_once('=').with_context(|| format!("Invalid exit code mapping {:?}, expected FROM=TO", text))?;
+    let from = from.trim().parse().with_context(|| format!("Invalid exit code {:?}", from))?;
+    let to = to.trim().parse().with_context(|| format!("Invalid exit code {:?}", to))?;
+
+    Ok((from, to))
+}
+
+fn run(opt: Opt) -> Result<i32> {
+    log::set_verbose(opt.verbose);
+    log::set_color(opt.color);
+
+    #[cfg(unix)]
+    if opt.log_journal {
+        log::set_system_log(log::SystemLog::Journal).context("Unable to log to the journal")?;
+    }
+
+    #[cfg(unix)]
+    if opt.log_syslog {
+        log::set_system_log(log::SystemLog::Syslog).context("Unable to log to syslog")?;
+    }
+
+    #[cfg(windows)]
+    if opt.log_journal || opt.log_syslog {
+        warning!("--log-journal and --log-syslog are only supported on Unix");
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    if opt.macos_io_throttle {
+        warning!("--macos-io-throttle is only supported on macOS");
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    if opt.no_timer_throttle {
+        warning!("--no-timer-throttle is only supported on macOS");
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    if opt.ioprio_from_nice {
+        warning!("--ioprio-from-nice is only supported on Linux");
+    }
+
+    #[cfg(not(windows))]
+    if opt.background_mode {
+        warning!("--background-mode is only supported on Windows");
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    if opt.sched.is_some() {
+        warning!("--sched is only supported on Linux");
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    if opt.oom_score_adj.is_some() {
+        warning!("--oom-score-adj is only supported on Linux");
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    if opt.tier != priority::Tier::Background {
+        warning!("--tier is only supported on macOS");
+    }
+
+    #[cfg(not(windows))]
+    if opt.eco {
+        warning!("--eco is only supported on Windows");
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+    if opt.io_class.is_some() {
+        warning!("--io-class is only supported on Linux and macOS");
+    }
+
+    #[cfg(target_os = "macos")]
+    if opt.io_level.is_some() {
+        warning!("--io-level is only supported on Linux");
+    }
+
+    if opt.io_level.is_some() && !matches!(opt.io_class, Some(priority::IoClass::BestEffort(_))) {
+        anyhow::bail!("--io-level only applies to --io-class best-effort");
+    }
+
+    if opt.cpus.as_ref().is_some_and(Vec::is_empty) {
+        anyhow::bail!("--cpus needs at least one CPU");
+    }
+
+    if opt.json && !opt.print_config && !opt.stats {
+        anyhow::bail!("--json needs --print-config or --stats");
+    }
+
+    let job = opt.job_file.as_deref().map(job::load).transpose()?;
+
+    if opt.print_config {
+        let settings = settings(&opt, job.as_ref())?;
+
+        match opt.json {
+            true => println!("{}", serde_json::to_string(&settings)?),
+            false => println!("{}", settings),
+        }
+
+        return Ok(0);
+    }
+
+    let mut opt = match job {
+        Some(job) => opt.with_job(job),
+        None => opt,
+    };
+
+    if opt.priority_preview {
+        println!("{}", opt.preview());
+        return Ok(0);
+    }
+
+    if let Some(delta) = opt.renice_by {
+        let current = priority::current_nice().context("Unable to read nicer's niceness")?;
+        let nice = (current + delta).clamp(-20, 19);
+
+        verbose!("renicing by {} from nice {} to {}", delta, current, nice);
+        opt.nice = Some(nice);
+    }
+
+    if let Some(ramp) = opt.ramp {
+        verbose!("ramping from nice {} to {} over {:?}", ramp.from, ramp.to, ramp.over);
+        opt.nice = Some(ramp.from);
+    }
+
+    if opt.preserve_nice {
+        let current = priority::current_nice().context("Unable to read nicer's niceness")?;
+        let nice = opt.priority().nice;
+
+        if current >= nice {
+            verbose!("already at nice {}, so staying there rather than going to {}", current, nice);
+            opt.nice = Some(current);
+        }
+    }
+
+    if let Some(pid) = opt.priority_from_pid {
+        let priority = priority::query(pid).with_context(|| format!("Unable to read the priority of process {}", pid))?;
+
+        // Once read it no longer matters if the process goes away.
+        verbose!("giving the program the priority of process {}: {}", pid, priority.to_string().replace('\n', ", "));
+        opt.copied_priority = Some(priority);
+    }
+
+    if let Some(deadline) = opt.deadline {
+        let remaining = deadline.remaining(opt.allow_past_deadline)?;
+
+        verbose!("stopping the program at {}, {:?} from now", deadline, remaining);
+        opt.watchdog = Some(Instant::now() + remaining);
+    }
+
+    // In the foreground the terminal signals the program's whole tree along
+    // with us; elsewhere (under a service manager, in the background) we
+    // have to, and only a process group reaches the lot. Our own timeouts
+    // and kills go to the group too whenever there is one.
+    // On Windows only --timeout needs one, to send Ctrl-Break to the
+    // program alone; everywhere else Ctrl-C is better.
+    #[cfg(unix)] {
+        opt.own_group = !signals::in_foreground();
+    }
+    #[cfg(windows)] {
+        opt.own_group = opt.timeout.is_some();
+    }
+
+    if opt.check {
+        let report = check(&opt);
+        println!("{}", report);
+        return Ok(if report.passed() { 0 } else { 1 });
+    }
+
+    // Held until we return, through every restart.
+    let _lock = match &opt.once {
+        Some(path) => match lock::acquire(path)? {
+            Some(lock) => Some(lock),
+            None => {
+                match lock::holder(path) {
+                    Some(pid) => warning!("{:?} is held by another run (pid {}), not starting", path, pid),
+                    None => warning!("{:?} is held by another run, not starting", path),
+                }
+
+                return Ok(EXIT_LOCKED);
+            }
+        },
+        None => None,
+    };
+
+    if let Some(path) = &opt.batch {
+        return batch(&opt, path);
+    }
+
+    if opt.no_path {
+        paths::require_explicit(&opt.program())?;
+    }
+
+    // Inside a chroot the program and --cwd only make sense once we're in it.
+    let program = match opt.chroot {
+        Some(_) => opt.program(),
+        None => paths::resolve_program(&opt.program(), opt.cwd.as_deref())?,
+    };
+
+    #[cfg(unix)]
+    if opt.chroot.is_some() && !nix::unistd::geteuid().is_root() {
+        anyhow::bail!("--chroot needs root");
+    }
+
+    #[cfg(windows)]
+    if opt.chroot.is_some() {
+        anyhow::bail!("--chroot is only supported on Unix");
+    }
+
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+    if opt.seccomp.is_some() {
+        anyhow::bail!("--seccomp is only supported on Linux (x86_64 and aarch64)");
+    }
+
+    #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    let seccomp = opt.seccomp.as_deref().map(seccomp::load).transpose()?;
+
+    // With --self-only the program is spawned before we lower our own
+    // priority, so it never inherits it and doesn't need raising back (which
+    // would take privileges we may not have). With --child-only,
+    // --wrapper-nice, --health-check, --priority-from-pid or --ramp it sets
+    // its own instead.
+    if !opt.self_only && !opt.nices_child() {
+        background_self(&opt)?;
+    }
+
+    if let Some(nice) = opt.wrapper_nice {
+        priority::set_nice(nice).with_context(|| format!("Unable to set nicer's own niceness to {}", nice))?;
+    }
+
+    if let Some(delay) = opt.delay {
+        verbose!("waiting {:?} before starting {:?}", delay, program);
+        thread::sleep(delay);
+    }
+
+    if let Some(threshold) = opt.wait_for_load {
+        load::wait_below(threshold, opt.load_wait_timeout).context("Not starting the program")?;
+    }
+
+    #[cfg(unix)]
+    let mut command = match (opt.exec_fallback, opt.start_suspended) {
+        (false, false) => Command::new(&program),
+        (_, start_suspended) => {
+            let script = if start_suspended { suspend::SCRIPT } else { "exec \"$0\" \"$@\"" };
+            let mut command = Command::new("/bin/sh");
+            command.arg("-c").arg(script).arg(&program);
+            command
+        }
+    };
+
+    #[cfg(unix)]
+    if let Some(argv0) = &opt.argv0 {
+        use std::os::unix::process::CommandExt;
+        command.arg0(argv0);
+    }
+
+    #[cfg(unix)]
+    if opt.own_group {
+        use std::os::unix::process::CommandExt;
+
+        verbose!("starting {:?} in a process group of its own, so relayed signals reach its children too", program);
+        command.process_group(0);
+    }
+
+    #[cfg(windows)]
+    if opt.exec_fallback {
+        warning!("--exec-fallback is only supported on Unix");
+    }
+
+    #[cfg(windows)]
+    if opt.argv0.is_some() {
+        warning!("--argv0 is only supported on Unix");
+    }
+
+    #[cfg(unix)]
+    if !opt.win_mitigations.is_empty() {
+        warning!("--win-mitigations is only supported on Windows");
+    }
+
+    #[cfg(unix)]
+    if opt.win_low_integrity {
+        warning!("--win-low-integrity is only supported on Windows");
+    }
+
+    // std can't start a program under mitigation policies or another token,
+    // so a hidden nicer subcommand does it for us.
+    #[cfg(windows)]
+    let mut command = match opt.win_mitigations.is_empty() && !opt.win_low_integrity {
+        true => Command::new(&program),
+        false => {
+            let names: Vec<&str> = opt.win_mitigations.iter().map(|mitigation| mitigation.name()).collect();
+            let mut command = Command::new(env::current_exe().context("Unable to find nicer itself")?);
+            command.arg("__mitigated");
+
+            if opt.win_low_integrity {
+                command.arg("--low-integrity");
+            }
+
+            command.arg(names.join(",")).arg("--").arg(&program);
+            command
+        }
+    };
+
+    // Into its job object and at its priority before it gets to run, and
+    // where Ctrl-Break can reach it alone for --timeout.
+    #[cfg(windows)] {
+        use std::os::windows::process::CommandExt;
+        use winapi::um::winbase::{CREATE_NEW_PROCESS_GROUP, CREATE_SUSPENDED};
+
+        let mut flags = 0;
+
+        if opt.start_suspended || opt.job_object() || opt.nices_child() {
+            flags |= CREATE_SUSPENDED;
+        }
+
+        if opt.own_group {
+            flags |= CREATE_NEW_PROCESS_GROUP;
+        }
+
+        command.creation_flags(flags);
+    }
+
+    command.args(opt.args());
+    command.envs(&opt.env);
+
+    match &opt.cwd {
+        Some(cwd) if opt.chroot.is_none() => {
+            command.current_dir(cwd);
+        }
+        _ => {}
+    }
+
+    if opt.close_stdin {
+        command.stdin(Stdio::null());
+    }
+
+    if let Some(stdin) = &opt.stdin {
+        let file = File::open(stdin).with_context(|| format!("Unable to open {:?} for the program's stdin", stdin))?;
+        command.stdin(Stdio::from(file));
+    }
+
+    if opt.quiet_child {
+        command.stdout(Stdio::null()).stderr(Stdio::null());
+    }
+
+    if opt.relays_output() {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+
+    #[cfg(unix)]
+    if let Some(mask) = opt.umask {
+        child::umask(&mut command, mask);
+    }
+
+    #[cfg(windows)]
+    if opt.umask.is_some() {
+        warning!("--umask is only supported on Unix");
+    }
+
+    #[cfg(unix)]
+    let mut limits = Vec::new();
+
+    #[cfg(target_os = "linux")]
+    let cgroup = match opt.tries_scope() && systemd_scope(&opt, &program) {
+        true => None,
+        false => transient_cgroup(&opt, &mut limits)?,
+    };
+
+    #[cfg(target_os = "linux")]
+    if let (Some(cgroup), true) = (&cgroup, opt.freezes()) {
+        control::freezable(cgroup.path());
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    if opt.freezes() {
+        warning!("--cgroup-freeze and --freeze-above-load are only supported on Linux");
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    if opt.systemd_scope {
+        warning!("--systemd-scope is only supported on Linux");
+    }
+
+    // Without systemd there's no scope to leave out.
+    #[cfg(not(target_os = "linux"))]
+    let _ = opt.no_systemd_scope;
+
+    #[cfg(unix)]
+    limits.extend(opt.limits());
+
+    #[cfg(windows)]
+    if opt.max_procs.is_some() {
+        warning!("--max-procs is only supported on Unix");
+    }
+
+    #[cfg(windows)]
+    if opt.max_memory.is_some() {
+        warning!("--max-memory is not supported on Windows yet");
+    }
+
+    #[cfg(not(any(target_os = "linux", windows)))]
+    if opt.cpu_quota.is_some() {
+        warning!("--cpu-quota is only supported on Linux and Windows");
+    }
+
+    #[cfg(not(windows))]
+    if opt.job_object {
+        warning!("--job-object is only supported on Windows");
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    if opt.cgroup_weights {
+        warning!("--cgroup-weights is only supported on Linux");
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(cgroup) = &cgroup {
+        cgroup.attach(&mut command);
+    }
+
+    #[cfg(unix)]
+    {
+        limits::check(&limits)?;
+        limits::apply(&mut command, limits);
+    }
+
+    #[cfg(target_os = "linux")]
+    if opt.efficiency_cores {
+        match cpus::efficiency_cores() {
+            Some(cores) => {
+                verbose!("pinning the program to efficiency cores {:?}", cores);
+                cpus::set_affinity(&mut command, &cores);
+            }
+            None => {
+                warning!("unable to identify efficiency cores, relying on background priority alone");
+            }
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    if opt.efficiency_cores {
+        verbose!("background priority already keeps the program on efficiency cores");
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(cpus) = &opt.cpus {
+        verbose!("pinning the program to CPUs {:?}", cpus);
+        cpus::set_affinity(&mut command, cpus);
+    }
+
+    #[cfg(target_os = "linux")]
+    if opt.inherit_priority_to_descendants {
+        unsafe {
+            if nix::libc::prctl(nix::libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error()).context("Unable to become a subreaper");
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    if opt.inherit_priority_to_descendants {
+        warning!("--inherit-priority-to-descendants is only supported on Linux");
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    if opt.renice_descendants {
+        warning!("--renice-descendants is only supported on Linux, macOS and Windows");
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    if opt.thread_nice {
+        warning!("--thread-nice is only supported on Linux");
+    }
+
+    #[cfg(unix)]
+    if opt.nices_child() {
+        opt.child_priority = Some(match opt.copied_priority {
+            Some(priority) => priority::copy_child(&mut command, priority, opt.strict)?,
+            None => priority::nice_child(&mut command, opt.priority(), opt.strict)?,
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    if opt.isolate_network {
+        verbose!("starting the program without network access");
+        child::isolate_network(&mut command, network_isolation()?);
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    if opt.isolate_network {
+        anyhow::bail!("--isolate-network is only supported on Linux");
+    }
+
+    // Late, as everything before it may still need our own file system.
+    #[cfg(unix)]
+    if let Some(root) = &opt.chroot {
+        child::chroot(&mut command, root, opt.cwd.as_deref())?;
+    }
+
+    // After anything that changes credentials, which would undo it.
+    #[cfg(target_os = "linux")]
+    if opt.tie_lifetime {
+        tie::tie(&mut command);
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    if opt.tie_lifetime {
+        warning!("--tie-lifetime is only supported on Linux, macOS and Windows");
+    }
+
+    // Last, as the filter may deny what the rest still needs.
+    #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    if let Some(filter) = &seccomp {
+        verbose!("starting the program under the {} seccomp filter", opt.seccomp.as_deref().unwrap_or_default());
+        seccomp::apply(&mut command, filter);
+    }
+
+    #[cfg(unix)]
+    let resumable = opt.resume_on_port.is_some() || opt.resume_on_signal.is_some() || opt.control_socket.is_some();
+    #[cfg(windows)]
+    let resumable = opt.resume_on_port.is_some();
+
+    if opt.start_suspended && !resumable {
+        anyhow::bail!("--start-suspended needs --resume-on-port, --resume-on-signal or --control-socket");
+    }
+
+    #[cfg(unix)]
+    if let Some(signal) = opt.resume_on_signal {
+        signals::resume_on(signal)?;
+
+        if opt.forward_signals.as_ref().is_some_and(|signals| signals.contains(&signal)) {
+            warning!("{} resumes the program, so it won't be forwarded", signal);
+        }
+    }
+
+    #[cfg(unix)]
+    if opt.restart_on_failure {
+        signals::drain_on(nix::sys::signal::Signal::SIGUSR1)?;
+
+        if opt.forward_signals.as_ref().is_some_and(|signals| signals.contains(&nix::sys::signal::Signal::SIGUSR1)) {
+            warning!("SIGUSR1 drains the restart loop, so it won't be forwarded");
+        }
+    }
+
+    #[cfg(unix)]
+    let _control = match &opt.control_socket {
+        Some(path) => Some(control::listen(path, program.to_string_lossy().into_owned())?),
+        None => None,
+    };
+
+    #[cfg(windows)]
+    if opt.control_socket.is_some() {
+        warning!("--control-socket is only supported on Unix");
+    }
+
+    if opt.max_restarts.is_some() && !opt.restart_on_failure && opt.health_check.is_none() {
+        anyhow::bail!("--max-restarts needs --restart-on-failure or --health-check");
+    }
 
-        let status = libc::setpriority(libc::PRIO_PROCESS, 0, 19);
+    let mut watch = if opt.watch.is_empty() { None } else { Some(watch::Watch::new(&opt.watch)) };
 
-        match status {
-            0 => Ok(()),
-            _ => {
-                let error = std::io::Error::last_os_error();
+    #[cfg(unix)]
+    if watch.is_some() {
+        signals::drain_on(nix::sys::signal::Signal::SIGINT)?;
+        signals::drain_on(nix::sys::signal::Signal::SIGTERM)?;
+    }
+
+    #[cfg(unix)]
+    let draining = signals::draining;
+    #[cfg(windows)]
+    let draining = || false;
+
+    let mut restarts = 0;
+
+    let (status, kill) = loop {
+        let started = Instant::now();
 
-                match error.raw_os_error() {
-                    Some(0) => Ok(()),
-                    _ => Err(anyhow::Error::new(error))
+        #[cfg(target_os = "linux")]
+        let ended = supervise(&opt, &program, &mut command, watch.as_mut(), cgroup.as_ref())?;
+        #[cfg(not(target_os = "linux"))]
+        let ended = supervise(&opt, &program, &mut command, watch.as_mut())?;
+
+        let (status, kill) = match ended {
+            Some(ended) => ended,
+            None => return Ok(0),
+        };
+
+        if let Some(watch) = &mut watch {
+            if kill != Some(Kill::Changed) && !draining() {
+                verbose!("{:?} ended with {}, waiting for changes", program, status);
+
+                while !draining() && !watch.changed() {
+                    thread::sleep(TICK);
                 }
             }
+
+            if draining() {
+                verbose!("stopped watching");
+                break (status, kill);
+            }
+
+            verbose!("files changed, starting {:?} again", program);
+            continue;
+        }
+
+        let failure = match kill {
+            Some(Kill::Unhealthy) => "was unhealthy".to_string(),
+            Some(Kill::CpuTime) => "ran out of CPU time".to_string(),
+            Some(Kill::Deadline) => "ran past its deadline".to_string(),
+            Some(Kill::Timeout) => "timed out".to_string(),
+            Some(Kill::Changed) | None => format!("failed ({})", status),
+        };
+
+        let restart = match kill {
+            Some(Kill::Unhealthy) => true,
+            Some(Kill::Deadline) => false,
+            _ => opt.restart_on_failure && !status.success(),
+        };
+
+        if !restart {
+            break (status, kill);
+        }
+
+        #[cfg(unix)]
+        if signals::draining() {
+            verbose!("draining, so {:?} isn't restarted", program);
+            break (status, kill);
+        }
+
+        match opt.healthy_after {
+            Some(healthy_after) if restarts > 0 && started.elapsed() >= healthy_after => {
+                verbose!("{:?} stayed up for {:?}, forgetting its {} earlier restarts", program, started.elapsed(), restarts);
+                restarts = 0;
+            }
+            _ => {}
+        }
+
+        if opt.max_restarts.is_some_and(|max| restarts >= max) {
+            warning!("{:?} {} after {} restarts, giving up", program, failure, restarts);
+            break (status, kill);
+        }
+
+        restarts += 1;
+        warning!("{:?} {}, restarting it (restart {})", program, failure, restarts);
+        thread::sleep(TICK);
+    };
+
+    if opt.exit_zero {
+        verbose!("{:?} ended with {}, exiting with 0 anyway", program, status);
+        return Ok(0);
+    }
+
+    let code = match kill {
+        Some(Kill::Unhealthy) => EXIT_UNHEALTHY,
+        Some(Kill::CpuTime) => EXIT_CPU_TIME,
+        Some(Kill::Deadline) | Some(Kill::Timeout) => EXIT_DEADLINE,
+        Some(Kill::Changed) | None => exit_code(status),
+    };
+
+    match opt.map_exit.iter().find(|(from, _)| *from == code) {
+        Some((_, to)) => {
+            verbose!("{:?} exited with {}, exiting with {} instead", program, code, to);
+            Ok(*to)
+        }
+        None => {
+            // Dying of the same signal tells whoever started us more than
+            // 128 plus its number does.
+            #[cfg(unix)]
+            if let (Some(Kill::Changed) | None, Some(signal)) = (kill, std::os::unix::process::ExitStatusExt::signal(&status)) {
+                signals::die_of(signal);
+            }
+
+            Ok(code)
         }
     }
 }
 
-#[cfg(all(unix, target_os = "macos"))]
-fn nice_process() -> Result<()>{
-    unsafe {
-        use nix::libc;
+/// Starts the program and looks after it until it exits, returning how it
+/// ended and why we killed it, if we did; with --detach, nothing as soon as
+/// it has started.
+fn supervise(opt: &Opt, program: &Path, command: &mut Command, mut watch: Option<&mut watch::Watch>, #[cfg(target_os = "linux")] cgroup: Option<&cgroup::Cgroup>) -> Result<Option<(ExitStatus, Option<Kill>)>> {
+    let open = |path: &Option<PathBuf>, what: &str| -> Result<Option<Arc<Mutex<File>>>> {
+        match path {
+            Some(path) => {
+                let file = fs::OpenOptions::new().create(true).append(true).open(path).with_context(|| format!("Unable to open {:?} for the program's {}", path, what))?;
+                Ok(Some(Arc::new(Mutex::new(file))))
+            }
+            None => Ok(None),
+        }
+    };
+
+    let log = open(&opt.output_log, "output")?;
+    let tee = open(&opt.tee, "stdout")?;
+    let tee_stderr = open(&opt.tee_stderr, "stderr")?;
+
+    let started = Instant::now();
+    let mut cmd = command.spawn().map_err(|error| {
+        #[cfg(unix)]
+        if let Some(failure) = opt.child_priority.as_ref().and_then(|niced| niced.failure(&error)) {
+            return failure;
+        }
 
-        let status = libc::setpriority(libc::PRIO_DARWIN_PROCESS, 0, libc::PRIO_DARWIN_BG);
+        spawn_error(program, error)
+    })?;
+    let pid = cmd.id();
 
-        match status {
-            0 => Ok(()),
-            _ => {
-                // Darwin returns ESRCH even though both values are correctly set; skip return
-                // print!("{}\n", libc::getpriority(libc::PRIO_DARWIN_PROCESS, 0));
-                // print!("{}\n", libc::getpriority(libc::PRIO_PROCESS, 0));
-                let error = std::io::Error::last_os_error();
+    #[cfg(unix)]
+    if let Some(niced) = &opt.child_priority {
+        niced.verify(pid);
+    }
+
+    #[cfg(windows)]
+    let _job = match opt.job_object() {
+        true => {
+            use std::os::windows::io::AsRawHandle;
 
-                match error.raw_os_error() {
-                    Some(0) => Ok(()),
-                    _ => Err(anyhow::Error::new(error))
+            let class = match opt.self_only {
+                true => None,
+                false => priority::priority_class(&opt.priority()),
+            };
+            let job = jobobject::Job::new(class, opt.cpu_quota, opt.memory_limit, opt.max_cpu_time, !opt.detach).and_then(|job| job.assign(cmd.as_raw_handle() as _).map(|()| job));
+
+            match job {
+                Ok(job) => Some(job),
+                Err(error) => {
+                    let _ = cmd.kill();
+                    let _ = cmd.wait();
+                    return Err(error);
                 }
-                // return Ok(());
             }
         }
+        false => None,
+    };
+
+    let relay = |tee: Option<Arc<Mutex<File>>>| output::Relay {
+        prefix: opt.prefix.clone(),
+        timestamps: opt.timestamps,
+        logs: log.iter().cloned().chain(tee).collect(),
+        idle_io: opt.priority().io && !opt.no_relay_ioprio,
+    };
+    let mut relays = Vec::new();
+
+    if let Some(stdout) = cmd.stdout.take() {
+        relays.push(relay(tee).spawn(stdout, io::stdout()));
     }
-}
 
-#[cfg(windows)]
-fn nice_process() -> Result<()>{
-    unsafe {
-        use winapi::shared::minwindef::{TRUE};
-        use winapi::um::winbase::IDLE_PRIORITY_CLASS;
-        use winapi::um::processthreadsapi::{GetCurrentProcess, SetPriorityClass};
+    if let Some(stderr) = cmd.stderr.take() {
+        relays.push(relay(tee_stderr).spawn(stderr, io::stderr()));
+    }
+
+    log::set_subject(&program.to_string_lossy(), pid);
+    log::system(log::Level::Info, &format!("started {:?} (pid {})", program, pid));
+
+    match opt.print_pid.as_ref().map(|stream| stream.as_deref()) {
+        Some(Some("stdout")) => println!("{}", pid),
+        Some(_) => eprintln!("{}", pid),
+        None => {}
+    }
+
+    #[cfg(target_os = "macos")]
+    if opt.tie_lifetime {
+        if let Err(error) = tie::tie(pid) {
+            let _ = cmd.kill();
+            let _ = cmd.wait();
+            return Err(error);
+        }
+    }
+
+    #[cfg(windows)]
+    if opt.nices_child() {
+        use std::os::windows::io::AsRawHandle;
+
+        let process = cmd.as_raw_handle() as winapi::um::winnt::HANDLE;
+        let niced = match opt.copied_priority {
+            Some(priority) => priority.apply(process).check(opt.strict),
+            None => priority::nice_child(process, &opt.priority(), opt.strict),
+        };
+
+        if let Err(error) = niced {
+            let _ = cmd.kill();
+            let _ = cmd.wait();
+            return Err(error.context("Unable to lower the priority"));
+        }
+    }
 
-        let h_process = GetCurrentProcess();
-        let status = SetPriorityClass(h_process, IDLE_PRIORITY_CLASS);
+    #[cfg(windows)]
+    if opt.efficiency_cores {
+        use std::os::windows::io::AsRawHandle;
 
-        match status {
-            TRUE => Ok(()),
-            _ => {
-                let error = std::io::Error::last_os_error();
+        let handle = cmd.as_raw_handle() as winapi::um::winnt::HANDLE;
+
+        match cpus::efficiency_cores() {
+            Some(cores) => {
+                verbose!("pinning the program to efficiency cores {:?}", cores);
+                cpus::set_affinity(handle, &cores).context("Unable to pin the program to efficiency cores")?;
+            }
+            None => {
+                warning!("unable to identify efficiency cores, falling back to EcoQoS");
 
-                match error.raw_os_error() {
-                    Some(0) => Ok(()),
-                    _ => Err(anyhow::Error::new(error))
+                if let Err(error) = priority::eco_qos(handle) {
+                    warning!("unable to enable EcoQoS: {}", error);
                 }
             }
         }
     }
-}
 
-#[cfg(all(unix,  target_os= "macos"))]
-fn wakelock(process: &str, pid: u32) {
-    unsafe {
-        use core_foundation::string::{CFStringRef, CFStringCreateWithCString};
-        use core_foundation::date::{CFTimeInterval};
-        use nix::libc::{c_int};
-        use std::ffi::CString;
+    #[cfg(windows)]
+    if let Some(cpus) = &opt.cpus {
+        use std::os::windows::io::AsRawHandle;
+
+        verbose!("pinning the program to CPUs {:?}", cpus);
+        cpus::set_affinity(cmd.as_raw_handle() as winapi::um::winnt::HANDLE, cpus).context("Unable to pin the program to its CPUs")?;
+    }
+
+    #[cfg(windows)]
+    if (opt.job_object() || opt.nices_child()) && !opt.start_suspended {
+        if let Err(error) = suspend::resume(&cmd) {
+            let _ = cmd.kill();
+            let _ = cmd.wait();
+            return Err(error);
+        }
+    }
+
+    #[cfg(all(unix, not(any(target_os = "linux", target_os = "macos"))))]
+    if opt.efficiency_cores {
+        warning!("--efficiency-cores is only supported on Linux, Windows and macOS");
+    }
 
-        let prevent_system_sleep: CString = CString::new("PreventUserIdleSystemSleep").unwrap();
-        let named: CString = CString::new("nicer").unwrap();
-        let detailsd: CString = CString::new(format!("Hi from Rust! We're keeping your Mac awake on behalf of {:?} (pid {})", process, pid)).unwrap();
-        // let localizedd: CString = CString::new("Hello from Rust!").unwrap();
+    #[cfg(all(unix, not(target_os = "linux")))]
+    if opt.cpus.is_some() {
+        warning!("--cpus is only supported on Linux and Windows");
+    }
 
-        #[allow(non_snake_case, unused_variables)]
-        let kIOPMAssertionLevelOn : u32 = 255;
-        #[allow(non_snake_case, unused_variables)]
-        let kIOPMAssertionLevelOff: u32 = 0;
-        #[allow(non_snake_case)]
-        let kCFStringEncodingASCII: u32= 0x0600;
-        #[allow(non_snake_case)]
-        let kIOPMAssertPreventUserIdleSystemSleep: CFStringRef = CFStringCreateWithCString(std::ptr::null(), prevent_system_sleep.as_ptr(), kCFStringEncodingASCII);
-        let name: CFStringRef = CFStringCreateWithCString(std::ptr::null(), named.as_ptr(), kCFStringEncodingASCII);
-        let details: CFStringRef = CFStringCreateWithCString(std::ptr::null(), detailsd.as_ptr(), kCFStringEncodingASCII);
-        // let localized: CFStringRef = CFStringCreateWithCString(std::ptr::null(), localizedd.as_ptr(), kCFStringEncodingASCII);
+    if opt.self_only {
+        background_self(opt)?;
+        verbose!("backgrounded nicer (pid {}) only; {:?} (pid {}) keeps normal priority", process::id(), program, pid);
+    } else if let Some(from) = opt.priority_from_pid {
+        verbose!("gave {:?} (pid {}) the priority of process {}; nicer (pid {}) keeps its own", program, pid, from, process::id());
+    } else if let Some(ramp) = opt.ramp {
+        verbose!("started {:?} (pid {}) at nice {}; nicer (pid {}) keeps normal priority to ramp it down", program, pid, ramp.from, process::id());
+    } else if let Some(nice) = opt.wrapper_nice {
+        verbose!("backgrounded {:?} (pid {}); nicer (pid {}) runs at nice {}", program, pid, process::id(), nice);
+    } else if opt.child_only {
+        verbose!("backgrounded {:?} (pid {}); nicer (pid {}) keeps its own priority", program, pid, process::id());
+    } else if opt.health_check.is_some() {
+        verbose!("backgrounded {:?} (pid {}); nicer (pid {}) keeps normal priority for the health check", program, pid, process::id());
+    } else {
+        verbose!("backgrounded nicer (pid {}) and {:?} (pid {}), which inherited it", process::id(), program, pid);
+    }
 
-        #[link(name = "IOKit", kind = "framework")]
-        extern {
-            #[allow(dead_code)]
-            fn IOPMAssertionCreateWithName(AssertionType: CFStringRef, AssertionLevel: u32, AssertionName: CFStringRef, AssertionID: *mut u32) -> c_int;
+    if opt.detach {
+        if opt.caffeinate || opt.require_caffeinate {
+            if let Err(error) = hold_wakelock(opt, program, pid) {
+                if opt.require_caffeinate {
+                    let _ = cmd.kill();
+                    let _ = cmd.wait();
+                    return Err(error.context("Unable to keep the system awake"));
+                }
 
-            fn IOPMAssertionCreateWithDescription(AssertionType: CFStringRef,  Name: CFStringRef, Details: CFStringRef,  HumanReadableReason: CFStringRef, LocalizationBundlePath: CFStringRef, Timeout: CFTimeInterval, TimeoutAction: CFStringRef, AssertionID: *mut u32) -> c_int;
+                warning!("unable to keep the system awake: {}", error);
+            }
         }
 
-        let mut id : u32 = 0;
-        // IOPMAssertionCreateWithName(kIOPMAssertPreventUserIdleSystemSleep, kIOPMAssertionLevelOn, name, &mut id);
-        // HumanReadableReason is ignored if non localizable
-        IOPMAssertionCreateWithDescription(kIOPMAssertPreventUserIdleSystemSleep, name, details, std::ptr::null(), std::ptr::null(), 0.0, std::ptr::null(), &mut id);
+        verbose!("detached from {:?} (pid {}), which keeps running", program, pid);
+        return Ok(None);
     }
-}
 
-#[cfg(windows)]
-fn wakelock(_process: &str, _pid: u32) {
-    unsafe {
-        use winapi::um::winbase::{SetThreadExecutionState};
-        use winapi::um::winnt::{ES_CONTINUOUS, ES_SYSTEM_REQUIRED};
+    let _wakelock = if (opt.caffeinate || opt.require_caffeinate) && opt.caffeinate_when_busy.is_none() {
+        match wakelock::wakelock(&program.to_string_lossy(), pid, &opt.caffeinate_type) {
+            Ok(wakelock) => Some(wakelock),
+            Err(error) if opt.require_caffeinate => {
+                let _ = cmd.kill();
+                let _ = cmd.wait();
+                return Err(error.context("Unable to keep the system awake"));
+            }
+            Err(error) => {
+                warning!("unable to keep the system awake: {}", error);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    #[cfg(unix)] {
+        use nix::sys::signal::Signal;
+
+        // With restarts, SIGUSR1 is ours; with --watch, so are SIGINT and
+        // SIGTERM, which stop the program below; and so is --resume-on-signal.
+        let forwarded: Vec<Signal> = opt
+            .forwarded_signals()
+            .iter()
+            .copied()
+            .filter(|signal| !opt.restart_on_failure || *signal != Signal::SIGUSR1)
+            .filter(|signal| opt.watch.is_empty() || (*signal != Signal::SIGINT && *signal != Signal::SIGTERM))
+            .filter(|signal| opt.resume_on_signal != Some(*signal))
+            .collect();
+
+        signals::forward_job_control(pid, opt.own_group)?;
+        signals::forward_signals(pid, opt.own_group, &forwarded)?;
+        control::started(pid);
+    }
+
+    #[cfg(windows)]
+    console::forward_ctrl(pid, opt.own_group)?;
+
+    if opt.start_suspended {
+        #[cfg(unix)]
+        control::suspended(true);
+
+        let resumed = suspend::wait(opt.resume_on_port.as_deref(), &mut cmd).and_then(|why| {
+            verbose!("resuming {:?} (pid {}): {}", program, pid, why);
+            suspend::resume(&cmd)
+        });
+
+        #[cfg(unix)]
+        control::suspended(false);
+
+        if let Err(error) = resumed {
+            let _ = cmd.kill();
+            let _ = cmd.wait();
+            return Err(error.context("The program was never resumed"));
+        }
+    }
+
+    if let Some(address) = &opt.wait_for_port {
+        if let Err(error) = ready::wait_for_port(address, opt.ready_timeout.unwrap_or(READY_TIMEOUT), &mut cmd) {
+            let _ = cmd.kill();
+            let _ = cmd.wait();
+            return Err(error.context("The program never became ready"));
+        }
+
+        match &opt.on_ready {
+            Some(hook) => hooks::run("on-ready", hook, pid)?,
+            None => eprintln!("nicer: {:?} is accepting connections on {}", program, address),
+        }
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || sender.send(cmd.wait()));
+
+    #[cfg(target_os = "linux")]
+    let mut memory_events = cgroup::MemoryEvents::new();
+    #[cfg(target_os = "linux")]
+    let mut backgrounded = std::collections::HashSet::new();
+    // Whether --freeze-above-load has the program frozen.
+    #[cfg(target_os = "linux")]
+    let mut load_frozen = false;
+
+    let mut health = opt.health_check.as_deref().map(|line| health::HealthCheck::new(line, opt.health_interval.unwrap_or(HEALTH_INTERVAL), opt.unhealthy_threshold.unwrap_or(UNHEALTHY_THRESHOLD)));
+    let mut kill = None;
+    #[cfg(windows)]
+    let mut cpu_times = None;
+    // The program's niceness while --ramp still has steps to take.
+    let mut ramped = opt.ramp.map(|ramp| ramp.from);
+    // When we asked the program to stop, for --watch.
+    let mut stopped = None;
+    let mut politeness = stats::Politeness::default();
+    // Whether the program was above its niceness when we last looked.
+    #[cfg(unix)]
+    let mut escaped = false;
+    #[cfg(unix)]
+    let watch_nice = opt.stats && opt.priority().cpu && !opt.self_only && opt.copied_priority.is_none();
+
+    if opt.stats && ((cfg!(target_os = "linux") && (opt.thread_nice || opt.inherit_priority_to_descendants)) || (cfg!(any(target_os = "linux", target_os = "macos", windows)) && opt.renice_descendants)) {
+        politeness.reasserted = Some(0);
+    }
+
+    // Descendants --renice-descendants couldn't renice, so it stops trying.
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    let mut unrenicable = std::collections::HashSet::new();
+
+    #[cfg(any(windows, target_os = "macos"))]
+    let mut busy_wakelock = opt.caffeinate_when_busy.map(|threshold| wakelock::BusyWakeLock::new(&program.to_string_lossy(), pid, threshold, &opt.caffeinate_type));
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if opt.caffeinate_when_busy.is_some() {
+        warning!("--caffeinate-when-busy is only supported on Windows and macOS");
+    }
+
+    // When --deadline or --timeout stops the program, whichever comes first,
+    // and when it was asked to stop.
+    let timeout = opt.timeout.map(|timeout| started + timeout);
+    let watchdog = opt.watchdog.into_iter().chain(timeout).min();
+    let grace = opt.kill_after.unwrap_or(KILL_AFTER);
+    let mut stopping: Option<Instant> = None;
+
+    let status = loop {
+        // Wake up in time for the deadline or the kill after it.
+        let wake = match (kill, stopping) {
+            (None, _) => watchdog,
+            (_, Some(at)) => Some(at + grace),
+            _ => None,
+        };
+        let tick = match wake {
+            Some(at) => at.saturating_duration_since(Instant::now()).min(TICK),
+            None => TICK,
+        };
+
+        match receiver.recv_timeout(tick) {
+            Ok(status) => break status.context("Unable to wait for the program")?,
+            Err(RecvTimeoutError::Timeout) => {
+                if kill.is_none() && watchdog.is_some_and(|at| Instant::now() >= at) {
+                    let why = if watchdog == timeout { Kill::Timeout } else { Kill::Deadline };
+
+                    match why {
+                        Kill::Timeout => warning!("{:?} (pid {}) timed out after {:?}, stopping it", program, pid, started.elapsed()),
+                        _ => warning!("{:?} (pid {}) reached its deadline, stopping it", program, pid),
+                    }
+
+                    #[cfg(windows)]
+                    match opt.own_group {
+                        true => interrupt(pid),
+                        false => terminate(pid, false),
+                    }
+                    #[cfg(unix)]
+                    terminate(pid, opt.own_group);
+
+                    stopping = Some(Instant::now());
+                    kill = Some(why);
+                }
+
+                if stopping.is_some_and(|at| at.elapsed() >= grace) {
+                    warning!("{:?} (pid {}) didn't stop within {:?}, killing it", program, pid, grace);
+                    self::kill(pid, opt.own_group);
+                    stopping = None;
+                }
+
+                if let Some(watch) = &mut watch {
+                    #[cfg(unix)]
+                    let draining = signals::draining();
+                    #[cfg(windows)]
+                    let draining = false;
+
+                    match stopped {
+                        None if draining || watch.changed() => {
+                            verbose!("stopping {:?} (pid {})", program, pid);
+                            terminate(pid, opt.own_group);
+                            stopped = Some(Instant::now());
+                            kill = Some(Kill::Changed).filter(|_| !draining);
+                        }
+                        Some(at) if at.elapsed() >= WATCH_GRACE => {
+                            warning!("{:?} (pid {}) didn't stop within {:?}, killing it", program, pid, WATCH_GRACE);
+                            self::kill(pid, opt.own_group);
+                            stopped = Some(Instant::now());
+                        }
+                        _ => {}
+                    }
+                }
+
+                if let Some(health) = &mut health {
+                    if kill.is_none() && health.unhealthy(pid) {
+                        warning!("{:?} (pid {}) is unhealthy, killing it", program, pid);
+                        self::kill(pid, opt.own_group);
+                        kill = Some(Kill::Unhealthy);
+                    }
+                }
+
+                if let (Some(ramp), Some(current)) = (opt.ramp, ramped) {
+                    let nice = ramp.at(started.elapsed());
+
+                    if nice != current {
+                        let config = priority::PriorityConfig { cpu: true, io: false, nice, ..Default::default() };
+
+                        ramped = match priority::renice(pid, &config).and_then(|applied| applied.check(false)) {
+                            Ok(()) => {
+                                verbose!("ramped {:?} (pid {}) down to nice {}", program, pid, nice);
+                                Some(nice).filter(|nice| *nice != ramp.to)
+                            }
+                            Err(error) => {
+                                warning!("unable to ramp {:?} (pid {}) down, leaving it at nice {}: {:#}", program, pid, current, error);
+                                None
+                            }
+                        };
+                    }
+                }
+
+                #[cfg(any(windows, target_os = "macos"))]
+                let times = stats::cpu_times(pid);
+
+                #[cfg(any(windows, target_os = "macos"))]
+                if let (Some(busy_wakelock), Some((user, system))) = (&mut busy_wakelock, times) {
+                    busy_wakelock.sample(user + system);
+                }
+
+                #[cfg(windows)]
+                if let Some(times) = times {
+                    cpu_times = Some(times);
+
+                    if kill.is_none() && opt.max_cpu_time.is_some_and(|limit| times.0 + times.1 >= limit) {
+                        warning!("{:?} (pid {}) used up its CPU time, killing it", program, pid);
+                        self::kill(pid, opt.own_group);
+                        kill = Some(Kill::CpuTime);
+                    }
+                }
+
+                #[cfg(target_os = "linux")]
+                if let Some(cgroup) = cgroup {
+                    memory_events.check(cgroup);
+                }
+
+                #[cfg(target_os = "linux")]
+                if let (Some(threshold), Some(cgroup), Ok(load)) = (opt.freeze_above_load, cgroup, load::load_average()) {
+                    if !load_frozen && kill.is_none() && stopped.is_none() && load > threshold {
+                        verbose!("the load average is {:.2}, freezing {:?} (pid {})", load, program, pid);
+                        load_frozen = cgroup.freeze(true).map_err(|error| warning!("unable to freeze {:?}: {:#}", program, error)).is_ok();
+                    } else if load_frozen && load < threshold {
+                        verbose!("the load average is down to {:.2}, thawing {:?} (pid {})", load, program, pid);
+                        load_frozen = cgroup.freeze(false).map_err(|error| warning!("unable to thaw {:?}: {:#}", program, error)).is_err();
+                    }
+                }
+
+                // A frozen program can't act on being asked to stop.
+                #[cfg(target_os = "linux")]
+                if let Some(cgroup) = cgroup {
+                    if (kill.is_some() || stopped.is_some()) && cgroup.frozen() {
+                        verbose!("thawing {:?} (pid {}) so it can stop", program, pid);
+                        load_frozen = false;
+                        let _ = cgroup.freeze(false);
+                    }
+                }
+
+                // Ramps only ever lower it further.
+                #[cfg(unix)]
+                if let (true, Ok(nice)) = (watch_nice, priority::niceness(pid)) {
+                    let escapes = politeness.escapes.get_or_insert(0);
+                    let above = nice < opt.priority().nice;
+
+                    if above && !escaped {
+                        *escapes += 1;
+                    }
+
+                    escaped = above;
+                }
+
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                if let (true, Ok(idle)) = (opt.stats, priority::idle_io(pid)) {
+                    politeness.io_samples += 1;
+                    politeness.idle_io += u32::from(idle);
+                }
+
+                #[cfg(target_os = "linux")]
+                if opt.inherit_priority_to_descendants {
+                    // The health check is ours, not the program's.
+                    let check: Vec<u32> = health.as_ref().and_then(|health| health.pid()).into_iter().collect();
+                    let ours: Vec<u32> = std::iter::once(pid).chain(check.iter().copied()).collect();
+
+                    tree::reap_orphans(&ours);
+
+                    for descendant in tree::descendants_except(process::id(), &check) {
+                        if backgrounded.insert(descendant) {
+                            match priority::renice(descendant, &opt.priority()).and_then(|applied| applied.check(false)) {
+                                Ok(()) => {
+                                    verbose!("backgrounded descendant {}", descendant);
+
+                                    if let Some(reasserted) = &mut politeness.reasserted {
+                                        *reasserted += 1;
+                                    }
+                                }
+                                Err(error) => verbose!("unable to background descendant {}: {}", descendant, error),
+                            }
+                        }
+                    }
+                }
+
+                #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+                if opt.renice_descendants {
+                    let mut config = opt.priority();
+                    config.nice = opt.ramp.map_or(config.nice, |ramp| ramp.at(started.elapsed()));
+
+                    for process in std::iter::once(pid).chain(tree::descendants(pid)) {
+                        if unrenicable.contains(&process) || !priority::slipped(process, &config) {
+                            continue;
+                        }
 
-        SetThreadExecutionState(ES_SYSTEM_REQUIRED | ES_CONTINUOUS);
+                        match priority::renice(process, &config).and_then(|applied| applied.check(false)) {
+                            Ok(()) => {
+                                verbose!("lowered the priority of {} again", process);
+
+                                if let Some(reasserted) = &mut politeness.reasserted {
+                                    *reasserted += 1;
+                                }
+                            }
+                            Err(error) => {
+                                verbose!("unable to lower the priority of {} again: {}", process, error);
+                                unrenicable.insert(process);
+                            }
+                        }
+                    }
+                }
+
+                #[cfg(target_os = "linux")]
+                if opt.thread_nice && opt.priority().cpu {
+                    let nice = opt.ramp.map_or(opt.priority().nice, |ramp| ramp.at(started.elapsed()));
+
+                    for process in std::iter::once(pid).chain(backgrounded.iter().copied()) {
+                        match priority::nice_threads(process, nice) {
+                            0 => {}
+                            changed => {
+                                verbose!("reniced {} threads of {}", changed, process);
+
+                                if let Some(reasserted) = &mut politeness.reasserted {
+                                    *reasserted += changed as u32;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("Lost track of the program"),
+        }
+    };
+
+    // Whatever the program left in the pipes, unless something it started
+    // in the background holds them open.
+    let flushed = Instant::now();
+
+    while relays.iter().any(|relay| !relay.is_finished()) && flushed.elapsed() < TICK {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    log::system(log::Level::Info, &format!("{:?} (pid {}) ended with {}", program, pid, status));
+
+    // RLIMIT_CPU sends SIGXCPU, and SIGKILL to a program that ignores it.
+    #[cfg(unix)]
+    if let Some(limit) = opt.max_cpu_time {
+        use std::os::unix::process::ExitStatusExt;
+
+        let signal = status.signal();
+
+        if signal == Some(nix::libc::SIGXCPU) || (signal == Some(nix::libc::SIGKILL) && stats::cpu_time().is_some_and(|used| used >= limit)) {
+            kill = Some(Kill::CpuTime);
+        }
+    }
+
+    // The job object kills what runs over its user time this way.
+    #[cfg(windows)]
+    if opt.max_cpu_time.is_some() && status.code() == Some(winapi::shared::winerror::ERROR_NOT_ENOUGH_QUOTA as i32) {
+        kill = Some(Kill::CpuTime);
+    }
+
+    if opt.stats {
+        #[cfg(target_os = "linux")]
+        let peak_memory = cgroup.and_then(|cgroup| cgroup.read("memory.peak").ok()).and_then(|peak| peak.trim().parse().ok());
+        #[cfg(not(target_os = "linux"))]
+        let peak_memory = None;
+
+        let mut stats = stats::Stats::new(program.to_string_lossy().into_owned(), pid, started.elapsed(), status, peak_memory);
+        stats.cpu_limit = opt.max_cpu_time;
+        stats.politeness = politeness;
+
+        #[cfg(windows)]
+        if let Some((user, system)) = cpu_times {
+            stats.user = Some(user);
+            stats.system = Some(system);
+        }
+
+        match opt.json {
+            true => eprintln!("{}", stats.to_json()),
+            false => eprintln!("{}", stats),
+        }
     }
+
+    Ok(Some((status, kill)))
 }
 
-#[cfg(all(unix, not(target_os = "macos")))]
-fn wakelock(_process: &str, _pid: u32) {
-    eprintln!("Linux has no caffeine, sadly.");
+/// Leaves a holder behind that keeps the system awake until `pid` exits,
+/// for --caffeinate with --detach, once it has taken the wakelock.
+fn hold_wakelock(opt: &Opt, program: &Path, pid: u32) -> Result<()> {
+    use std::io::BufRead;
+
+    let types: Vec<&str> = opt.caffeinate_type.iter().map(|assertion| assertion.name()).collect();
+    let mut holder = Command::new(env::current_exe().context("Unable to find nicer itself")?)
+        .arg("__hold-wakelock")
+        .arg(format!("--types={}", types.join(",")))
+        .arg("--")
+        .arg(pid.to_string())
+        .arg(program.to_string_lossy().as_ref())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Unable to start the wakelock holder")?;
+
+    let mut answer = String::new();
+    let _ = io::BufReader::new(holder.stdout.take().expect("piped stdout")).read_line(&mut answer);
+
+    match answer.trim_end() {
+        "held" => {
+            verbose!("keeping the system awake from a holder (pid {})", holder.id());
+            Ok(())
+        }
+        answer => {
+            let _ = holder.wait();
+            Err(anyhow::anyhow!("{}", answer.strip_prefix("error: ").unwrap_or("The wakelock holder gave up")))
+        }
+    }
 }
 
-#[derive(StructOpt, Debug)]
-#[structopt(about = "Automagically call your tools with background priority")]
-struct Opt {
-    /// Keep the system awake (supported on Windows and macOS).
-    #[structopt(short, long)]
-    caffeinate: bool,
+/// The settings a run would use and where each came from, for
+/// --print-config. This mirrors the layering of `Opt::priority` and
+/// `Opt::with_job`.
+fn settings(opt: &Opt, job: Option<&job::Job>) -> Result<config::Settings> {
+    use config::Source::{Default, Flag, JobFile};
+
+    let mut settings = config::Settings::default();
+    let priority = job.and_then(|job| job.priority);
+    let flag = |flags: &[(bool, &'static str)]| flags.iter().find(|(set, _)| *set).map_or(Default, |(_, name)| Flag(name));
+
+    match (job, opt.command.first()) {
+        (Some(job), _) => settings.add("program", job.program.display(), JobFile),
+        (None, Some(program)) => settings.add("program", program.to_string_lossy(), Flag("command line")),
+        (None, None) => settings.add("program", "(none)", Default),
+    }
+
+    let cpu = [(opt.no_nice, "--no-nice"), (opt.only_io, "--only-io"), (cfg!(target_os = "macos") && opt.macos_io_throttle, "--macos-io-throttle")];
+    let io = [(opt.no_ioprio, "--no-ioprio"), (opt.only_cpu, "--only-cpu")];
+
+    match priority {
+        Some(priority) => {
+            settings.add("cpu", priority.cpu, JobFile);
+            settings.add("io", priority.io, JobFile);
+            settings.add("lowest", priority.lowest, JobFile);
+        }
+        None => {
+            settings.add("cpu", opt.priority().cpu, flag(&cpu));
+            settings.add("io", opt.priority().io, flag(&io));
+            settings.add("lowest", opt.lowest, flag(&[(opt.lowest, "--lowest")]));
+        }
+    }
+
+    match (opt.ioprio_from_nice, opt.io_class) {
+        (true, _) => settings.add("io class", "none", Flag("--ioprio-from-nice")),
+        (false, Some(_)) => settings.add("io class", opt.priority().io_class, Flag("--io-class")),
+        (false, None) => settings.add("io class", "idle", Default),
+    }
+
+    match (opt.sched, opt.lowest) {
+        (Some(sched), _) => settings.add("sched", sched.name(), Flag("--sched")),
+        (None, true) => settings.add("sched", "idle", Flag("--lowest")),
+        (None, false) => settings.add("sched", "unchanged", Default),
+    }
+
+    match (opt.oom_score_adj, opt.lowest) {
+        (Some(adj), _) => settings.add("oom score adj", adj, Flag("--oom-score-adj")),
+        (None, true) => settings.add("oom score adj", 1000, Flag("--lowest")),
+        (None, false) => settings.add("oom score adj", "unchanged", Default),
+    }
+
+    settings.add("tier", opt.tier.name(), flag(&[(opt.tier != priority::Tier::Background, "--tier")]));
+    settings.add("eco", opt.eco || opt.lowest, flag(&[(opt.eco, "--eco"), (opt.lowest, "--lowest")]));
+    settings.add("memory", opt.background_mode, flag(&[(opt.background_mode, "--background-mode")]));
+    settings.add("timers", !opt.no_timer_throttle, flag(&[(opt.no_timer_throttle, "--no-timer-throttle")]));
+
+    match (opt.renice_by, opt.ramp) {
+        _ if opt.nice.is_some() => settings.add("nice", opt.priority().nice, Flag("--nice")),
+        _ if opt.priority_class.is_some() => settings.add("nice", opt.priority().nice, Flag("--priority")),
+        (Some(delta), _) => {
+            let current = priority::current_nice().context("Unable to read nicer's niceness")?;
+            settings.add("nice", (current + delta).clamp(-20, 19), Flag("--renice-by"));
+        }
+        (None, Some(ramp)) => settings.add("nice", format!("{} to {}", ramp.from, ramp.to), Flag("--ramp")),
+        (None, None) => settings.add("nice", 19, Default),
+    }
+
+    match opt.wrapper_nice {
+        Some(nice) => settings.add("wrapper nice", nice, Flag("--wrapper-nice")),
+        None if opt.child_only => settings.add("wrapper nice", "its own", Flag("--child-only")),
+        None => settings.add("wrapper nice", "the program's", Default),
+    }
+
+    settings.add("strict", opt.strict, flag(&[(opt.strict, "--strict")]));
+
+    let caffeinate = [(opt.caffeinate, "--caffeinate"), (opt.require_caffeinate, "--require-caffeinate"), (opt.caffeinate_when_busy.is_some(), "--caffeinate-when-busy")];
+
+    match job.and_then(|job| job.caffeinate) {
+        Some(caffeinate) => settings.add("caffeinate", caffeinate, JobFile),
+        None => settings.add("caffeinate", caffeinate.iter().any(|(set, _)| *set), flag(&caffeinate)),
+    }
+
+    let types: Vec<&str> = opt.caffeinate_type.iter().map(|assertion| assertion.name()).collect();
+    settings.add("caffeinate type", types.join(","), flag(&[(opt.caffeinate_type != [wakelock::Assertion::System], "--caffeinate-type")]));
 
-    /// Name or path to the program I'll background to.
-    #[structopt(parse(from_os_str))]
-    program: PathBuf,
+    match (job.and_then(|job| job.cwd.as_ref()), &opt.cwd) {
+        (Some(cwd), _) => settings.add("cwd", cwd.display(), JobFile),
+        (None, Some(cwd)) => settings.add("cwd", cwd.display(), Flag("--cwd")),
+        (None, None) => settings.add("cwd", "(nicer's)", Default),
+    }
+
+    match job {
+        Some(job) => settings.add("env", format!("{} variables", job.env.len()), JobFile),
+        None => settings.add("env", "(nicer's)", Default),
+    }
 
-    /// Arguments to the program.
-    #[structopt(parse(from_os_str))]
-    args: Vec<PathBuf>,
+    Ok(settings)
 }
 
-fn main() -> Result<()> {
-    let opt = Opt::from_args();
-    nice_process()?;
+/// Runs the commands of a --batch file, up to --concurrency at a time and
+/// starting the next as each one exits, then sums up how they went.
+fn batch(opt: &Opt, path: &Path) -> Result<i32> {
+    let commands = batch::load(path)?;
+    let concurrency = opt.concurrency.unwrap_or(1).max(1);
 
-    let program = opt.program.clone();
+    background_self(opt)?;
 
-    let cmd = Command::new(opt.program).args(opt.args).spawn().context("Unable to spawn program")?;
+    if let Some(delay) = opt.delay {
+        verbose!("waiting {:?} before starting the batch", delay);
+        thread::sleep(delay);
+    }
 
-    if opt.caffeinate {
-        wakelock(&program.to_string_lossy(), cmd.id());
+    if let Some(threshold) = opt.wait_for_load {
+        load::wait_below(threshold, opt.load_wait_timeout).context("Not starting the batch")?;
     }
 
-    let arc = Arc::new(Mutex::new(cmd));
+    let _wakelock = match opt.caffeinate || opt.require_caffeinate {
+        true => match wakelock::wakelock(&format!("a batch of {} commands", commands.len()), process::id(), &opt.caffeinate_type) {
+            Ok(wakelock) => Some(wakelock),
+            Err(error) if opt.require_caffeinate => return Err(error.context("Unable to keep the system awake")),
+            Err(error) => {
+                warning!("unable to keep the system awake: {}", error);
+                None
+            }
+        },
+        false => None,
+    };
 
     #[cfg(unix)] {
-        use nix::unistd::Pid;
-        use nix::sys::signal::{kill, Signal};
+        use nix::sys::signal::Signal;
 
-        let arc_handler = arc.clone();
-        ctrlc::set_handler(move || {
-            let pid = Pid::from_raw(arc_handler.lock().unwrap().id() as i32);
-            kill(pid, Signal::SIGINT).context("Unable to kill the program").unwrap();
-        }).context("Unable to set the signal handler")?;
+        signals::drain_on(Signal::SIGINT)?;
+        signals::drain_on(Signal::SIGTERM)?;
     }
 
-    let status = arc.lock().unwrap().wait().context("Unable to wait for the program")?;
+    let mut summary = batch::Summary::new(&commands);
+    let mut queue = commands.iter().enumerate();
+    let mut running = BTreeMap::new();
+    #[cfg_attr(windows, allow(unused_mut))]
+    let mut stopping = false;
+    let (sender, receiver) = mpsc::channel();
 
-    match status.code() {
-        Some(i) => process::exit(i),
-        None => {
-            #[cfg(unix)] {
-                use std::os::unix::process::ExitStatusExt;
-                process::exit(status.signal().unwrap_or_else(|| 9) + 128);
+    loop {
+        while running.len() < concurrency && !stopping {
+            let (index, line) = match queue.next() {
+                Some(next) => next,
+                None => break,
+            };
+
+            let mut command = batch::command(line);
+            command.envs(&opt.env).stdin(Stdio::null());
+
+            if let Some(cwd) = &opt.cwd {
+                command.current_dir(cwd);
             }
 
-            #[cfg(windows)]
-            process::exit(127);
+            if opt.quiet_child {
+                command.stdout(Stdio::null()).stderr(Stdio::null());
+            }
+
+            match command.spawn() {
+                Ok(mut child) => {
+                    verbose!("started {:?} (pid {})", line, child.id());
+                    running.insert(index, child.id());
+
+                    let sender = sender.clone();
+                    thread::spawn(move || sender.send((index, child.wait())));
+                }
+                Err(error) => {
+                    warning!("unable to start {:?}: {}", line, error);
+                    summary.record(index, batch::Outcome::Failed(error.to_string()));
+                }
+            }
         }
-    };
+
+        if running.is_empty() {
+            break;
+        }
+
+        if let Ok((index, status)) = receiver.recv_timeout(TICK) {
+            running.remove(&index);
+
+            let outcome = match status {
+                Ok(status) => {
+                    verbose!("{:?} ended with {}", commands[index], status);
+                    batch::Outcome::Exited(exit_code(status))
+                }
+                Err(error) => batch::Outcome::Failed(error.to_string()),
+            };
+
+            summary.record(index, outcome);
+        }
+
+        #[cfg(unix)]
+        if signals::draining() && !stopping {
+            warning!("stopping the batch and the {} commands still running", running.len());
+            stopping = true;
+            running.values().for_each(|pid| terminate(*pid, false));
+        }
+    }
+
+    eprintln!("{}", summary);
+    Ok(if summary.succeeded() { 0 } else { 1 })
 }
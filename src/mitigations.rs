@@ -0,0 +1,298 @@
+use anyhow::{anyhow, Result};
+
+/// A process mitigation policy the program can be started under (Windows).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Mitigation {
+    /// Data execution prevention (Windows 7).
+    Dep,
+    /// Rebase every image, even those not built for ASLR (Windows 8).
+    Aslr,
+    /// No generating or modifying code at run time (Windows 8.1).
+    NoDynamicCode,
+    /// No starting processes of its own (Windows 10 1607).
+    NoChildProcesses,
+}
+
+/// The names --win-mitigations takes.
+pub const NAMES: &[&str] = &["dep", "aslr", "no-dynamic-code", "no-child-processes"];
+
+impl Mitigation {
+    #[cfg_attr(unix, allow(dead_code))]
+    pub fn name(self) -> &'static str {
+        match self {
+            Mitigation::Dep => "dep",
+            Mitigation::Aslr => "aslr",
+            Mitigation::NoDynamicCode => "no-dynamic-code",
+            Mitigation::NoChildProcesses => "no-child-processes",
+        }
+    }
+}
+
+impl std::str::FromStr for Mitigation {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Mitigation> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "dep" => Ok(Mitigation::Dep),
+            "aslr" => Ok(Mitigation::Aslr),
+            "no-dynamic-code" => Ok(Mitigation::NoDynamicCode),
+            "no-child-processes" => Ok(Mitigation::NoChildProcesses),
+            _ => Err(anyhow!("Unknown mitigation {:?}, expected one of {}", text, NAMES.join(", "))),
+        }
+    }
+}
+
+/// Quotes `arg` for a Windows command line the way the C runtime splits it
+/// back up.
+#[cfg(windows)]
+fn quote(arg: &std::ffi::OsStr, line: &mut Vec<u16>) {
+    use std::os::windows::ffi::OsStrExt;
+
+    let arg: Vec<u16> = arg.encode_wide().collect();
+    let needs_quotes = arg.is_empty() || arg.iter().any(|c| *c == b' ' as u16 || *c == b'\t' as u16 || *c == b'"' as u16);
+
+    if !needs_quotes {
+        line.extend(arg);
+        return;
+    }
+
+    line.push(b'"' as u16);
+    let mut backslashes = 0;
+
+    for c in arg {
+        if c == b'\\' as u16 {
+            backslashes += 1;
+        } else {
+            // Backslashes only escape when a quote follows them.
+            if c == b'"' as u16 {
+                line.extend(std::iter::repeat_n(b'\\' as u16, backslashes + 1));
+            }
+
+            backslashes = 0;
+        }
+
+        line.push(c);
+    }
+
+    line.extend(std::iter::repeat_n(b'\\' as u16, backslashes));
+    line.push(b'"' as u16);
+}
+
+/// A copy of our own token at low integrity, for the program to run with.
+#[cfg(windows)]
+fn low_integrity_token() -> Result<winapi::um::winnt::HANDLE> {
+    use std::io;
+    use std::mem;
+    use std::ptr;
+    use winapi::shared::minwindef::{DWORD, FALSE};
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+    use winapi::um::securitybaseapi::{AllocateAndInitializeSid, DuplicateTokenEx, FreeSid, GetLengthSid, SetTokenInformation};
+    use winapi::um::winnt::{
+        SecurityImpersonation, TokenIntegrityLevel, TokenPrimary, SECURITY_MANDATORY_LABEL_AUTHORITY, SECURITY_MANDATORY_LOW_RID, SE_GROUP_INTEGRITY, SID_IDENTIFIER_AUTHORITY, TOKEN_ADJUST_DEFAULT, TOKEN_ASSIGN_PRIMARY, TOKEN_DUPLICATE,
+        TOKEN_MANDATORY_LABEL, TOKEN_QUERY,
+    };
+
+    unsafe {
+        let mut token = ptr::null_mut();
+
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_DUPLICATE | TOKEN_QUERY, &mut token) == FALSE {
+            return Err(anyhow::Error::new(io::Error::last_os_error()).context("Unable to open nicer's own token"));
+        }
+
+        let mut low = ptr::null_mut();
+        let duplicated = DuplicateTokenEx(token, TOKEN_ADJUST_DEFAULT | TOKEN_ASSIGN_PRIMARY | TOKEN_DUPLICATE | TOKEN_QUERY, ptr::null_mut(), SecurityImpersonation, TokenPrimary, &mut low);
+        CloseHandle(token);
+
+        if duplicated == FALSE {
+            return Err(anyhow::Error::new(io::Error::last_os_error()).context("Unable to copy nicer's own token"));
+        }
+
+        let mut authority = SID_IDENTIFIER_AUTHORITY { Value: SECURITY_MANDATORY_LABEL_AUTHORITY };
+        let mut sid = ptr::null_mut();
+
+        if AllocateAndInitializeSid(&mut authority, 1, SECURITY_MANDATORY_LOW_RID, 0, 0, 0, 0, 0, 0, 0, &mut sid) == FALSE {
+            CloseHandle(low);
+            return Err(anyhow::Error::new(io::Error::last_os_error()).context("Unable to make the low integrity SID"));
+        }
+
+        let mut label: TOKEN_MANDATORY_LABEL = mem::zeroed();
+        label.Label.Sid = sid;
+        label.Label.Attributes = SE_GROUP_INTEGRITY;
+
+        let size = mem::size_of::<TOKEN_MANDATORY_LABEL>() as DWORD + GetLengthSid(sid);
+        let lowered = SetTokenInformation(low, TokenIntegrityLevel, &mut label as *mut _ as _, size);
+        let error = io::Error::last_os_error();
+        FreeSid(sid);
+
+        if lowered == FALSE {
+            CloseHandle(low);
+            return Err(anyhow::Error::new(error).context("Unable to lower the integrity level, which this token may not allow"));
+        }
+
+        Ok(low)
+    }
+}
+
+/// Starts `command` under `mitigations`, at low integrity if asked to, waits
+/// for it and returns its exit code.
+///
+/// std can't hand CreateProcess the attributes these need, so nicer runs this
+/// as a hidden subcommand in between. The program goes into a job object
+/// that dies with us, so killing the go-between kills the program too.
+/// Policies this Windows doesn't know are dropped, newest first, with a
+/// warning.
+#[cfg(windows)]
+pub fn run(mut mitigations: Vec<Mitigation>, low_integrity: bool, command: &[std::ffi::OsString]) -> Result<i32> {
+    use std::io;
+    use std::mem;
+    use std::ptr;
+    use winapi::shared::basetsd::SIZE_T;
+    use winapi::shared::minwindef::{DWORD, FALSE, TRUE};
+    use winapi::shared::winerror::{ERROR_INVALID_PARAMETER, ERROR_NOT_SUPPORTED};
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::jobapi2::{AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject};
+    use winapi::um::processenv::GetStdHandle;
+    use winapi::um::processthreadsapi::{CreateProcessAsUserW, CreateProcessW, DeleteProcThreadAttributeList, GetExitCodeProcess, InitializeProcThreadAttributeList, ResumeThread, UpdateProcThreadAttribute, PROCESS_INFORMATION};
+    use winapi::um::synchapi::WaitForSingleObject;
+    use winapi::um::winbase::{CREATE_SUSPENDED, EXTENDED_STARTUPINFO_PRESENT, INFINITE, STARTF_USESTDHANDLES, STARTUPINFOEXW, STD_ERROR_HANDLE, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE};
+    use winapi::um::winnt::{JobObjectExtendedLimitInformation, JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE};
+
+    // Not in winapi yet.
+    const PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY: usize = 0x0002_0007;
+    const PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY: usize = 0x0002_000E;
+    const DEP_ENABLE: u64 = 0x01;
+    const FORCE_RELOCATE_IMAGES_ALWAYS_ON: u64 = 0x01 << 8;
+    const PROHIBIT_DYNAMIC_CODE_ALWAYS_ON: u64 = 0x01 << 36;
+    const CHILD_PROCESS_RESTRICTED: DWORD = 0x01;
+
+    let mut line = Vec::new();
+
+    for (i, arg) in command.iter().enumerate() {
+        if i > 0 {
+            line.push(b' ' as u16);
+        }
+
+        quote(arg, &mut line);
+    }
+
+    line.push(0);
+    mitigations.sort();
+    mitigations.dedup();
+
+    let token = match low_integrity {
+        true => Some(low_integrity_token()?),
+        false => None,
+    };
+
+    unsafe {
+        let job = CreateJobObjectW(ptr::null_mut(), ptr::null());
+
+        if job.is_null() {
+            return Err(anyhow::Error::new(io::Error::last_os_error()).context("Unable to create a job object"));
+        }
+
+        let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = mem::zeroed();
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        SetInformationJobObject(job, JobObjectExtendedLimitInformation, &mut limits as *mut _ as _, mem::size_of_val(&limits) as DWORD);
+
+        let information = loop {
+            let process_policy = mitigations.iter().fold(0u64, |policy, mitigation| {
+                policy | match mitigation {
+                    Mitigation::Dep => DEP_ENABLE,
+                    Mitigation::Aslr => FORCE_RELOCATE_IMAGES_ALWAYS_ON,
+                    Mitigation::NoDynamicCode => PROHIBIT_DYNAMIC_CODE_ALWAYS_ON,
+                    Mitigation::NoChildProcesses => 0,
+                }
+            });
+            let mut child_policy = CHILD_PROCESS_RESTRICTED;
+            let restrict_children = mitigations.contains(&Mitigation::NoChildProcesses);
+
+            let mut size: SIZE_T = 0;
+            InitializeProcThreadAttributeList(ptr::null_mut(), 2, 0, &mut size);
+            // u64s keep the list aligned.
+            let mut buffer = vec![0u64; size.div_ceil(8)];
+            let list = buffer.as_mut_ptr() as _;
+
+            if InitializeProcThreadAttributeList(list, 2, 0, &mut size) == FALSE {
+                return Err(anyhow::Error::new(io::Error::last_os_error()).context("Unable to set up the mitigation policies"));
+            }
+
+            if process_policy != 0 {
+                UpdateProcThreadAttribute(list, 0, PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY, &process_policy as *const _ as _, mem::size_of::<u64>(), ptr::null_mut(), ptr::null_mut());
+            }
+
+            if restrict_children {
+                UpdateProcThreadAttribute(list, 0, PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY, &mut child_policy as *mut _ as _, mem::size_of::<DWORD>(), ptr::null_mut(), ptr::null_mut());
+            }
+
+            let mut startup: STARTUPINFOEXW = mem::zeroed();
+            startup.StartupInfo.cb = mem::size_of::<STARTUPINFOEXW>() as DWORD;
+            startup.StartupInfo.dwFlags = STARTF_USESTDHANDLES;
+            startup.StartupInfo.hStdInput = GetStdHandle(STD_INPUT_HANDLE);
+            startup.StartupInfo.hStdOutput = GetStdHandle(STD_OUTPUT_HANDLE);
+            startup.StartupInfo.hStdError = GetStdHandle(STD_ERROR_HANDLE);
+            startup.lpAttributeList = list;
+
+            let mut information: PROCESS_INFORMATION = mem::zeroed();
+            let flags = CREATE_SUSPENDED | EXTENDED_STARTUPINFO_PRESENT;
+            let created = match token {
+                Some(token) => CreateProcessAsUserW(token, ptr::null(), line.as_mut_ptr(), ptr::null_mut(), ptr::null_mut(), TRUE, flags, ptr::null_mut(), ptr::null(), &mut startup.StartupInfo, &mut information),
+                None => CreateProcessW(ptr::null(), line.as_mut_ptr(), ptr::null_mut(), ptr::null_mut(), TRUE, flags, ptr::null_mut(), ptr::null(), &mut startup.StartupInfo, &mut information),
+            };
+            let error = io::Error::last_os_error();
+            DeleteProcThreadAttributeList(list);
+
+            if created != FALSE {
+                break information;
+            }
+
+            match (error.raw_os_error().map(|code| code as DWORD), mitigations.pop()) {
+                (Some(ERROR_INVALID_PARAMETER), Some(mitigation)) | (Some(ERROR_NOT_SUPPORTED), Some(mitigation)) => {
+                    warning!("this Windows doesn't support the {} mitigation, starting the program without it", mitigation.name());
+                }
+                _ => {
+                    if let Some(token) = token {
+                        CloseHandle(token);
+                    }
+
+                    return Err(anyhow::Error::new(error).context("Unable to spawn program"));
+                }
+            }
+        };
+
+        if let Some(token) = token {
+            CloseHandle(token);
+        }
+
+        if AssignProcessToJobObject(job, information.hProcess) == FALSE {
+            verbose!("unable to put the program in a job object: {}", io::Error::last_os_error());
+        }
+
+        ResumeThread(information.hThread);
+        CloseHandle(information.hThread);
+        WaitForSingleObject(information.hProcess, INFINITE);
+
+        let mut code: DWORD = 0;
+        GetExitCodeProcess(information.hProcess, &mut code);
+        CloseHandle(information.hProcess);
+        CloseHandle(job);
+
+        Ok(code as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mitigation_names() {
+        for name in NAMES {
+            assert_eq!(name.parse::<Mitigation>().unwrap().name(), *name);
+        }
+
+        assert_eq!("DEP".parse::<Mitigation>().unwrap(), Mitigation::Dep);
+        assert!("cfg".parse::<Mitigation>().is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Past this much without a newline, a line goes out in pieces.
+const MAX_LINE: usize = 64 * 1024;
+
+/// How the program's output is passed on, for --prefix, --output-log and
+/// --tee.
+#[derive(Clone)]
+pub struct Relay {
+    pub prefix: Option<String>,
+    pub timestamps: bool,
+    /// Each gets every byte the program writes, as it was written.
+    pub logs: Vec<Arc<Mutex<File>>>,
+    /// Copy at idle I/O priority, like the program's own.
+    pub idle_io: bool,
+}
+
+/// The local time of day, to the millisecond.
+#[cfg(unix)]
+fn clock() -> String {
+    use nix::libc;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = now.as_secs() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+
+    unsafe {
+        libc::localtime_r(&seconds, &mut tm);
+    }
+
+    format!("{:02}:{:02}:{:02}.{:03}", tm.tm_hour, tm.tm_min, tm.tm_sec, now.subsec_millis())
+}
+
+/// The local time of day, to the millisecond.
+#[cfg(windows)]
+fn clock() -> String {
+    use winapi::um::minwinbase::SYSTEMTIME;
+    use winapi::um::sysinfoapi::GetLocalTime;
+
+    let mut time: SYSTEMTIME = unsafe { std::mem::zeroed() };
+
+    unsafe {
+        GetLocalTime(&mut time);
+    }
+
+    format!("{:02}:{:02}:{:02}.{:03}", time.wHour, time.wMinute, time.wSecond, time.wMilliseconds)
+}
+
+/// Whether `bytes` can't be text: a NUL, or a byte sequence that isn't
+/// UTF-8 (as opposed to one cut short at the end).
+fn binary(bytes: &[u8]) -> bool {
+    bytes.contains(&0) || std::str::from_utf8(bytes).is_err_and(|error| error.error_len().is_some())
+}
+
+impl Relay {
+    /// What goes in front of each line.
+    fn label(&self) -> Vec<u8> {
+        let mut label = String::new();
+
+        if let Some(prefix) = &self.prefix {
+            label.push_str(&format!("[{}] ", prefix));
+        }
+
+        if self.timestamps {
+            label.push_str(&clock());
+            label.push(' ');
+        }
+
+        label.into_bytes()
+    }
+
+    /// Copies `input` to `output` line by line, each behind the label, until
+    /// the program closes it. Output that turns out not to be text is passed
+    /// on untouched from then on, rather than cut up.
+    fn copy(&self, mut input: impl Read, mut output: impl Write) -> io::Result<()> {
+        let mut buffer = [0; 8192];
+        let mut line = Vec::new();
+        let mut raw = self.prefix.is_none() && !self.timestamps;
+        // Whether the next byte starts a line, rather than continuing one
+        // that was too long to hold.
+        let mut fresh = true;
+
+        loop {
+            let read = match input.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            };
+
+            let chunk = &buffer[..read];
+
+            // Files aren't buffered, so this lands even if we're killed.
+            for log in &self.logs {
+                log.lock().unwrap().write_all(chunk)?;
+            }
+
+            if raw {
+                output.write_all(chunk)?;
+                output.flush()?;
+                continue;
+            }
+
+            line.extend_from_slice(chunk);
+
+            while let Some(end) = line.iter().position(|byte| *byte == b'\n') {
+                if binary(&line[..end]) {
+                    break;
+                }
+
+                let rest = line.split_off(end + 1);
+
+                if fresh {
+                    output.write_all(&self.label())?;
+                }
+
+                output.write_all(&line)?;
+                line = rest;
+                fresh = true;
+            }
+
+            if binary(&line) {
+                // Not text, so stop looking for lines.
+                output.write_all(&line)?;
+                line.clear();
+                raw = true;
+            } else if line.len() >= MAX_LINE {
+                if fresh {
+                    output.write_all(&self.label())?;
+                }
+
+                output.write_all(&line)?;
+                line.clear();
+                fresh = false;
+            }
+
+            output.flush()?;
+        }
+
+        if !line.is_empty() {
+            if fresh {
+                output.write_all(&self.label())?;
+            }
+
+            output.write_all(&line)?;
+        }
+
+        output.flush()
+    }
+
+    /// Passes `input` on in the background.
+    pub fn spawn(&self, input: impl Read + Send + 'static, output: impl Write + Send + 'static) -> JoinHandle<()> {
+        let relay = self.clone();
+
+        thread::spawn(move || {
+            if relay.idle_io {
+                if let Err(error) = crate::priority::background_io_thread() {
+                    verbose!("unable to lower the I/O priority of the output relay: {}", error);
+                }
+            }
+
+            if let Err(error) = relay.copy(input, output) {
+                verbose!("stopped relaying the program's output: {}", error);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relay(prefix: Option<&str>, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let relay = Relay { prefix: prefix.map(String::from), timestamps: false, logs: Vec::new(), idle_io: false };
+
+        relay.copy(input, &mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn prefixes_lines() {
+        assert_eq!(relay(Some("job"), b"one\ntwo\nthree"), b"[job] one\n[job] two\n[job] three");
+        assert_eq!(relay(None, b"one\ntwo\n"), b"one\ntwo\n");
+    }
+
+    #[test]
+    fn leaves_binary_output_alone() {
+        assert_eq!(relay(Some("job"), b"text\n\x00\x01\n\xff\n"), b"[job] text\n\x00\x01\n\xff\n");
+    }
+}
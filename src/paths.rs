@@ -0,0 +1,179 @@
+use anyhow::{bail, Result};
+use std::env;
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+
+fn home_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let home = env::var_os("USERPROFILE").or_else(|| env::var_os("HOME"));
+    #[cfg(not(windows))]
+    let home = env::var_os("HOME");
+
+    home.filter(|home| !home.is_empty()).map(PathBuf::from)
+}
+
+/// Expands a leading `~` to the user's home directory, for paths that did not
+/// go through a shell. `~user` forms are left alone.
+pub fn expand_tilde(path: &OsStr) -> PathBuf {
+    let path = Path::new(path);
+    let mut components = path.components();
+
+    match (components.next(), home_dir()) {
+        (Some(Component::Normal(first)), Some(home)) if first == "~" => home.join(components.as_path()),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Whether `program` names a file directly instead of something to look up
+/// in PATH, i.e. it is absolute or contains a path separator.
+pub fn is_explicit(program: &Path) -> bool {
+    program.is_absolute() || program.parent().is_some_and(|parent| !parent.as_os_str().is_empty())
+}
+
+/// Refuses bare program names, so PATH can never pick the binary for us.
+pub fn require_explicit(program: &Path) -> Result<()> {
+    if !is_explicit(program) {
+        bail!("{:?} would be looked up in PATH; pass an absolute path or one containing a separator, like ./{}", program, program.display());
+    }
+
+    Ok(())
+}
+
+/// Works out which file to run when the program is started from `cwd`.
+///
+/// A relative path with a separator, like `./build.sh`, means the same thing
+/// as in `cd cwd && ./build.sh`, so it is joined onto `cwd` and has to exist
+/// there. Absolute paths are used as they are, and bare names are still left
+/// for PATH to resolve.
+pub fn resolve_program(program: &Path, cwd: Option<&Path>) -> Result<PathBuf> {
+    match cwd {
+        Some(cwd) if is_explicit(program) && program.is_relative() => {
+            let resolved = cwd.join(program);
+
+            if !resolved.is_file() {
+                bail!("{:?} does not exist in {:?}", program, cwd);
+            }
+
+            Ok(resolved)
+        }
+        _ => Ok(program.to_path_buf()),
+    }
+}
+
+/// Whether `path` is a file we may execute.
+fn runnable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        path.metadata().is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    }
+
+    #[cfg(windows)]
+    path.is_file()
+}
+
+/// Finds the file `program` would run from `cwd`, looking bare names up in
+/// PATH the way the system does.
+pub fn find_program(program: &Path, cwd: Option<&Path>) -> Result<PathBuf> {
+    if is_explicit(program) {
+        let found = resolve_program(program, cwd)?;
+
+        if !found.is_file() {
+            bail!("{:?} does not exist", program);
+        }
+
+        if !runnable(&found) {
+            bail!("{:?} is not executable", program);
+        }
+
+        return Ok(found);
+    }
+
+    #[cfg(windows)]
+    let extensions: Vec<String> = match program.extension() {
+        Some(_) => vec![String::new()],
+        None => env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string()).split(';').map(String::from).collect(),
+    };
+    #[cfg(not(windows))]
+    let extensions = vec![String::new()];
+
+    for dir in env::split_paths(&env::var_os("PATH").unwrap_or_default()) {
+        for extension in &extensions {
+            let mut candidate = dir.join(program).into_os_string();
+            candidate.push(extension);
+            let candidate = PathBuf::from(candidate);
+
+            if runnable(&candidate) {
+                return Ok(candidate);
+            }
+        }
+    }
+
+    bail!("{:?} is not in PATH", program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nicer-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("build.sh"), "").unwrap();
+        dir
+    }
+
+    #[test]
+    fn tilde_is_expanded_only_at_the_start() {
+        let home = home_dir().unwrap();
+        assert_eq!(expand_tilde(OsStr::new("~")), home);
+        assert_eq!(expand_tilde(OsStr::new("~/bin/tool")), home.join("bin").join("tool"));
+        assert_eq!(expand_tilde(OsStr::new("bin/~")), Path::new("bin/~"));
+        assert_eq!(expand_tilde(OsStr::new("~user/bin")), Path::new("~user/bin"));
+    }
+
+    #[test]
+    fn relative_program_without_cwd_is_untouched() {
+        assert_eq!(resolve_program(Path::new("./build.sh"), None).unwrap(), Path::new("./build.sh"));
+    }
+
+    #[test]
+    fn programs_are_found_in_path() {
+        let cargo = find_program(Path::new("cargo"), None).unwrap();
+
+        assert!(cargo.is_absolute() || cargo.components().count() > 1);
+        assert!(find_program(Path::new("nicer-surely-not-a-program"), None).is_err());
+        assert!(find_program(Path::new("./nicer-surely-not-a-program"), None).is_err());
+    }
+
+    #[test]
+    fn bare_name_with_cwd_goes_through_path() {
+        let dir = scratch("bare");
+        assert_eq!(resolve_program(Path::new("build.sh"), Some(&dir)).unwrap(), Path::new("build.sh"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn absolute_program_with_cwd_is_untouched() {
+        let dir = scratch("absolute");
+        let program = std::env::temp_dir().join("elsewhere").join("tool");
+        assert_eq!(resolve_program(&program, Some(&dir)).unwrap(), program);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn relative_program_with_cwd_is_joined() {
+        let dir = scratch("joined");
+        assert_eq!(resolve_program(Path::new("./build.sh"), Some(&dir)).unwrap(), dir.join("./build.sh"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_relative_program_with_cwd_is_an_error() {
+        let dir = scratch("missing");
+        assert!(resolve_program(Path::new("./missing.sh"), Some(&dir)).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,1623 @@
+use anyhow::Result;
+use std::fmt;
+
+/// Why putting a process back at normal priority usually fails.
+#[cfg(unix)]
+const RESTORE: &str = "Restoring priority back up may need root or CAP_SYS_NICE";
+
+/// Which kinds of priority nicer lowers.
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PriorityConfig {
+    pub cpu: bool,
+    pub io: bool,
+    /// Go all the way on every axis that is lowered: SCHED_IDLE and the
+    /// highest oom_score_adj on Linux, EcoQoS on Windows, an I/O throttle on
+    /// top of the background band on macOS.
+    pub lowest: bool,
+    /// Let macOS coalesce timers, as its background band does.
+    pub timers: bool,
+    /// Lower memory priority too, so the program's pages are the first to
+    /// be dropped from the page cache and working sets (Windows).
+    #[serde(skip)]
+    pub memory: bool,
+    /// Opt into EcoQoS without going all the way, as --lowest does (Windows).
+    #[serde(skip)]
+    pub eco: bool,
+    /// How far down macOS's tiers the program goes.
+    #[serde(skip)]
+    pub tier: Tier,
+    /// The scheduling policy to switch to, if any besides what --lowest
+    /// brings (Linux).
+    #[serde(skip)]
+    pub sched: Option<Sched>,
+    /// The oom_score_adj to give the program, from -1000 to 1000, if any
+    /// besides what --lowest brings (Linux).
+    #[serde(skip)]
+    pub oom_score_adj: Option<i32>,
+    /// The I/O class I/O priority is lowered to (Linux).
+    #[serde(skip)]
+    pub io_class: IoClass,
+    /// The niceness CPU priority is lowered to; the closest priority class
+    /// on Windows.
+    #[serde(skip)]
+    pub nice: i32,
+}
+
+impl Default for PriorityConfig {
+    fn default() -> PriorityConfig {
+        PriorityConfig { cpu: true, io: true, lowest: false, timers: true, memory: false, eco: false, tier: Tier::Background, sched: None, oom_score_adj: None, io_class: IoClass::Idle, nice: 19 }
+    }
+}
+
+impl PriorityConfig {
+    /// The flags that ask for this configuration.
+    pub fn flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+
+        if !self.cpu {
+            flags.push("--no-nice".to_string());
+        }
+
+        if !self.io {
+            flags.push("--no-ioprio".to_string());
+        }
+
+        if self.lowest {
+            flags.push("--lowest".to_string());
+        }
+
+        if !self.timers {
+            flags.push("--no-timer-throttle".to_string());
+        }
+
+        if self.memory {
+            flags.push("--background-mode".to_string());
+        }
+
+        if self.eco {
+            flags.push("--eco".to_string());
+        }
+
+        if let Some(sched) = self.sched {
+            flags.push(format!("--sched={}", sched.name()));
+        }
+
+        if let Some(adj) = self.oom_score_adj {
+            flags.push(format!("--oom-score-adj={}", adj));
+        }
+
+        if self.tier != Tier::Background {
+            flags.push(format!("--tier={}", self.tier.name()));
+        }
+
+        match self.io_class {
+            IoClass::Idle => {}
+            IoClass::BestEffort(level) => {
+                flags.push("--io-class=best-effort".to_string());
+                flags.push(format!("--io-level={}", level));
+            }
+            IoClass::None => flags.push("--ioprio-from-nice".to_string()),
+        }
+
+        flags
+    }
+}
+
+/// A Linux I/O scheduling class, with its level where it has one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoClass {
+    /// Only gets the disk when nobody else wants it.
+    Idle,
+    /// A share of the disk by level, from 0 (the most) to 7 (the least).
+    BestEffort(i32),
+    /// No class of its own: the kernel goes by niceness, as best-effort at
+    /// level (nice + 20) / 5.
+    None,
+}
+
+impl fmt::Display for IoClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IoClass::Idle => f.write_str("idle"),
+            IoClass::BestEffort(level) => write!(f, "best-effort {}", level),
+            IoClass::None => f.write_str("none"),
+        }
+    }
+}
+
+/// The names --io-class takes.
+pub const IO_CLASS_NAMES: &[&str] = &["idle", "best-effort", "none"];
+
+impl std::str::FromStr for IoClass {
+    type Err = anyhow::Error;
+
+    /// Best-effort starts at its lowest level, 7.
+    fn from_str(text: &str) -> Result<IoClass> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "idle" => Ok(IoClass::Idle),
+            "best-effort" => Ok(IoClass::BestEffort(7)),
+            "none" => Ok(IoClass::None),
+            _ => Err(anyhow::anyhow!("Unknown I/O class {:?}, expected one of {}", text, IO_CLASS_NAMES.join(", "))),
+        }
+    }
+}
+
+/// A Windows priority class for --priority, standing for the niceness the
+/// read-back maps it to, which picks the same class again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PriorityClass {
+    Idle,
+    BelowNormal,
+    Normal,
+}
+
+/// The names --priority takes.
+pub const PRIORITY_CLASS_NAMES: &[&str] = &["idle", "below-normal", "normal"];
+
+impl std::str::FromStr for PriorityClass {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<PriorityClass> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "idle" => Ok(PriorityClass::Idle),
+            "below-normal" => Ok(PriorityClass::BelowNormal),
+            "normal" => Ok(PriorityClass::Normal),
+            _ => Err(anyhow::anyhow!("Unknown priority class {:?}, expected one of {}", text, PRIORITY_CLASS_NAMES.join(", "))),
+        }
+    }
+}
+
+impl PriorityClass {
+    pub fn name(self) -> &'static str {
+        match self {
+            PriorityClass::Idle => "idle",
+            PriorityClass::BelowNormal => "below-normal",
+            PriorityClass::Normal => "normal",
+        }
+    }
+
+    pub fn nice(self) -> i32 {
+        match self {
+            PriorityClass::Idle => 19,
+            PriorityClass::BelowNormal => 10,
+            PriorityClass::Normal => 0,
+        }
+    }
+}
+
+/// A Linux scheduling policy for --sched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sched {
+    /// Only runs when nothing else wants the CPU, whatever its niceness.
+    Idle,
+    /// Niceness as usual, but treated as CPU-bound: never preempts others on
+    /// waking up.
+    Batch,
+    /// The default policy.
+    Other,
+}
+
+/// The names --sched takes.
+pub const SCHED_NAMES: &[&str] = &["idle", "batch", "other"];
+
+impl std::str::FromStr for Sched {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Sched> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "idle" => Ok(Sched::Idle),
+            "batch" => Ok(Sched::Batch),
+            "other" => Ok(Sched::Other),
+            _ => Err(anyhow::anyhow!("Unknown scheduling policy {:?}, expected one of {}", text, SCHED_NAMES.join(", "))),
+        }
+    }
+}
+
+impl Sched {
+    pub fn name(self) -> &'static str {
+        match self {
+            Sched::Idle => "idle",
+            Sched::Batch => "batch",
+            Sched::Other => "other",
+        }
+    }
+}
+
+/// A macOS tier for --tier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tier {
+    /// The Darwin background band: CPU, I/O and network throttled, and
+    /// timers coalesced.
+    Background,
+    /// Marked as not driving any UI, which lowers its QoS without the
+    /// throttling; disk I/O gets the utility policy.
+    Utility,
+    /// Neither, with only niceness and the I/O policy lowered.
+    Default,
+}
+
+/// The names --tier takes.
+pub const TIER_NAMES: &[&str] = &["background", "utility", "default"];
+
+impl std::str::FromStr for Tier {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Tier> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "background" => Ok(Tier::Background),
+            "utility" => Ok(Tier::Utility),
+            "default" => Ok(Tier::Default),
+            _ => Err(anyhow::anyhow!("Unknown tier {:?}, expected one of {}", text, TIER_NAMES.join(", "))),
+        }
+    }
+}
+
+impl Tier {
+    pub fn name(self) -> &'static str {
+        match self {
+            Tier::Background => "background",
+            Tier::Utility => "utility",
+            Tier::Default => "default",
+        }
+    }
+}
+
+/// Version of the --json format; bumped whenever a field changes meaning or
+/// goes away.
+pub const JSON_VERSION: u32 = 1;
+
+/// One of the things a priority change adjusts; not every platform has them
+/// all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Knob {
+    #[cfg_attr(windows, allow(dead_code))]
+    Nice,
+    /// Darwin's background band.
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    Background,
+    #[cfg_attr(not(any(target_os = "linux", target_os = "android", windows)), allow(dead_code))]
+    Io,
+    /// macOS's disk I/O throttle.
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    IoThrottle,
+    #[cfg_attr(not(any(target_os = "linux", target_os = "android")), allow(dead_code))]
+    Scheduler,
+    #[cfg_attr(not(any(target_os = "linux", target_os = "android")), allow(dead_code))]
+    Oom,
+    #[cfg_attr(not(windows), allow(dead_code))]
+    PriorityClass,
+    #[cfg_attr(not(windows), allow(dead_code))]
+    EcoQos,
+    #[cfg_attr(not(windows), allow(dead_code))]
+    Memory,
+    /// Darwin's non-UI tier.
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    NonUi,
+}
+
+impl Knob {
+    /// The name --json uses.
+    pub fn key(self) -> &'static str {
+        match self {
+            Knob::Nice => "nice",
+            Knob::Background => "background",
+            Knob::Io => "ioprio",
+            Knob::IoThrottle => "io_throttle",
+            Knob::Scheduler => "sched",
+            Knob::Oom => "oom",
+            Knob::PriorityClass => "priority_class",
+            Knob::EcoQos => "eco_qos",
+            Knob::Memory => "memory_priority",
+            Knob::NonUi => "non_ui",
+        }
+    }
+}
+
+impl fmt::Display for Knob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Knob::Nice => "nice",
+            Knob::Background => "background",
+            Knob::Io => "I/O priority",
+            Knob::IoThrottle => "disk I/O",
+            Knob::Scheduler => "scheduler",
+            Knob::Oom => "OOM score",
+            Knob::PriorityClass => "priority class",
+            Knob::EcoQos => "EcoQoS",
+            Knob::Memory => "memory priority",
+            Knob::NonUi => "non-UI tier",
+        })
+    }
+}
+
+/// How one adjustment went; `result` is None if it was skipped.
+#[derive(Debug)]
+struct Outcome {
+    knob: Knob,
+    requested: Option<String>,
+    result: Option<Result<()>>,
+    /// What reading the knob back afterwards gave, where we can.
+    actual: Option<String>,
+}
+
+/// How each of the adjustments making up a priority change went.
+#[derive(Debug, Default)]
+pub struct ApplyReport {
+    outcomes: Vec<Outcome>,
+    /// Why the adjustments may have failed, for the error.
+    hint: Option<&'static str>,
+}
+
+/// One adjustment of one process, as --json reports it.
+#[derive(Debug, serde::Serialize)]
+pub struct Adjustment {
+    pub pid: u32,
+    pub knob: &'static str,
+    pub requested: Option<String>,
+    /// What the knob read back as afterwards, where that can be read.
+    pub actual: Option<String>,
+    /// `applied`, `skipped` or `failed`.
+    pub outcome: &'static str,
+    /// The errno, or the Windows error code, of a failure.
+    pub code: Option<i32>,
+    pub error: Option<String>,
+}
+
+/// The OS error behind `error`, if there is one.
+fn os_error(error: &anyhow::Error) -> Option<&std::io::Error> {
+    error.chain().find_map(|cause| cause.downcast_ref::<std::io::Error>())
+}
+
+impl ApplyReport {
+    /// Adds the outcome of setting `knob` to `requested`.
+    pub fn add(&mut self, knob: Knob, requested: impl fmt::Display, result: Result<()>) {
+        self.outcomes.push(Outcome { knob, requested: Some(requested.to_string()), result: Some(result), actual: None });
+    }
+
+    /// Records that `knob` was left alone, as asked.
+    pub fn skip(&mut self, knob: Knob) {
+        self.outcomes.push(Outcome { knob, requested: None, result: None, actual: None });
+    }
+
+    /// Explains failures with `hint`.
+    #[cfg_attr(windows, allow(dead_code))]
+    pub fn hint(mut self, hint: &'static str) -> ApplyReport {
+        self.hint = Some(hint);
+        self
+    }
+
+    /// Reads back what each adjustment of `who` (0 for ourselves) left
+    /// behind, where it can be read, and says under --verbose when that
+    /// isn't what was asked for. setpriority can report a failure it didn't
+    /// have (Darwin's ESRCH), or a success that didn't stick.
+    #[cfg(unix)]
+    fn verify(mut self, who: u32) -> ApplyReport {
+        let pid = match who {
+            0 => std::process::id(),
+            pid => pid,
+        };
+
+        for outcome in &mut self.outcomes {
+            let (requested, result) = match (&outcome.requested, &outcome.result) {
+                (Some(requested), Some(result)) => (requested, result),
+                _ => continue,
+            };
+
+            outcome.actual = read_back(outcome.knob, who);
+
+            match (&outcome.actual, result) {
+                (Some(actual), Ok(())) if actual != requested => verbose!("the {} of pid {} reads back as {}, not the {} asked for", outcome.knob, pid, actual, requested),
+                (Some(actual), Err(_)) if actual == requested => verbose!("setting the {} of pid {} failed, yet it reads back as {}", outcome.knob, pid, actual),
+                _ => {}
+            }
+        }
+
+        self
+    }
+
+    /// Every adjustment of `pid` in this report, for --json.
+    pub fn adjustments(&self, pid: u32) -> impl Iterator<Item = Adjustment> + '_ {
+        self.outcomes.iter().map(move |outcome| {
+            let (name, code, error) = match &outcome.result {
+                None => ("skipped", None, None),
+                Some(Ok(())) => ("applied", None, None),
+                Some(Err(error)) => ("failed", os_error(error).and_then(|io| io.raw_os_error()), Some(format!("{:#}", error))),
+            };
+
+            Adjustment { pid, knob: outcome.knob.key(), requested: outcome.requested.clone(), actual: outcome.actual.clone(), outcome: name, code, error }
+        })
+    }
+
+    /// Fails if every adjustment did, or with `strict` if any did; otherwise
+    /// the failures are only reported under --verbose. The error is caused by
+    /// the first failure, so callers can still tell what went wrong.
+    pub fn check(self, strict: bool) -> Result<()> {
+        let tried: Vec<(Knob, Result<()>)> = self.outcomes.into_iter().filter_map(|Outcome { knob, result, .. }| result.map(|result| (knob, result))).collect();
+        let total = tried.len();
+        let mut errors: Vec<anyhow::Error> = Vec::new();
+        let failures: Vec<String> = tried
+            .into_iter()
+            .filter_map(|(knob, result)| result.err().map(|error| {
+                let failure = format!("{}: {}", knob, error);
+                errors.push(error);
+                failure
+            }))
+            .collect();
+
+        if failures.is_empty() {
+            return Ok(());
+        }
+
+        if strict || failures.len() == total {
+            let error = errors.remove(0).context(failures.join("; "));
+
+            return match self.hint {
+                Some(hint) => Err(error.context(hint)),
+                None => Err(error),
+            };
+        }
+
+        for failure in failures {
+            verbose!("unable to adjust {}", failure);
+        }
+
+        Ok(())
+    }
+}
+
+/// How a walk over a process tree went, for --count and --json.
+#[derive(Debug, serde::Serialize)]
+pub struct TreeReport {
+    pub version: u32,
+    pub adjusted: u32,
+    /// Skipped for lack of permission.
+    pub denied: u32,
+    /// Gone before we got to them.
+    pub vanished: u32,
+    /// Skipped for any other reason.
+    pub failed: u32,
+    pub adjustments: Vec<Adjustment>,
+}
+
+impl Default for TreeReport {
+    fn default() -> TreeReport {
+        TreeReport { version: JSON_VERSION, adjusted: 0, denied: 0, vanished: 0, failed: 0, adjustments: Vec::new() }
+    }
+}
+
+impl TreeReport {
+    /// Counts how adjusting one process went.
+    pub fn add(&mut self, result: &Result<()>) {
+        let error = match result {
+            Ok(()) => return self.adjusted += 1,
+            Err(error) => error,
+        };
+
+        let io = os_error(error);
+
+        #[cfg(unix)]
+        let vanished = io.is_some_and(|io| io.raw_os_error() == Some(nix::libc::ESRCH));
+        #[cfg(windows)]
+        let vanished = io.is_some_and(|io| io.raw_os_error() == Some(winapi::shared::winerror::ERROR_INVALID_PARAMETER as i32));
+
+        match io {
+            _ if vanished => self.vanished += 1,
+            Some(io) if io.kind() == std::io::ErrorKind::PermissionDenied => self.denied += 1,
+            _ => self.failed += 1,
+        }
+    }
+}
+
+impl fmt::Display for TreeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "adjusted {}, skipped {} (permission), {} vanished", self.adjusted, self.denied, self.vanished)?;
+
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn set_priority(which: nix::libc::c_int, who: u32, value: i32) -> Result<()>{
+    raw_set_priority(which, who, value).map_err(anyhow::Error::new)
+}
+
+/// setpriority(2), without allocating.
+#[cfg(unix)]
+fn raw_set_priority(which: nix::libc::c_int, who: u32, value: i32) -> std::io::Result<()> {
+    unsafe {
+        use nix::libc;
+
+        let status = libc::setpriority(which as _, who as libc::id_t, value);
+
+        match status {
+            0 => Ok(()),
+            _ => {
+                // Darwin returns ESRCH for PRIO_DARWIN_PROCESS even though both values are correctly set; skip return
+                // print!("{}\n", libc::getpriority(libc::PRIO_DARWIN_PROCESS, 0));
+                // print!("{}\n", libc::getpriority(libc::PRIO_PROCESS, 0));
+                let error = std::io::Error::last_os_error();
+
+                match error.raw_os_error() {
+                    Some(0) => Ok(()),
+                    _ => Err(error)
+                }
+                // return Ok(());
+            }
+        }
+    }
+}
+
+/// One adjustment, worked out ahead down to the raw values, so that the
+/// program can also make it to itself between fork and exec.
+#[cfg(unix)]
+#[derive(Debug)]
+enum Op {
+    /// setpriority(2) with this `which` and value.
+    Priority(nix::libc::c_int, i32),
+    /// An I/O class and level.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Ioprio(i32, i32),
+    /// A scheduling policy and real-time priority.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Scheduler(nix::libc::c_int, nix::libc::c_int),
+    /// An oom_score_adj, with its digits ready to be written.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    OomScoreAdj(i32, Vec<u8>),
+    /// A disk I/O policy for the whole process, which only we can set.
+    #[cfg(target_os = "macos")]
+    DiskPolicy(nix::libc::c_int),
+}
+
+#[cfg(unix)]
+impl Op {
+    /// Makes the adjustment to `who`. For ourselves (0) this neither
+    /// allocates nor takes a lock, as between fork and exec.
+    fn apply(&self, who: u32) -> std::io::Result<()> {
+        match self {
+            Op::Priority(which, value) => raw_set_priority(*which, who, *value),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Op::Ioprio(class, level) => linux::set_ioprio(who, *class, *level),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Op::Scheduler(policy, priority) => linux::set_scheduler(who, *policy, *priority),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Op::OomScoreAdj(value, digits) => match who {
+                0 => crate::child::write_file(b"/proc/self/oom_score_adj\0", digits),
+                pid => linux::set_oom_score_adj(pid, *value),
+            },
+            #[cfg(target_os = "macos")]
+            Op::DiskPolicy(policy) => match who {
+                0 => darwin::set_iopolicy(darwin::IOPOL_TYPE_DISK, darwin::IOPOL_SCOPE_PROCESS, *policy),
+                _ => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "macOS only lets nicer throttle its own I/O")),
+            },
+        }
+    }
+
+    /// Whether failing with `error` leaves things as good as done.
+    fn tolerated(&self, error: &std::io::Error) -> bool {
+        match self {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Op::Ioprio(..) => ioprio_denied(error),
+            _ => {
+                let _ = error;
+                false
+            }
+        }
+    }
+}
+
+/// A knob to set, with the value in the words the report uses, or to leave
+/// alone.
+#[cfg(unix)]
+#[derive(Debug)]
+enum Step {
+    Set(Knob, String, Op),
+    Skip(Knob),
+}
+
+/// Makes each of `steps` to `who` (0 for ourselves), returning how it went.
+#[cfg(unix)]
+fn apply_steps(steps: &[Step], who: u32) -> ApplyReport {
+    let mut report = ApplyReport::default();
+
+    for step in steps {
+        match step {
+            Step::Set(knob, requested, op) => {
+                let result = match op.apply(who) {
+                    Err(error) if op.tolerated(&error) => {
+                        verbose!("not allowed to adjust the {} ({}), leaving it alone", knob, error);
+                        Ok(())
+                    }
+                    result => result.map_err(anyhow::Error::new),
+                };
+
+                report.add(*knob, requested, result);
+            }
+            Step::Skip(knob) => report.skip(*knob),
+        }
+    }
+
+    report
+}
+
+#[cfg(unix)]
+fn set_background(who: u32, config: &PriorityConfig) -> ApplyReport {
+    apply_steps(&plan(config), who).verify(who)
+}
+
+/// The steps lowering a process's priority along `config`. Other Unixes
+/// have no I/O priority to speak of.
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android", target_os = "macos"))))]
+fn plan(config: &PriorityConfig) -> Vec<Step> {
+    vec![match config.cpu {
+        true => Step::Set(Knob::Nice, config.nice.to_string(), Op::Priority(nix::libc::PRIO_PROCESS as _, config.nice)),
+        false => Step::Skip(Knob::Nice),
+    }]
+}
+
+/// The steps lowering a process's priority along `config`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn plan(config: &PriorityConfig) -> Vec<Step> {
+    let mut steps = vec![match config.cpu {
+        true => Step::Set(Knob::Nice, config.nice.to_string(), Op::Priority(nix::libc::PRIO_PROCESS as _, config.nice)),
+        false => Step::Skip(Knob::Nice),
+    }];
+
+    let (class, level) = match config.io_class {
+        IoClass::Idle => (linux::IOPRIO_CLASS_IDLE, 0),
+        IoClass::BestEffort(level) => (linux::IOPRIO_CLASS_BE, level),
+        IoClass::None => (linux::IOPRIO_CLASS_NONE, 0),
+    };
+
+    steps.push(match config.io {
+        true => Step::Set(Knob::Io, linux::io_name(class, level), Op::Ioprio(class, level)),
+        false => Step::Skip(Knob::Io),
+    });
+
+    let sched = match (config.sched, config.lowest) {
+        (Some(sched), _) => Some(sched),
+        (None, true) => Some(Sched::Idle),
+        (None, false) => None,
+    };
+
+    if let (Some(sched), true) = (sched, config.cpu) {
+        let policy = match sched {
+            Sched::Idle => linux::SCHED_IDLE,
+            Sched::Batch => linux::SCHED_BATCH,
+            Sched::Other => linux::SCHED_OTHER,
+        };
+
+        steps.push(Step::Set(Knob::Scheduler, linux::policy_name(policy, 0), Op::Scheduler(policy, 0)));
+    }
+
+    let oom = match (config.oom_score_adj, config.lowest) {
+        (Some(adj), _) => Some(adj),
+        (None, true) => Some(1000),
+        (None, false) => None,
+    };
+
+    if let Some(adj) = oom {
+        steps.push(Step::Set(Knob::Oom, adj.to_string(), Op::OomScoreAdj(adj, adj.to_string().into_bytes())));
+    }
+
+    steps
+}
+
+/// Whether ioprio_set failed with `error` only because Android's SELinux
+/// policy denies it to apps (Termux included), in which case the process
+/// keeps its I/O priority and is none the worse for it.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn ioprio_denied(error: &std::io::Error) -> bool {
+    cfg!(target_os = "android") && matches!(error.raw_os_error(), Some(nix::libc::EACCES) | Some(nix::libc::EPERM))
+}
+
+/// Moves `who` into I/O class `class` at `level`, which only best-effort
+/// heeds; see IoClass.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn background_io(who: u32, class: i32, level: i32) -> Result<()>{
+    match linux::set_ioprio(who, class, level) {
+        Err(error) if ioprio_denied(&error) => {
+            verbose!("not allowed to lower the I/O priority ({}), leaving it alone", error);
+            Ok(())
+        }
+        result => result.map_err(anyhow::Error::new),
+    }
+}
+
+/// Whether `config` goes with the Darwin background band; see below.
+#[cfg(target_os = "macos")]
+fn band(config: &PriorityConfig) -> bool {
+    config.tier == Tier::Background && config.cpu && config.io && config.timers && config.nice == 19 && config.io_class == IoClass::Idle
+}
+
+/// The Darwin background band covers CPU, I/O and timer coalescing. Short
+/// of all three, CPU is lowered with nice 19 through setpriority(PRIO_PROCESS)
+/// and disk I/O with IOPOL_THROTTLE through setiopolicy_np(IOPOL_TYPE_DISK),
+/// both there since macOS 10.5. Timer coalescing has no public per-process
+/// switch; it comes with PRIO_DARWIN_BG, so leaving that out is what turns
+/// it off. The band has no niceness of its own, so any other than 19 means
+/// going without it too, and it only throttles I/O, so does any I/O class
+/// but idle: best-effort is IOPOL_UTILITY, and none the default policy. The
+/// utility tier swaps the band for PRIO_DARWIN_NONUI.
+#[cfg(all(unix, target_os = "macos"))]
+fn plan(config: &PriorityConfig) -> Vec<Step> {
+    use nix::libc;
+
+    let mut steps = Vec::new();
+    let band = band(config);
+    let (name, policy) = match config.io_class {
+        IoClass::Idle => ("throttle", darwin::IOPOL_THROTTLE),
+        IoClass::BestEffort(_) => ("utility", darwin::IOPOL_UTILITY),
+        IoClass::None => ("default", darwin::IOPOL_DEFAULT),
+    };
+
+    if band {
+        steps.push(Step::Set(Knob::Background, true.to_string(), Op::Priority(libc::PRIO_DARWIN_PROCESS, libc::PRIO_DARWIN_BG)));
+    } else if config.cpu {
+        steps.push(Step::Set(Knob::Nice, config.nice.to_string(), Op::Priority(libc::PRIO_PROCESS, config.nice)));
+    } else {
+        steps.push(Step::Skip(Knob::Nice));
+    }
+
+    if config.tier == Tier::Utility {
+        steps.push(Step::Set(Knob::NonUi, true.to_string(), Op::Priority(libc::PRIO_DARWIN_PROCESS, libc::PRIO_DARWIN_NONUI)));
+    }
+
+    // The background band throttles I/O already, unless we go all the way.
+    if config.io && (!band || config.lowest) {
+        steps.push(Step::Set(Knob::IoThrottle, name.to_string(), Op::DiskPolicy(policy)));
+    } else if !config.io {
+        steps.push(Step::Skip(Knob::IoThrottle));
+    }
+
+    steps
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod linux {
+    use nix::libc;
+    use std::fs;
+    use std::io;
+
+    // Not every libc we build against exports these.
+    pub const SCHED_OTHER: libc::c_int = 0;
+    pub const SCHED_BATCH: libc::c_int = 3;
+    pub const SCHED_IDLE: libc::c_int = 5;
+    const SCHED_RESET_ON_FORK: libc::c_int = 0x4000_0000;
+
+    pub const IOPRIO_CLASS_NONE: i32 = 0;
+    pub const IOPRIO_CLASS_BE: i32 = 2;
+    pub const IOPRIO_CLASS_IDLE: i32 = 3;
+    const IOPRIO_CLASS_SHIFT: i32 = 13;
+    const IOPRIO_WHO_PROCESS: i32 = 1;
+
+    /// An I/O class, with its level if it has one.
+    pub fn io_name(class: i32, level: i32) -> String {
+        let name = match class {
+            0 => "none",
+            1 => "realtime",
+            2 => "best-effort",
+            3 => "idle",
+            _ => "unknown",
+        };
+
+        // Only these two classes have levels.
+        match class {
+            1 | 2 => format!("{} {}", name, level),
+            _ => name.to_string(),
+        }
+    }
+
+    /// A scheduling policy, with its real-time priority if it has one.
+    pub fn policy_name(policy: libc::c_int, priority: libc::c_int) -> String {
+        let name = match policy {
+            0 => "other",
+            1 => "fifo",
+            2 => "rr",
+            3 => "batch",
+            5 => "idle",
+            6 => "deadline",
+            _ => "unknown",
+        };
+
+        match priority {
+            0 => name.to_string(),
+            priority => format!("{} {}", name, priority),
+        }
+    }
+
+    pub fn set_ioprio(pid: u32, class: i32, level: i32) -> io::Result<()> {
+        let ioprio = (class << IOPRIO_CLASS_SHIFT) | level;
+
+        match unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, pid as libc::c_int, ioprio) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// The I/O class and level of `pid`.
+    pub fn ioprio(pid: u32) -> io::Result<(i32, i32)> {
+        match unsafe { libc::syscall(libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, pid as libc::c_int) } {
+            -1 => Err(io::Error::last_os_error()),
+            ioprio => Ok(((ioprio as i32) >> IOPRIO_CLASS_SHIFT, (ioprio as i32) & ((1 << IOPRIO_CLASS_SHIFT) - 1))),
+        }
+    }
+
+    /// The scheduling policy of `pid`, and its real-time priority.
+    pub fn scheduler(pid: u32) -> io::Result<(libc::c_int, libc::c_int)> {
+        let mut param = libc::sched_param { sched_priority: 0 };
+
+        match unsafe { libc::sched_getscheduler(pid as libc::pid_t) } {
+            -1 => Err(io::Error::last_os_error()),
+            policy => match unsafe { libc::sched_getparam(pid as libc::pid_t, &mut param) } {
+                0 => Ok((policy & !SCHED_RESET_ON_FORK, param.sched_priority)),
+                _ => Err(io::Error::last_os_error()),
+            },
+        }
+    }
+
+    pub fn set_scheduler(pid: u32, policy: libc::c_int, priority: libc::c_int) -> io::Result<()> {
+        let param = libc::sched_param { sched_priority: priority };
+
+        match unsafe { libc::sched_setscheduler(pid as libc::pid_t, policy, &param) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    pub fn set_oom_score_adj(pid: u32, value: i32) -> io::Result<()> {
+        let path = match pid {
+            0 => "/proc/self/oom_score_adj".to_string(),
+            _ => format!("/proc/{}/oom_score_adj", pid),
+        };
+
+        fs::write(path, value.to_string())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod darwin {
+    use nix::libc;
+    use std::io;
+
+    // From <sys/resource.h>; libc doesn't have the I/O policy API.
+    pub const IOPOL_TYPE_DISK: libc::c_int = 0;
+    pub const IOPOL_SCOPE_PROCESS: libc::c_int = 0;
+    pub const IOPOL_SCOPE_THREAD: libc::c_int = 1;
+    pub const IOPOL_DEFAULT: libc::c_int = 0;
+    pub const IOPOL_THROTTLE: libc::c_int = 3;
+    pub const IOPOL_UTILITY: libc::c_int = 4;
+
+    extern "C" {
+        fn setiopolicy_np(iotype: libc::c_int, scope: libc::c_int, policy: libc::c_int) -> libc::c_int;
+    }
+
+    /// Sets one of our own I/O policies (macOS 10.5 and later), for the
+    /// whole process (which children inherit) or the calling thread alone.
+    pub fn set_iopolicy(iotype: libc::c_int, scope: libc::c_int, policy: libc::c_int) -> io::Result<()> {
+        match unsafe { setiopolicy_np(iotype, scope, policy) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+/// Lowers the I/O priority of the calling thread alone, for the work nicer
+/// does on the program's behalf while its own priority is left alone.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn background_io_thread() -> Result<()>{
+    // ioprio_set(IOPRIO_WHO_PROCESS, 0) means the calling thread.
+    background_io(0, linux::IOPRIO_CLASS_IDLE, 0)
+}
+
+/// Lowers the I/O priority of the calling thread alone, for the work nicer
+/// does on the program's behalf while its own priority is left alone.
+#[cfg(target_os = "macos")]
+pub fn background_io_thread() -> Result<()>{
+    darwin::set_iopolicy(darwin::IOPOL_TYPE_DISK, darwin::IOPOL_SCOPE_THREAD, darwin::IOPOL_THROTTLE).map_err(anyhow::Error::new)
+}
+
+/// Lowers the I/O priority of the calling thread alone, for the work nicer
+/// does on the program's behalf while its own priority is left alone.
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android", target_os = "macos"))))]
+pub fn background_io_thread() -> Result<()>{
+    anyhow::bail!("this system has no I/O priorities")
+}
+
+/// Lowers our own priority; whatever we spawn afterwards inherits it.
+#[cfg(unix)]
+pub fn nice_process(config: &PriorityConfig) -> ApplyReport{
+    set_background(0, config)
+}
+
+/// Adjustments the program makes to itself on its way in, between fork and
+/// exec. nicer has threads by then, and a lock one of them held at the fork
+/// stays held in the program for good, so everything is worked out ahead
+/// and nothing in between allocates, takes a lock or prints.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct ChildPriority {
+    /// What the error says was being done.
+    what: &'static str,
+    steps: Vec<Step>,
+    strict: bool,
+    /// A pipe the program writes the index of the step that stopped it to;
+    /// close-on-exec, so a program that got started never holds it.
+    reader: nix::libc::c_int,
+    writer: nix::libc::c_int,
+}
+
+#[cfg(unix)]
+impl ChildPriority {
+    fn new(what: &'static str, steps: Vec<Step>, strict: bool) -> Result<ChildPriority> {
+        use nix::libc;
+
+        let mut fds = [0; 2];
+
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(anyhow::Error::new(std::io::Error::last_os_error()).context("Unable to make a pipe for the program's priority"));
+        }
+
+        unsafe {
+            libc::fcntl(fds[0], libc::F_SETFD, libc::FD_CLOEXEC);
+            libc::fcntl(fds[1], libc::F_SETFD, libc::FD_CLOEXEC);
+            libc::fcntl(fds[0], libc::F_SETFL, libc::O_NONBLOCK);
+        }
+
+        Ok(ChildPriority { what, steps, strict, reader: fds[0], writer: fds[1] })
+    }
+
+    /// Makes the adjustments to ourselves, in the program between fork and
+    /// exec. Like `ApplyReport::check`, this fails if every adjustment did,
+    /// or with `strict` if any did.
+    fn run(&self) -> std::io::Result<()> {
+        let mut tried = 0;
+        let mut failed = 0;
+        let mut first = None;
+
+        for (index, step) in self.steps.iter().enumerate() {
+            let op = match step {
+                Step::Set(_, _, op) => op,
+                Step::Skip(_) => continue,
+            };
+
+            tried += 1;
+
+            match op.apply(0) {
+                Ok(()) => {}
+                Err(error) if op.tolerated(&error) => {}
+                Err(error) => {
+                    failed += 1;
+
+                    if first.is_none() {
+                        first = Some((index as u8, error));
+                    }
+
+                    if self.strict {
+                        break;
+                    }
+                }
+            }
+        }
+
+        match first {
+            Some((index, error)) if self.strict || failed == tried => {
+                unsafe {
+                    nix::libc::write(self.writer, &index as *const u8 as *const nix::libc::c_void, 1);
+                }
+
+                Err(error)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Explains `error`, which spawning the program failed with, if it was
+    /// one of these adjustments that failed.
+    pub fn failure(&self, error: &std::io::Error) -> Option<anyhow::Error> {
+        let mut index = 0u8;
+
+        if unsafe { nix::libc::read(self.reader, &mut index as *mut u8 as *mut nix::libc::c_void, 1) } != 1 {
+            return None;
+        }
+
+        let knob = match self.steps.get(index as usize)? {
+            Step::Set(knob, _, _) | Step::Skip(knob) => *knob,
+        };
+
+        Some(anyhow::Error::new(std::io::Error::from_raw_os_error(error.raw_os_error()?)).context(knob.to_string()).context(self.what))
+    }
+
+    /// Reads back what the program, started as `pid`, made of these
+    /// adjustments, and says under --verbose where that isn't what was asked
+    /// for. Any failure the program got past shows up here, as it can't
+    /// report one itself.
+    pub fn verify(&self, pid: u32) {
+        for step in &self.steps {
+            let (knob, requested) = match step {
+                Step::Set(knob, requested, _) => (*knob, requested),
+                Step::Skip(_) => continue,
+            };
+
+            match read_back(knob, pid) {
+                Some(actual) if actual != *requested => verbose!("the {} of pid {} reads back as {}, not the {} asked for", knob, pid, actual, requested),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ChildPriority {
+    fn drop(&mut self) {
+        unsafe {
+            nix::libc::close(self.reader);
+            nix::libc::close(self.writer);
+        }
+    }
+}
+
+/// Has the program lower its own priority on its way in, rather than
+/// inheriting ours. What's returned explains a failure to spawn it.
+#[cfg(unix)]
+pub fn nice_child(command: &mut std::process::Command, config: PriorityConfig, strict: bool) -> Result<std::sync::Arc<ChildPriority>> {
+    use std::os::unix::process::CommandExt;
+
+    let child = std::sync::Arc::new(ChildPriority::new("Unable to lower the priority", plan(&config), strict)?);
+    let hook = std::sync::Arc::clone(&child);
+
+    unsafe {
+        command.pre_exec(move || hook.run());
+    }
+
+    Ok(child)
+}
+
+/// Sets our own niceness to exactly `nice`.
+#[cfg(unix)]
+pub fn set_nice(nice: i32) -> Result<()>{
+    set_priority(nix::libc::PRIO_PROCESS as _, 0, nice)
+}
+
+/// Lowers the priority of an already running process, returning how each
+/// adjustment went.
+#[cfg(unix)]
+pub fn renice(pid: u32, config: &PriorityConfig) -> Result<ApplyReport>{
+    Ok(set_background(pid, config))
+}
+
+/// Sets every thread of `pid` not at niceness `nice` to it, as Linux keeps
+/// niceness per thread, and returns how many it changed.
+#[cfg(target_os = "linux")]
+pub fn nice_threads(pid: u32, nice: i32) -> usize {
+    let mut changed = 0;
+
+    for tid in crate::tree::threads(pid) {
+        if get_priority(nix::libc::PRIO_PROCESS as _, tid).is_ok_and(|current| current != nice) {
+            match set_priority(nix::libc::PRIO_PROCESS as _, tid, nice) {
+                Ok(()) => changed += 1,
+                Err(error) => verbose!("unable to renice thread {} of {}: {}", tid, pid, error),
+            }
+        }
+    }
+
+    changed
+}
+
+/// Puts a running process back at normal priority: nice 0, and on Linux the
+/// default I/O class, SCHED_OTHER and an oom_score_adj of 0.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn reset(pid: u32) -> Result<ApplyReport>{
+    use nix::libc;
+
+    let mut report = ApplyReport::default();
+    report.add(Knob::Nice, 0, set_priority(libc::PRIO_PROCESS as _, pid, 0));
+    report.add(Knob::Scheduler, linux::policy_name(linux::SCHED_OTHER, 0), linux::set_scheduler(pid, linux::SCHED_OTHER, 0).map_err(anyhow::Error::new));
+    report.add(Knob::Io, linux::io_name(linux::IOPRIO_CLASS_NONE, 0), linux::set_ioprio(pid, linux::IOPRIO_CLASS_NONE, 0).map_err(anyhow::Error::new));
+    report.add(Knob::Oom, 0, linux::set_oom_score_adj(pid, 0).map_err(anyhow::Error::new));
+    Ok(report.verify(pid).hint(RESTORE))
+}
+
+/// Puts a running process back at normal priority, out of Darwin's
+/// background band and at nice 0.
+#[cfg(all(unix, target_os = "macos"))]
+pub fn reset(pid: u32) -> Result<ApplyReport>{
+    use nix::libc;
+
+    let mut report = ApplyReport::default();
+    report.add(Knob::Background, false, set_priority(libc::PRIO_DARWIN_PROCESS, pid, 0));
+    report.add(Knob::Nice, 0, set_priority(libc::PRIO_PROCESS, pid, 0));
+    Ok(report.verify(pid).hint(RESTORE))
+}
+
+/// Puts a running process back at normal priority.
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android", target_os = "macos"))))]
+pub fn reset(pid: u32) -> Result<ApplyReport>{
+    let mut report = ApplyReport::default();
+    report.add(Knob::Nice, 0, set_priority(nix::libc::PRIO_PROCESS as _, pid, 0));
+    Ok(report.verify(pid).hint(RESTORE))
+}
+
+#[cfg(windows)]
+mod handle {
+    use winapi::um::winnt::HANDLE;
+
+    /// A process handle we opened ourselves, closed on drop.
+    pub struct Handle(pub HANDLE);
+
+    impl Handle {
+        pub fn open(pid: u32, access: u32) -> anyhow::Result<Handle> {
+            unsafe {
+                use winapi::shared::minwindef::FALSE;
+                use winapi::um::processthreadsapi::OpenProcess;
+
+                let handle = OpenProcess(access, FALSE, pid);
+
+                match handle.is_null() {
+                    false => Ok(Handle(handle)),
+                    true => Err(anyhow::Error::new(std::io::Error::last_os_error()))
+                }
+            }
+        }
+    }
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            unsafe {
+                winapi::um::handleapi::CloseHandle(self.0);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+fn set_priority_class(h_process: winapi::um::winnt::HANDLE, class: u32) -> Result<()>{
+    unsafe {
+        use winapi::shared::minwindef::{TRUE};
+        use winapi::um::processthreadsapi::{SetPriorityClass};
+
+        let status = SetPriorityClass(h_process, class);
+
+        match status {
+            TRUE => Ok(()),
+            _ => {
+                let error = std::io::Error::last_os_error();
+
+                match error.raw_os_error() {
+                    Some(0) => Ok(()),
+                    _ => Err(anyhow::Error::new(error))
+                }
+            }
+        }
+    }
+}
+
+/// Lowers the I/O priority of a process to very low, like Task Manager's
+/// background processes get. Children inherit it.
+#[cfg(windows)]
+fn background_io(h_process: winapi::um::winnt::HANDLE) -> Result<()>{
+    use winapi::shared::minwindef::ULONG;
+    use winapi::shared::ntdef::{NTSTATUS, PVOID};
+    use winapi::um::winnt::HANDLE;
+
+    // Not in winapi: the ProcessIoPriority class and its IoPriorityVeryLow value.
+    const PROCESS_IO_PRIORITY: u32 = 33;
+    const IO_PRIORITY_VERY_LOW: ULONG = 0;
+
+    #[link(name = "ntdll")]
+    extern "system" {
+        fn NtSetInformationProcess(ProcessHandle: HANDLE, ProcessInformationClass: u32, ProcessInformation: PVOID, ProcessInformationLength: ULONG) -> NTSTATUS;
+    }
+
+    let mut priority = IO_PRIORITY_VERY_LOW;
+    let status = unsafe {
+        NtSetInformationProcess(h_process, PROCESS_IO_PRIORITY, &mut priority as *mut _ as PVOID, std::mem::size_of::<ULONG>() as ULONG)
+    };
+
+    match status {
+        0 => Ok(()),
+        status => Err(anyhow::anyhow!("NtSetInformationProcess failed with status {:#x}", status)),
+    }
+}
+
+/// Lowers the memory priority of a process to very low, which background
+/// mode gives the calling process, so that its pages are the first to go.
+#[cfg(windows)]
+fn background_memory(h_process: winapi::um::winnt::HANDLE) -> Result<()>{
+    use winapi::shared::minwindef::{DWORD, LPVOID, ULONG};
+    use winapi::um::processthreadsapi::{ProcessMemoryPriority, SetProcessInformation};
+    use winapi::um::winnt::MEMORY_PRIORITY_VERY_LOW;
+
+    // MEMORY_PRIORITY_INFORMATION is a lone ULONG.
+    let mut priority: ULONG = MEMORY_PRIORITY_VERY_LOW;
+
+    match unsafe { SetProcessInformation(h_process, ProcessMemoryPriority, &mut priority as *mut _ as LPVOID, std::mem::size_of::<ULONG>() as DWORD) } {
+        0 => Err(anyhow::Error::new(std::io::Error::last_os_error())),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(windows)]
+fn set_background(h_process: winapi::um::winnt::HANDLE, config: &PriorityConfig) -> ApplyReport{
+    let mut report = ApplyReport::default();
+    let class = class_for_nice(config.nice);
+
+    match config.cpu {
+        true => report.add(Knob::PriorityClass, class_name(class), set_priority_class(h_process, class)),
+        false => report.skip(Knob::PriorityClass),
+    }
+
+    match config.io {
+        true => report.add(Knob::Io, "very-low", background_io(h_process)),
+        false => report.skip(Knob::Io),
+    }
+
+    if config.memory {
+        report.add(Knob::Memory, "very-low", background_memory(h_process));
+    }
+
+    if config.eco || (config.lowest && config.cpu) {
+        report.add(Knob::EcoQos, true, eco_qos(h_process));
+    }
+
+    report
+}
+
+/// Puts the calling thread in background mode, which lowers its I/O (and
+/// memory) priority, for the work nicer does on the program's behalf while
+/// its own priority is left alone.
+#[cfg(windows)]
+pub fn background_io_thread() -> Result<()>{
+    use winapi::um::processthreadsapi::{GetCurrentThread, SetThreadPriority};
+    use winapi::um::winbase::THREAD_MODE_BACKGROUND_BEGIN;
+
+    match unsafe { SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_BEGIN as _) } {
+        0 => Err(anyhow::Error::new(std::io::Error::last_os_error())),
+        _ => Ok(()),
+    }
+}
+
+/// Lowers our own priority; whatever we spawn afterwards inherits it.
+#[cfg(windows)]
+pub fn nice_process(config: &PriorityConfig) -> ApplyReport{
+    unsafe {
+        set_background(winapi::um::processthreadsapi::GetCurrentProcess(), config)
+    }
+}
+
+/// Lowers the priority of the program we just started.
+#[cfg(windows)]
+pub fn nice_child(h_process: winapi::um::winnt::HANDLE, config: &PriorityConfig, strict: bool) -> Result<()>{
+    set_background(h_process, config).check(strict)
+}
+
+/// The priority class `config` lowers CPU priority to, if it does.
+#[cfg(windows)]
+pub fn priority_class(config: &PriorityConfig) -> Option<u32> {
+    match config.cpu {
+        true => Some(class_for_nice(config.nice)),
+        false => None,
+    }
+}
+
+/// The priority class closest to a Unix niceness.
+#[cfg(windows)]
+fn class_for_nice(nice: i32) -> u32 {
+    use winapi::um::winbase::{ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, HIGH_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS};
+
+    match nice {
+        15..=i32::MAX => IDLE_PRIORITY_CLASS,
+        5..=14 => BELOW_NORMAL_PRIORITY_CLASS,
+        -4..=4 => NORMAL_PRIORITY_CLASS,
+        -14..=-5 => ABOVE_NORMAL_PRIORITY_CLASS,
+        _ => HIGH_PRIORITY_CLASS,
+    }
+}
+
+#[cfg(windows)]
+fn class_name(class: u32) -> &'static str {
+    use winapi::um::winbase::*;
+
+    match class {
+        IDLE_PRIORITY_CLASS => "idle",
+        BELOW_NORMAL_PRIORITY_CLASS => "below-normal",
+        NORMAL_PRIORITY_CLASS => "normal",
+        ABOVE_NORMAL_PRIORITY_CLASS => "above-normal",
+        HIGH_PRIORITY_CLASS => "high",
+        REALTIME_PRIORITY_CLASS => "realtime",
+        _ => "unknown",
+    }
+}
+
+/// Sets our own priority class to the one closest to niceness `nice`.
+#[cfg(windows)]
+pub fn set_nice(nice: i32) -> Result<()>{
+    unsafe {
+        set_priority_class(winapi::um::processthreadsapi::GetCurrentProcess(), class_for_nice(nice))
+    }
+}
+
+/// Lowers the priority of an already running process, returning how each
+/// adjustment went.
+#[cfg(windows)]
+pub fn renice(pid: u32, config: &PriorityConfig) -> Result<ApplyReport>{
+    use winapi::um::winnt::PROCESS_SET_INFORMATION;
+
+    let process = handle::Handle::open(pid, PROCESS_SET_INFORMATION)?;
+    Ok(set_background(process.0, config))
+}
+
+/// Puts a running process back at normal priority.
+#[cfg(windows)]
+pub fn reset(pid: u32) -> Result<ApplyReport>{
+    use winapi::um::winbase::NORMAL_PRIORITY_CLASS;
+    use winapi::um::winnt::PROCESS_SET_INFORMATION;
+
+    let process = handle::Handle::open(pid, PROCESS_SET_INFORMATION)?;
+    let mut report = ApplyReport::default();
+    report.add(Knob::PriorityClass, class_name(NORMAL_PRIORITY_CLASS), set_priority_class(process.0, NORMAL_PRIORITY_CLASS));
+    Ok(report)
+}
+
+/// Opts a running process into EcoQoS, which has Windows 11 prefer efficiency
+/// cores and lower clock speeds for it.
+#[cfg(windows)]
+pub fn eco_qos(h_process: winapi::um::winnt::HANDLE) -> Result<()>{
+    use winapi::shared::minwindef::{BOOL, DWORD, LPVOID, ULONG};
+    use winapi::um::winnt::HANDLE;
+
+    // Not in winapi yet.
+    const PROCESS_POWER_THROTTLING: u32 = 4;
+    const PROCESS_POWER_THROTTLING_CURRENT_VERSION: ULONG = 1;
+    const PROCESS_POWER_THROTTLING_EXECUTION_SPEED: ULONG = 1;
+
+    #[repr(C)]
+    struct ProcessPowerThrottlingState {
+        version: ULONG,
+        control_mask: ULONG,
+        state_mask: ULONG,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetProcessInformation(hProcess: HANDLE, ProcessInformationClass: u32, ProcessInformation: LPVOID, ProcessInformationSize: DWORD) -> BOOL;
+    }
+
+    let mut state = ProcessPowerThrottlingState {
+        version: PROCESS_POWER_THROTTLING_CURRENT_VERSION,
+        control_mask: PROCESS_POWER_THROTTLING_EXECUTION_SPEED,
+        state_mask: PROCESS_POWER_THROTTLING_EXECUTION_SPEED,
+    };
+
+    let status = unsafe {
+        SetProcessInformation(h_process, PROCESS_POWER_THROTTLING, &mut state as *mut _ as LPVOID, std::mem::size_of::<ProcessPowerThrottlingState>() as DWORD)
+    };
+
+    match status {
+        0 => Err(anyhow::Error::new(std::io::Error::last_os_error())),
+        _ => Ok(()),
+    }
+}
+
+/// The scheduling state of a process, as far as nicer is concerned.
+#[derive(Clone, Copy, Debug)]
+pub struct Priority {
+    #[cfg(unix)]
+    nice: i32,
+    /// I/O class and level.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    io: (i32, i32),
+    /// Scheduling policy and real-time priority.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    scheduler: (i32, i32),
+    #[cfg(all(unix, target_os = "macos"))]
+    background: bool,
+    #[cfg(windows)]
+    class: u32,
+}
+
+#[cfg(unix)]
+fn get_priority(which: nix::libc::c_int, who: u32) -> Result<i32> {
+    unsafe {
+        use nix::errno::Errno;
+        use nix::libc;
+
+        // -1 is a valid niceness, so errors can only be told apart through errno.
+        Errno::clear();
+        let value = libc::getpriority(which as _, who as libc::id_t);
+
+        match Errno::last() {
+            Errno::UnknownErrno => Ok(value),
+            errno => Err(anyhow::Error::new(std::io::Error::from(errno)))
+        }
+    }
+}
+
+/// The current value of `knob` for `who`, in the words the report asks for
+/// it in, if this platform can read it back.
+#[cfg(unix)]
+fn read_back(knob: Knob, who: u32) -> Option<String> {
+    use nix::libc;
+
+    match knob {
+        Knob::Nice => get_priority(libc::PRIO_PROCESS as _, who).ok().map(|nice| nice.to_string()),
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Knob::Io => linux::ioprio(who).ok().map(|(class, level)| linux::io_name(class, level)),
+        #[cfg(target_os = "macos")]
+        Knob::Background => get_priority(libc::PRIO_DARWIN_PROCESS, who).ok().map(|background| (background != 0).to_string()),
+        _ => None,
+    }
+}
+
+/// The niceness of `pid`.
+#[cfg(unix)]
+pub fn niceness(pid: u32) -> Result<i32> {
+    get_priority(nix::libc::PRIO_PROCESS as nix::libc::c_int, pid)
+}
+
+/// Whether `pid` is in the idle I/O class.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn idle_io(pid: u32) -> Result<bool> {
+    Ok(linux::ioprio(pid)?.0 == linux::IOPRIO_CLASS_IDLE)
+}
+
+/// Whether `pid` has got out from under `config`: to a lower niceness or
+/// out of the background band, or on Linux out of the idle I/O class.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn slipped(pid: u32, config: &PriorityConfig) -> bool {
+    #[cfg(target_os = "macos")]
+    if band(config) {
+        return get_priority(nix::libc::PRIO_DARWIN_PROCESS, pid).is_ok_and(|background| background == 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.io && config.io_class == IoClass::Idle && idle_io(pid).is_ok_and(|idle| !idle) {
+        return true;
+    }
+
+    config.cpu && niceness(pid).is_ok_and(|nice| nice < config.nice)
+}
+
+/// Whether `pid` has got out from under `config`, to a higher priority class.
+#[cfg(windows)]
+pub fn slipped(pid: u32, config: &PriorityConfig) -> bool {
+    use winapi::um::winbase::{ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, HIGH_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS};
+
+    // The classes' values don't sort, so rank them.
+    let rank = |class| [IDLE_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS, ABOVE_NORMAL_PRIORITY_CLASS, HIGH_PRIORITY_CLASS].iter().position(|known| *known == class).unwrap_or(5);
+
+    match (config.cpu, query(pid)) {
+        (true, Ok(priority)) => rank(priority.class) > rank(class_for_nice(config.nice)),
+        _ => false,
+    }
+}
+
+/// Our own niceness.
+#[cfg(unix)]
+pub fn current_nice() -> Result<i32> {
+    get_priority(nix::libc::PRIO_PROCESS as nix::libc::c_int, 0)
+}
+
+/// The niceness closest to our own priority class.
+#[cfg(windows)]
+pub fn current_nice() -> Result<i32> {
+    use winapi::um::winbase::{ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, HIGH_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, REALTIME_PRIORITY_CLASS};
+
+    unsafe {
+        match winapi::um::processthreadsapi::GetPriorityClass(winapi::um::processthreadsapi::GetCurrentProcess()) {
+            0 => Err(anyhow::Error::new(std::io::Error::last_os_error())),
+            IDLE_PRIORITY_CLASS => Ok(19),
+            BELOW_NORMAL_PRIORITY_CLASS => Ok(10),
+            ABOVE_NORMAL_PRIORITY_CLASS => Ok(-10),
+            HIGH_PRIORITY_CLASS | REALTIME_PRIORITY_CLASS => Ok(-20),
+            _ => Ok(0),
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn query(pid: u32) -> Result<Priority> {
+    use nix::libc;
+
+    Ok(Priority {
+        nice: get_priority(libc::PRIO_PROCESS as libc::c_int, pid)?,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        io: linux::ioprio(pid)?,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        scheduler: linux::scheduler(pid)?,
+        #[cfg(all(unix, target_os = "macos"))]
+        background: get_priority(libc::PRIO_DARWIN_PROCESS as libc::c_int, pid)? != 0,
+    })
+}
+
+#[cfg(windows)]
+pub fn query(pid: u32) -> Result<Priority> {
+    unsafe {
+        use winapi::um::processthreadsapi::GetPriorityClass;
+        use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+
+        let process = handle::Handle::open(pid, PROCESS_QUERY_LIMITED_INFORMATION)?;
+
+        match GetPriorityClass(process.0) {
+            0 => Err(anyhow::Error::new(std::io::Error::last_os_error())),
+            class => Ok(Priority { class })
+        }
+    }
+}
+
+impl Priority {
+    /// Gives `who` (0 for ourselves) this same priority.
+    #[cfg(unix)]
+    pub fn apply(&self, who: u32) -> ApplyReport {
+        apply_steps(&self.plan(), who)
+    }
+
+    /// The steps giving a process this same priority.
+    #[cfg(unix)]
+    fn plan(&self) -> Vec<Step> {
+        use nix::libc;
+
+        let mut steps = Vec::new();
+
+        #[cfg(target_os = "macos")]
+        steps.push(Step::Set(Knob::Background, self.background.to_string(), Op::Priority(libc::PRIO_DARWIN_PROCESS, if self.background { libc::PRIO_DARWIN_BG } else { 0 })));
+
+        steps.push(Step::Set(Knob::Nice, self.nice.to_string(), Op::Priority(libc::PRIO_PROCESS as _, self.nice)));
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            steps.push(Step::Set(Knob::Scheduler, linux::policy_name(self.scheduler.0, self.scheduler.1), Op::Scheduler(self.scheduler.0, self.scheduler.1)));
+            steps.push(Step::Set(Knob::Io, linux::io_name(self.io.0, self.io.1), Op::Ioprio(self.io.0, self.io.1)));
+        }
+
+        steps
+    }
+
+    /// Gives a process this same priority.
+    #[cfg(windows)]
+    pub fn apply(&self, h_process: winapi::um::winnt::HANDLE) -> ApplyReport {
+        let mut report = ApplyReport::default();
+        report.add(Knob::PriorityClass, class_name(self.class), set_priority_class(h_process, self.class));
+        report
+    }
+}
+
+/// Has the program take on `priority` on its way in, rather than inheriting
+/// ours. What's returned explains a failure to spawn it.
+#[cfg(unix)]
+pub fn copy_child(command: &mut std::process::Command, priority: Priority, strict: bool) -> Result<std::sync::Arc<ChildPriority>> {
+    use std::os::unix::process::CommandExt;
+
+    let child = std::sync::Arc::new(ChildPriority::new("Unable to copy the priority", priority.plan(), strict)?);
+    let hook = std::sync::Arc::clone(&child);
+
+    unsafe {
+        command.pre_exec(move || hook.run());
+    }
+
+    Ok(child)
+}
+
+impl fmt::Display for Priority {
+    #[cfg(unix)]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "nice: {}", self.nice)?;
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        write!(f, "\nI/O: {}\nscheduler: {}", linux::io_name(self.io.0, self.io.1), linux::policy_name(self.scheduler.0, self.scheduler.1))?;
+
+        #[cfg(all(unix, target_os = "macos"))]
+        write!(f, "\nbackground: {}", self.background)?;
+
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "priority class: {}", class_name(self.class))
+    }
+}
@@ -0,0 +1,93 @@
+use crate::duration::parse_duration;
+use anyhow::{anyhow, bail, Result};
+use std::fmt;
+use std::time::Duration;
+
+/// A niceness that goes from `from` to `to` over `over`, for --ramp.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ramp {
+    pub from: i32,
+    pub to: i32,
+    pub over: Duration,
+}
+
+impl Ramp {
+    /// The niceness `elapsed` into the ramp, in whole steps.
+    pub fn at(&self, elapsed: Duration) -> i32 {
+        if elapsed >= self.over {
+            return self.to;
+        }
+
+        let progress = elapsed.as_secs_f64() / self.over.as_secs_f64();
+
+        self.from + (f64::from(self.to - self.from) * progress).floor() as i32
+    }
+}
+
+fn nice(key: &str, text: &str) -> Result<i32> {
+    match text.trim().parse() {
+        Ok(nice) if (-20..=19).contains(&nice) => Ok(nice),
+        _ => bail!("Invalid {} {:?} in ramp, expected a niceness from -20 to 19", key, text),
+    }
+}
+
+impl std::str::FromStr for Ramp {
+    type Err = anyhow::Error;
+
+    /// Parses `from=0,to=19,over=5m`; `from` defaults to 0 and `to` to 19.
+    fn from_str(text: &str) -> Result<Ramp> {
+        let mut from = 0;
+        let mut to = 19;
+        let mut over = None;
+
+        for part in text.split(',') {
+            let (key, value) = part.split_once('=').ok_or_else(|| anyhow!("Expected key=value in ramp {:?}, got {:?}", text, part))?;
+
+            match key.trim() {
+                "from" => from = nice("from", value)?,
+                "to" => to = nice("to", value)?,
+                "over" => over = Some(parse_duration(value)?),
+                key => bail!("Unknown key {:?} in ramp {:?}, expected from, to or over", key, text),
+            }
+        }
+
+        let over = over.ok_or_else(|| anyhow!("Ramp {:?} needs over=DURATION", text))?;
+
+        if to < from {
+            bail!("Ramp {:?} would raise the priority; to must be at least from", text);
+        }
+
+        if over.is_zero() {
+            bail!("Ramp {:?} needs a duration longer than zero", text);
+        }
+
+        Ok(Ramp { from, to, over })
+    }
+}
+
+impl fmt::Display for Ramp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "from={},to={},over={}ms", self.from, self.to, self.over.as_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ramps_in_whole_steps() {
+        let ramp: Ramp = "from=0,to=19,over=5m".parse().unwrap();
+
+        assert_eq!(ramp, Ramp { from: 0, to: 19, over: Duration::from_secs(300) });
+        assert_eq!(ramp.at(Duration::ZERO), 0);
+        assert_eq!(ramp.at(Duration::from_secs(150)), 9);
+        assert_eq!(ramp.at(Duration::from_secs(299)), 18);
+        assert_eq!(ramp.at(Duration::from_secs(600)), 19);
+        assert_eq!(ramp.to_string().parse::<Ramp>().unwrap(), ramp);
+
+        for text in &["", "over=0s", "from=10,to=5,over=1m", "from=0,to=20,over=1m", "to=10", "over=1m,speed=2"] {
+            assert!(text.parse::<Ramp>().is_err(), "{:?} should not parse", text);
+        }
+    }
+}
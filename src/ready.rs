@@ -0,0 +1,50 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::process::Child;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long each connection attempt may take.
+const ATTEMPT: Duration = Duration::from_millis(250);
+
+/// How long to wait between connection attempts.
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// Resolves `address` (e.g. `localhost:8080`) to every address it stands for.
+pub fn resolve(address: &str) -> Result<Vec<SocketAddr>> {
+    let addresses: Vec<SocketAddr> = address.to_socket_addrs().with_context(|| format!("Unable to resolve {}", address))?.collect();
+
+    if addresses.is_empty() {
+        bail!("{} does not resolve to any address", address);
+    }
+
+    Ok(addresses)
+}
+
+/// Whether something accepts connections on any of `addresses`.
+pub fn accepting(addresses: &[SocketAddr]) -> bool {
+    addresses.iter().any(|address| TcpStream::connect_timeout(address, ATTEMPT).is_ok())
+}
+
+/// Waits until something accepts connections on `address` (e.g.
+/// `localhost:8080`), giving up after `timeout` or as soon as `child` exits.
+pub fn wait_for_port(address: &str, timeout: Duration, child: &mut Child) -> Result<()> {
+    let addresses = resolve(address)?;
+    let started = Instant::now();
+
+    loop {
+        if let Some(status) = child.try_wait().context("Unable to check on the program")? {
+            bail!("The program exited ({}) before {} accepted connections", status, address);
+        }
+
+        if accepting(&addresses) {
+            return Ok(());
+        }
+
+        if started.elapsed() >= timeout {
+            return Err(anyhow!("{} did not accept connections within {:?}", address, timeout));
+        }
+
+        thread::sleep(INTERVAL);
+    }
+}
@@ -0,0 +1,345 @@
+use crate::priority::{IoClass, PriorityConfig};
+use anyhow::{anyhow, bail, Context, Result};
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long systemd gets to move us into the new scope.
+const MOVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What goes into a transient scope, for --systemd-scope.
+pub struct Scope {
+    pub description: String,
+    pub priority: PriorityConfig,
+    pub memory_high: Option<u64>,
+    pub memory_max: Option<u64>,
+    /// A share of one CPU, in percent.
+    pub cpu_quota: Option<u32>,
+}
+
+/// A D-Bus value, of the few types the unit properties need.
+#[derive(Debug)]
+enum Value {
+    String(String),
+    U64(u64),
+    U32s(Vec<u32>),
+}
+
+impl Value {
+    fn signature(&self) -> &'static str {
+        match self {
+            Value::String(_) => "s",
+            Value::U64(_) => "t",
+            Value::U32s(_) => "au",
+        }
+    }
+}
+
+/// Builds a little-endian D-Bus message.
+#[derive(Default)]
+struct Writer {
+    buffer: Vec<u8>,
+}
+
+impl Writer {
+    fn align(&mut self, alignment: usize) {
+        self.buffer.resize(self.buffer.len().div_ceil(alignment) * alignment, 0);
+    }
+
+    fn byte(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.align(8);
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.buffer.extend_from_slice(value.as_bytes());
+        self.buffer.push(0);
+    }
+
+    fn signature(&mut self, value: &str) {
+        self.byte(value.len() as u8);
+        self.buffer.extend_from_slice(value.as_bytes());
+        self.buffer.push(0);
+    }
+
+    /// Writes an array whose elements are aligned to `alignment`, filling in
+    /// its length once `elements` have been written.
+    fn array(&mut self, alignment: usize, elements: impl FnOnce(&mut Writer)) {
+        self.u32(0);
+        let length_at = self.buffer.len() - 4;
+        self.align(alignment);
+        let start = self.buffer.len();
+
+        elements(self);
+
+        let length = (self.buffer.len() - start) as u32;
+        self.buffer[length_at..length_at + 4].copy_from_slice(&length.to_le_bytes());
+    }
+
+    fn variant(&mut self, value: &Value) {
+        self.signature(value.signature());
+
+        match value {
+            Value::String(value) => self.string(value),
+            Value::U64(value) => self.u64(*value),
+            Value::U32s(values) => self.array(4, |writer| values.iter().for_each(|value| writer.u32(*value))),
+        }
+    }
+
+    /// Writes an `a(sv)` of unit properties.
+    fn properties(&mut self, properties: &[(&str, Value)]) {
+        self.array(8, |writer| {
+            for (name, value) in properties {
+                writer.align(8);
+                writer.string(name);
+                writer.variant(value);
+            }
+        });
+    }
+}
+
+/// A method call on systemd's manager object.
+fn method_call(serial: u32, member: &str, signature: &str, body: &[u8]) -> Vec<u8> {
+    // Header fields, as (code, value).
+    let fields = [
+        (1, "o", "/org/freedesktop/systemd1"),
+        (2, "s", "org.freedesktop.systemd1.Manager"),
+        (3, "s", member),
+        (6, "s", "org.freedesktop.systemd1"),
+        (8, "g", signature),
+    ];
+
+    let mut message = Writer::default();
+    message.byte(b'l');
+    // METHOD_CALL, no flags, protocol version 1.
+    message.byte(1);
+    message.byte(0);
+    message.byte(1);
+    message.u32(body.len() as u32);
+    message.u32(serial);
+    message.array(8, |writer| {
+        for (code, kind, value) in fields {
+            writer.align(8);
+            writer.byte(code);
+            writer.signature(kind);
+
+            match kind {
+                "g" => writer.signature(value),
+                _ => writer.string(value),
+            }
+        }
+    });
+    message.align(8);
+    message.buffer.extend_from_slice(body);
+    message.buffer
+}
+
+/// The body of a StartTransientUnit call putting `pid` in a new scope.
+fn start_transient_unit(name: &str, scope: &Scope, pid: u32) -> Vec<u8> {
+    let mut properties = vec![("Description", Value::String(scope.description.clone())), ("PIDs", Value::U32s(vec![pid]))];
+
+    if scope.priority.cpu {
+        properties.push(("CPUWeight", Value::U64(crate::cgroup::cpu_weight(scope.priority.nice))));
+    }
+
+    // Only the idle class means the lowest weight; best-effort and the
+    // niceness-driven none class still get a share, so the default is left.
+    if scope.priority.io && scope.priority.io_class == IoClass::Idle {
+        properties.push(("IOWeight", Value::U64(1)));
+    }
+
+    if let Some(memory_high) = scope.memory_high {
+        properties.push(("MemoryHigh", Value::U64(memory_high)));
+    }
+
+    if let Some(memory_max) = scope.memory_max {
+        properties.push(("MemoryMax", Value::U64(memory_max)));
+    }
+
+    if let Some(quota) = scope.cpu_quota {
+        properties.push(("CPUQuotaPerSecUSec", Value::U64(quota as u64 * 10_000)));
+    }
+
+    let mut body = Writer::default();
+    body.string(name);
+    body.string("fail");
+    body.properties(&properties);
+    // No auxiliary units.
+    body.array(8, |_| {});
+    body.buffer
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Result<u32> {
+    let bytes = bytes.get(at..at + 4).ok_or_else(|| anyhow!("Truncated D-Bus message"))?;
+
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn string_at(bytes: &[u8], at: usize) -> Result<String> {
+    let length = u32_at(bytes, at)? as usize;
+    let string = bytes.get(at + 4..at + 4 + length).ok_or_else(|| anyhow!("Truncated D-Bus message"))?;
+
+    Ok(String::from_utf8_lossy(string).into_owned())
+}
+
+/// Reads the reply to our call, failing with the D-Bus error if it is one.
+fn read_reply(stream: &mut UnixStream) -> Result<()> {
+    loop {
+        let mut header = [0; 16];
+        stream.read_exact(&mut header)?;
+
+        if header[0] != b'l' {
+            bail!("systemd answered in big-endian D-Bus, which nicer doesn't speak");
+        }
+
+        let fields_length = u32_at(&header, 12)? as usize;
+        let body_length = u32_at(&header, 4)? as usize;
+        let body_at = (16 + fields_length).div_ceil(8) * 8;
+        let mut message = header.to_vec();
+        message.resize(body_at + body_length, 0);
+        stream.read_exact(&mut message[16..])?;
+
+        match header[1] {
+            // METHOD_RETURN
+            2 => return Ok(()),
+            // ERROR
+            3 => {
+                let name = error_name(&message, fields_length).unwrap_or_else(|| "an unknown error".to_string());
+
+                match string_at(&message, body_at) {
+                    Ok(details) if body_length > 0 => bail!("{}: {}", name, details),
+                    _ => bail!("{}", name),
+                }
+            }
+            // Signals aren't ours to handle.
+            _ => continue,
+        }
+    }
+}
+
+/// The ERROR_NAME header field of an error `message`.
+fn error_name(message: &[u8], fields_length: usize) -> Option<String> {
+    let end = 16 + fields_length;
+    let mut at = 16;
+
+    while at < end {
+        at = at.div_ceil(8) * 8;
+        let code = *message.get(at)?;
+        let signature_length = *message.get(at + 1)? as usize;
+        let signature = message.get(at + 2..at + 2 + signature_length)?.to_vec();
+        at += 3 + signature_length;
+
+        match signature.as_slice() {
+            b"s" | b"o" => {
+                at = at.div_ceil(4) * 4;
+                let value = string_at(message, at).ok()?;
+
+                if code == 4 {
+                    return Some(value);
+                }
+
+                at += 4 + value.len() + 1;
+            }
+            b"g" => at += 2 + *message.get(at)? as usize,
+            b"u" => at = at.div_ceil(4) * 4 + 4,
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+/// The private socket of the systemd instance that manages us: the system
+/// one for root, our user's otherwise.
+fn private_socket() -> Option<PathBuf> {
+    match nix::unistd::geteuid().is_root() {
+        true => Some(PathBuf::from("/run/systemd/private")),
+        false => env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join("systemd/private")),
+    }
+}
+
+/// Whether there's a systemd to ask for a scope.
+pub fn available() -> bool {
+    private_socket().is_some_and(|socket| socket.exists())
+}
+
+/// Moves nicer into a new transient scope unit, which the program then
+/// inherits; like `systemd-run --scope`, systemd does the resource control
+/// and the program shows up in `systemctl status`. Returns the unit's name.
+pub fn enter(scope: &Scope) -> Result<String> {
+    let pid = std::process::id();
+    let name = format!("nicer-{}.scope", pid);
+    let socket = private_socket().ok_or_else(|| anyhow!("XDG_RUNTIME_DIR isn't set"))?;
+    let mut stream = UnixStream::connect(&socket).with_context(|| format!("Unable to connect to systemd at {:?}", socket))?;
+
+    let uid: String = nix::unistd::geteuid().to_string().bytes().map(|byte| format!("{:02x}", byte)).collect();
+    stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", uid).as_bytes())?;
+
+    let mut line = Vec::new();
+    let mut byte = [0];
+
+    while !line.ends_with(b"\r\n") {
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+
+    if !line.starts_with(b"OK ") {
+        bail!("systemd turned us away: {}", String::from_utf8_lossy(&line).trim_end());
+    }
+
+    stream.write_all(b"BEGIN\r\n")?;
+    stream.write_all(&method_call(1, "StartTransientUnit", "ssa(sv)a(sa(sv))", &start_transient_unit(&name, scope, pid)))?;
+    read_reply(&mut stream).context("Unable to start a transient scope")?;
+
+    // The job that moves us may still be queued.
+    let started = Instant::now();
+
+    while started.elapsed() < MOVE_TIMEOUT {
+        if fs::read_to_string("/proc/self/cgroup").is_ok_and(|cgroup| cgroup.trim_end().ends_with(&format!("/{}", name))) {
+            return Ok(name);
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    Err(io::Error::from(io::ErrorKind::TimedOut)).with_context(|| format!("systemd never moved us into {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marshals_arrays_with_their_length() {
+        let mut writer = Writer::default();
+        writer.byte(1);
+        writer.array(8, |writer| writer.u64(7));
+
+        assert_eq!(writer.buffer, [1, 0, 0, 0, 8, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut writer = Writer::default();
+        writer.properties(&[("PIDs", Value::U32s(vec![42]))]);
+
+        #[rustfmt::skip]
+        assert_eq!(writer.buffer, [
+            24, 0, 0, 0, 0, 0, 0, 0,
+            4, 0, 0, 0, b'P', b'I', b'D', b's', 0,
+            2, b'a', b'u', 0, 0, 0, 0,
+            4, 0, 0, 0, 42, 0, 0, 0,
+        ]);
+    }
+}
@@ -0,0 +1,291 @@
+use anyhow::{anyhow, bail, Context, Result};
+use nix::libc;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+// Not in libc yet.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+#[repr(C)]
+struct SockFprog {
+    len: libc::c_ushort,
+    filter: *const SockFilter,
+}
+
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+#[cfg(target_arch = "x86_64")]
+const BPF_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+// Offsets into struct seccomp_data; both architectures are little-endian,
+// so the first argument's low half comes first.
+const DATA_NR: u32 = 0;
+const DATA_ARCH: u32 = 4;
+const DATA_ARG0: u32 = 16;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+/// Syscall numbers at or past this are the x32 ABI's.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+macro_rules! syscalls {
+    ($($name:ident)*) => { &[$((stringify!($name), libc::$name as i64)),*] };
+}
+
+/// Syscalls every architecture we filter on has.
+const SYSCALLS: &[(&str, i64)] = syscalls!(
+    SYS_accept SYS_accept4 SYS_acct SYS_add_key SYS_adjtimex SYS_bind SYS_bpf SYS_brk SYS_capget
+    SYS_capset SYS_chdir SYS_chroot SYS_clock_adjtime SYS_clock_getres SYS_clock_gettime
+    SYS_clock_nanosleep SYS_clock_settime SYS_clone SYS_close SYS_connect SYS_copy_file_range
+    SYS_delete_module SYS_dup SYS_dup3 SYS_epoll_create1 SYS_epoll_ctl SYS_epoll_pwait SYS_eventfd2
+    SYS_execve SYS_execveat SYS_exit SYS_exit_group SYS_faccessat SYS_fallocate SYS_fanotify_init
+    SYS_fanotify_mark SYS_fchdir SYS_fchmod SYS_fchmodat SYS_fchown SYS_fchownat SYS_fcntl
+    SYS_fdatasync SYS_fgetxattr SYS_finit_module SYS_flistxattr SYS_flock SYS_fremovexattr
+    SYS_fsetxattr SYS_fstat SYS_fstatfs SYS_fsync SYS_ftruncate SYS_futex SYS_get_mempolicy
+    SYS_get_robust_list SYS_getcpu SYS_getcwd SYS_getdents64 SYS_getegid SYS_geteuid SYS_getgid
+    SYS_getgroups SYS_getitimer SYS_getpeername SYS_getpgid SYS_getpid SYS_getppid SYS_getpriority
+    SYS_getrandom SYS_getresgid SYS_getresuid SYS_getrusage SYS_getsid SYS_getsockname
+    SYS_getsockopt SYS_gettid SYS_gettimeofday SYS_getuid SYS_getxattr SYS_init_module
+    SYS_inotify_add_watch SYS_inotify_init1 SYS_inotify_rm_watch SYS_io_cancel SYS_io_destroy
+    SYS_io_getevents SYS_io_setup SYS_io_submit SYS_ioctl SYS_ioprio_get SYS_ioprio_set SYS_kcmp
+    SYS_kexec_load SYS_keyctl SYS_kill SYS_lgetxattr SYS_linkat SYS_listen SYS_listxattr
+    SYS_llistxattr SYS_lookup_dcookie SYS_lremovexattr SYS_lseek SYS_lsetxattr SYS_madvise
+    SYS_mbind SYS_membarrier SYS_memfd_create SYS_migrate_pages SYS_mincore SYS_mkdirat SYS_mknodat
+    SYS_mlock SYS_mlock2 SYS_mlockall SYS_mmap SYS_mount SYS_move_pages SYS_mprotect
+    SYS_mq_getsetattr SYS_mq_notify SYS_mq_open SYS_mq_timedreceive SYS_mq_timedsend SYS_mq_unlink
+    SYS_mremap SYS_msgctl SYS_msgget SYS_msgrcv SYS_msgsnd SYS_msync SYS_munlock SYS_munlockall
+    SYS_munmap SYS_name_to_handle_at SYS_nanosleep SYS_newfstatat SYS_nfsservctl
+    SYS_open_by_handle_at SYS_openat SYS_perf_event_open SYS_personality SYS_pipe2 SYS_pivot_root
+    SYS_pkey_alloc SYS_pkey_free SYS_pkey_mprotect SYS_ppoll SYS_prctl SYS_pread64 SYS_preadv
+    SYS_preadv2 SYS_prlimit64 SYS_process_vm_readv SYS_process_vm_writev SYS_pselect6 SYS_ptrace
+    SYS_pwrite64 SYS_pwritev SYS_pwritev2 SYS_quotactl SYS_read SYS_readahead SYS_readlinkat
+    SYS_readv SYS_reboot SYS_recvfrom SYS_recvmmsg SYS_recvmsg SYS_remap_file_pages SYS_removexattr
+    SYS_renameat2 SYS_request_key SYS_restart_syscall SYS_rt_sigaction SYS_rt_sigpending
+    SYS_rt_sigprocmask SYS_rt_sigqueueinfo SYS_rt_sigreturn SYS_rt_sigsuspend SYS_rt_sigtimedwait
+    SYS_rt_tgsigqueueinfo SYS_sched_get_priority_max SYS_sched_get_priority_min
+    SYS_sched_getaffinity SYS_sched_getattr SYS_sched_getparam SYS_sched_getscheduler
+    SYS_sched_rr_get_interval SYS_sched_setaffinity SYS_sched_setattr SYS_sched_setparam
+    SYS_sched_setscheduler SYS_sched_yield SYS_seccomp SYS_semctl SYS_semget SYS_semop
+    SYS_semtimedop SYS_sendmmsg SYS_sendmsg SYS_sendto SYS_set_mempolicy SYS_set_robust_list
+    SYS_set_tid_address SYS_setdomainname SYS_setfsgid SYS_setfsuid SYS_setgid SYS_setgroups
+    SYS_sethostname SYS_setitimer SYS_setns SYS_setpgid SYS_setpriority SYS_setregid SYS_setresgid
+    SYS_setresuid SYS_setreuid SYS_setsid SYS_setsockopt SYS_settimeofday SYS_setuid SYS_setxattr
+    SYS_shmat SYS_shmctl SYS_shmdt SYS_shmget SYS_shutdown SYS_sigaltstack SYS_signalfd4 SYS_socket
+    SYS_socketpair SYS_splice SYS_statfs SYS_statx SYS_swapoff SYS_swapon SYS_symlinkat SYS_sync
+    SYS_syncfs SYS_sysinfo SYS_syslog SYS_tee SYS_tgkill SYS_timer_create SYS_timer_delete
+    SYS_timer_getoverrun SYS_timer_gettime SYS_timer_settime SYS_timerfd_create SYS_timerfd_gettime
+    SYS_timerfd_settime SYS_times SYS_tkill SYS_truncate SYS_umask SYS_umount2 SYS_uname
+    SYS_unlinkat SYS_unshare SYS_userfaultfd SYS_utimensat SYS_vhangup SYS_vmsplice SYS_wait4
+    SYS_waitid SYS_write SYS_writev
+);
+
+/// The older syscalls only x86_64 kept, such as open and fork.
+#[cfg(target_arch = "x86_64")]
+const LEGACY_SYSCALLS: &[(&str, i64)] = syscalls!(
+    SYS__sysctl SYS_access SYS_afs_syscall SYS_alarm SYS_arch_prctl SYS_chmod SYS_chown SYS_creat
+    SYS_create_module SYS_dup2 SYS_epoll_create SYS_epoll_ctl_old SYS_epoll_wait SYS_epoll_wait_old
+    SYS_eventfd SYS_fadvise64 SYS_fork SYS_futimesat SYS_get_kernel_syms SYS_get_thread_area
+    SYS_getdents SYS_getpgrp SYS_getpmsg SYS_getrlimit SYS_inotify_init SYS_ioperm SYS_iopl
+    SYS_kexec_file_load SYS_lchown SYS_link SYS_lstat SYS_mkdir SYS_mknod SYS_modify_ldt SYS_open
+    SYS_pause SYS_pipe SYS_poll SYS_putpmsg SYS_query_module SYS_readlink SYS_rename SYS_renameat
+    SYS_rmdir SYS_security SYS_select SYS_sendfile SYS_set_thread_area SYS_setrlimit SYS_signalfd
+    SYS_stat SYS_symlink SYS_sync_file_range SYS_sysfs SYS_time SYS_tuxcall SYS_unlink SYS_uselib
+    SYS_ustat SYS_utime SYS_utimes SYS_vfork SYS_vserver
+);
+
+/// The profiles --seccomp knows by name.
+pub const PROFILES: &[&str] = &["no-network", "no-ptrace"];
+
+/// What a filtered syscall gets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    Allow,
+    /// Fails with EPERM, which programs cope with far better than being
+    /// killed.
+    Deny,
+}
+
+impl Action {
+    fn value(self) -> u32 {
+        match self {
+            Action::Allow => SECCOMP_RET_ALLOW,
+            Action::Deny => SECCOMP_RET_ERRNO | libc::EPERM as u32,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Rule {
+    syscall: i64,
+    /// Let the call through anyway when its first argument is this.
+    unless_arg0: Option<u32>,
+}
+
+/// A seccomp filter: `rules` get the opposite of `default`.
+#[derive(Debug)]
+pub struct Filter {
+    default: Action,
+    rules: Vec<Rule>,
+}
+
+/// A custom filter spec, listing either the only syscalls allowed or the
+/// ones denied.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Spec {
+    allow: Option<Vec<String>>,
+    deny: Option<Vec<String>>,
+}
+
+fn syscall(name: &str) -> Result<i64> {
+    #[cfg(target_arch = "x86_64")]
+    let mut known = SYSCALLS.iter().chain(LEGACY_SYSCALLS);
+    #[cfg(not(target_arch = "x86_64"))]
+    let mut known = SYSCALLS.iter();
+
+    known.find(|(known, _)| known.strip_prefix("SYS_") == Some(name)).map(|(_, number)| *number).ok_or_else(|| anyhow!("Unknown syscall {:?}", name))
+}
+
+fn rules(names: &[&str]) -> Result<Vec<Rule>> {
+    names.iter().map(|name| Ok(Rule { syscall: syscall(name)?, unless_arg0: None })).collect()
+}
+
+fn parse(text: &str, json: bool) -> Result<Filter> {
+    let spec: Spec = match json {
+        true => serde_json::from_str(text)?,
+        false => toml::from_str(text)?,
+    };
+
+    let (default, names) = match (spec.allow, spec.deny) {
+        (Some(allow), None) => (Action::Deny, allow),
+        (None, Some(deny)) => (Action::Allow, deny),
+        _ => bail!("a filter spec sets exactly one of allow and deny"),
+    };
+
+    Ok(Filter { default, rules: rules(&names.iter().map(String::as_str).collect::<Vec<_>>())? })
+}
+
+/// Reads a built-in profile by name, or else a filter spec file: JSON if its
+/// name ends in `.json`, TOML otherwise.
+pub fn load(profile: &str) -> Result<Filter> {
+    match profile {
+        // Local sockets are fine, the network isn't.
+        "no-network" => Ok(Filter { default: Action::Allow, rules: vec![Rule { syscall: syscall("socket")?, unless_arg0: Some(libc::AF_UNIX as u32) }] }),
+        "no-ptrace" => Ok(Filter { default: Action::Allow, rules: rules(&["ptrace", "process_vm_readv", "process_vm_writev"])? }),
+        _ => {
+            let path = Path::new(profile);
+
+            if !path.is_file() {
+                bail!("Unknown seccomp profile {:?}, expected {} or a filter spec file", profile, PROFILES.join(", "));
+            }
+
+            let text = fs::read_to_string(path).with_context(|| format!("Unable to read seccomp filter {:?}", path))?;
+            let json = path.extension().is_some_and(|extension| extension == "json");
+
+            parse(&text, json).with_context(|| format!("Invalid seccomp filter {:?}", path))
+        }
+    }
+}
+
+impl Filter {
+    /// The BPF program, checking the architecture first so syscall numbers
+    /// mean what we think they do.
+    fn program(&self) -> Vec<SockFilter> {
+        let statement = |code, k| SockFilter { code, jt: 0, jf: 0, k };
+        let jump = |k, jt, jf| SockFilter { code: BPF_JEQ_K, jt, jf, k };
+        let matched = match self.default {
+            Action::Allow => Action::Deny,
+            Action::Deny => Action::Allow,
+        };
+
+        let mut program = vec![
+            statement(BPF_LD_W_ABS, DATA_ARCH),
+            jump(AUDIT_ARCH, 1, 0),
+            statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS),
+            statement(BPF_LD_W_ABS, DATA_NR),
+        ];
+
+        #[cfg(target_arch = "x86_64")]
+        program.extend(vec![SockFilter { code: BPF_JGE_K, jt: 0, jf: 1, k: X32_SYSCALL_BIT }, statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS)]);
+
+        for rule in &self.rules {
+            match rule.unless_arg0 {
+                None => program.extend(vec![jump(rule.syscall as u32, 0, 1), statement(BPF_RET_K, matched.value())]),
+                Some(arg0) => program.extend(vec![
+                    jump(rule.syscall as u32, 0, 4),
+                    statement(BPF_LD_W_ABS, DATA_ARG0),
+                    jump(arg0, 1, 0),
+                    statement(BPF_RET_K, matched.value()),
+                    statement(BPF_LD_W_ABS, DATA_NR),
+                ]),
+            }
+        }
+
+        program.push(statement(BPF_RET_K, self.default.value()));
+        program
+    }
+}
+
+/// Has the program start under `filter`. This sets no_new_privs, so setuid
+/// programs it runs don't gain privileges either. Register it last: the
+/// filter may deny what other pre_exec hooks still need.
+pub fn apply(command: &mut Command, filter: &Filter) {
+    let program = filter.program();
+
+    unsafe {
+        command.pre_exec(move || {
+            let fprog = SockFprog { len: program.len() as libc::c_ushort, filter: program.as_ptr() };
+
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, &fprog as *const SockFprog) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_specs() {
+        let filter = parse("deny = [\"ptrace\", \"mount\"]\n", false).unwrap();
+
+        assert_eq!(filter.default, Action::Allow);
+        assert_eq!(filter.rules, vec![Rule { syscall: libc::SYS_ptrace, unless_arg0: None }, Rule { syscall: libc::SYS_mount, unless_arg0: None }]);
+
+        let filter = parse("{\"allow\": [\"read\", \"write\", \"exit_group\"]}", true).unwrap();
+        assert_eq!(filter.default, Action::Deny);
+        assert_eq!(filter.rules.len(), 3);
+
+        assert!(parse("deny = [\"frobnicate\"]\n", false).is_err());
+        assert!(parse("allow = []\ndeny = []\n", false).is_err());
+        assert!(parse("", false).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use anyhow::{anyhow, Context, Result};
+use nix::libc;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering};
+
+/// PID of the program signals get relayed to, negated for its whole process
+/// group; zero until it has been spawned.
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+/// Set once we've been asked to stop restarting the program.
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// How many signals have been relayed to the program.
+static RELAYED: AtomicU32 = AtomicU32::new(0);
+
+/// The signal the program died of, for us to die of too on the way out.
+static FATAL: AtomicI32 = AtomicI32::new(0);
+
+/// Set when a suspended program has been asked to resume.
+static RESUME: AtomicBool = AtomicBool::new(false);
+
+/// What --forward-signals relays when it isn't given: what service
+/// managers, terminals and tmux send to stop, reload or resize the program.
+pub const FORWARDED: &[Signal] = &[Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP, Signal::SIGQUIT, Signal::SIGUSR1, Signal::SIGUSR2, Signal::SIGWINCH];
+
+/// Parses a signal name such as `TERM`, `SIGTERM` or `usr1`.
+pub fn signal_named(name: &str) -> Result<Signal> {
+    let name = name.trim().to_ascii_uppercase();
+    let name = if name.starts_with("SIG") { name } else { format!("SIG{}", name) };
+
+    Signal::from_str(&name).map_err(|_| anyhow!("Unknown signal {} on this platform", name))
+}
+
+/// Parses the name of a signal we can forward.
+pub fn parse_signal(name: &str) -> Result<Signal> {
+    match signal_named(name)? {
+        signal @ Signal::SIGKILL | signal @ Signal::SIGSTOP => Err(anyhow!("{} cannot be caught, so it cannot be forwarded", signal)),
+        signal => Ok(signal),
+    }
+}
+
+/// Relays signals to `pid` from now on, or to its process group if it leads
+/// one.
+fn relay_to(pid: u32, group: bool) {
+    CHILD.store(if group { -(pid as i32) } else { pid as i32 }, Ordering::SeqCst);
+}
+
+/// Whether we're in the foreground of our terminal, which then signals our
+/// whole process group (the program included) on Ctrl-C and the like.
+pub fn in_foreground() -> bool {
+    let group = unsafe { libc::getpgrp() };
+
+    (0..3).any(|fd| unsafe { libc::tcgetpgrp(fd) } == group)
+}
+
+/// How Linux lays out the start of a siginfo_t for a signal from kill():
+/// the sender follows three ints, aligned like the pointers elsewhere in
+/// the union. libc doesn't expose it on Android.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[repr(C)]
+struct Sent {
+    _head: [libc::c_int; 3],
+    _align: [*const libc::c_void; 0],
+    pid: libc::pid_t,
+}
+
+/// Who sent a signal, or 0 if the kernel (a terminal included) did.
+fn sender(info: &libc::siginfo_t) -> libc::pid_t {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    return unsafe { (*(info as *const libc::siginfo_t as *const Sent)).pid };
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    return info.si_pid;
+}
+
+/// Whether the terminal can send `signal` to its foreground process group.
+fn from_terminal(signal: libc::c_int) -> bool {
+    matches!(signal, libc::SIGINT | libc::SIGQUIT | libc::SIGHUP | libc::SIGWINCH)
+}
+
+extern "C" fn forward(signal: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    let pid = CHILD.load(Ordering::SeqCst);
+
+    // A program sharing our process group already got whatever the
+    // terminal sent it along with us; a second Ctrl-C reads as "force quit".
+    if pid > 0 && from_terminal(signal) && !info.is_null() && sender(unsafe { &*info }) == 0 {
+        return;
+    }
+
+    if pid != 0 {
+        unsafe {
+            libc::kill(pid, signal);
+        }
+
+        RELAYED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Ctrl-Z handler: stop the program, then ourselves, and wake the program
+/// back up once the shell continues us with `fg` or `bg`.
+///
+/// When the program shares our process group the terminal already stops it
+/// alongside us, and the extra SIGSTOP is harmless. When it runs in a process
+/// group of its own the terminal never sees it, so this is the only thing
+/// keeping the two halves of the job in step.
+extern "C" fn suspend(_: libc::c_int) {
+    let pid = CHILD.load(Ordering::SeqCst);
+
+    unsafe {
+        if pid != 0 {
+            libc::kill(pid, libc::SIGSTOP);
+        }
+
+        // SIGSTOP cannot be caught; execution resumes right here on SIGCONT.
+        libc::kill(libc::getpid(), libc::SIGSTOP);
+
+        if pid != 0 {
+            libc::kill(pid, libc::SIGCONT);
+        }
+    }
+}
+
+/// Makes shell job control (Ctrl-Z, `fg`, `bg`) work through us, for `pid`
+/// or, with `group`, the process group it leads.
+pub fn forward_job_control(pid: u32, group: bool) -> Result<()> {
+    relay_to(pid, group);
+
+    let action = SigAction::new(SigHandler::Handler(suspend), SaFlags::SA_RESTART, SigSet::empty());
+
+    unsafe {
+        sigaction(Signal::SIGTSTP, &action).context("Unable to set the handler for SIGTSTP")?;
+    }
+
+    Ok(())
+}
+
+/// Relays every signal in `signals` to `pid`, or with `group` to the process
+/// group it leads, for as long as we live.
+pub fn forward_signals(pid: u32, group: bool, signals: &[Signal]) -> Result<()> {
+    relay_to(pid, group);
+
+    let action = SigAction::new(SigHandler::SigAction(forward), SaFlags::SA_RESTART | SaFlags::SA_SIGINFO, SigSet::empty());
+
+    for signal in signals {
+        unsafe {
+            sigaction(*signal, &action).with_context(|| format!("Unable to set the handler for {}", signal))?;
+        }
+    }
+
+    Ok(())
+}
+
+extern "C" fn drain(_: libc::c_int) {
+    DRAINING.store(true, Ordering::SeqCst);
+}
+
+/// Has `signal` ask us to stop restarting the program once it exits.
+pub fn drain_on(signal: Signal) -> Result<()> {
+    let action = SigAction::new(SigHandler::Handler(drain), SaFlags::SA_RESTART, SigSet::empty());
+
+    unsafe {
+        sigaction(signal, &action).with_context(|| format!("Unable to set the handler for {}", signal))?;
+    }
+
+    Ok(())
+}
+
+/// Whether the drain signal has arrived.
+pub fn draining() -> bool {
+    DRAINING.load(Ordering::SeqCst)
+}
+
+/// How many signals have been relayed to the program so far.
+pub fn relayed() -> u32 {
+    RELAYED.load(Ordering::SeqCst)
+}
+
+extern "C" fn resume(_: libc::c_int) {
+    request_resume();
+}
+
+/// Has `signal` resume a --start-suspended program.
+pub fn resume_on(signal: Signal) -> Result<()> {
+    let action = SigAction::new(SigHandler::Handler(resume), SaFlags::SA_RESTART, SigSet::empty());
+
+    unsafe {
+        sigaction(signal, &action).with_context(|| format!("Unable to set the handler for {}", signal))?;
+    }
+
+    Ok(())
+}
+
+/// Asks for a suspended program to be resumed.
+pub fn request_resume() {
+    RESUME.store(true, Ordering::SeqCst);
+}
+
+/// Whether the program has been asked to resume since we last looked.
+pub fn take_resume() -> bool {
+    RESUME.swap(false, Ordering::SeqCst)
+}
+
+/// Has us die of `signal`, which the program died of, once we're done.
+pub fn die_of(signal: i32) {
+    FATAL.store(signal, Ordering::SeqCst);
+}
+
+/// Dies of the signal the program died of, if it did, so that whoever
+/// started us sees a signal death rather than an exit code. Any core dump
+/// was the program's to leave, so we don't leave one of our own. Returns if
+/// the signal doesn't kill us after all.
+pub fn reraise() {
+    let signal = FATAL.load(Ordering::SeqCst);
+
+    if signal == 0 {
+        return;
+    }
+
+    let none = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+
+    unsafe {
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, signal);
+
+        libc::setrlimit(libc::RLIMIT_CORE, &none);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::sigprocmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+        libc::raise(signal);
+    }
+}
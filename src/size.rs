@@ -0,0 +1,76 @@
+use anyhow::{anyhow, bail, Result};
+
+/// Parses a size in bytes such as `4G`, `512M`, `64KiB` or a bare `1048576`.
+///
+/// Suffixes are binary and case-insensitive: `K` is 1024 bytes, `M` is 1024
+/// `K` and so on up to `T`. A trailing `B` or `iB` is accepted and ignored.
+pub fn parse_size(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let digits = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+
+    if digits == 0 {
+        bail!("Expected a number in size {:?}", text);
+    }
+
+    let value: u64 = text[..digits].parse().map_err(|_| anyhow!("Size {:?} is too large", text))?;
+    let unit = text[digits..].trim().to_ascii_uppercase();
+    let unit = unit.strip_suffix("IB").or_else(|| unit.strip_suffix('B')).unwrap_or(&unit);
+
+    let shift = match unit {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => bail!("Unknown unit in size {:?} (use K, M, G or T)", text),
+    };
+
+    value.checked_mul(1 << shift).ok_or_else(|| anyhow!("Size {:?} is too large", text))
+}
+
+/// Formats a size in bytes for humans, e.g. `123.4 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["bytes", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} bytes", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1048576").unwrap(), 1 << 20);
+        assert_eq!(parse_size("64k").unwrap(), 64 << 10);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("4G").unwrap(), 4 << 30);
+        assert_eq!(parse_size("4GiB").unwrap(), 4 << 30);
+        assert_eq!(parse_size("2 TB").unwrap(), 2 << 40);
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format_size(512), "512 bytes");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(4 << 30), "4.0 GiB");
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        for text in &["", "G", "-1G", "1.5G", "4X", "99999999999T"] {
+            assert!(parse_size(text).is_err(), "{:?} should not parse", text);
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::fmt;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// What we know about a finished run, for --stats.
+pub struct Stats {
+    pub program: String,
+    pub pid: u32,
+    pub elapsed: Duration,
+    pub status: ExitStatus,
+    pub user: Option<Duration>,
+    pub system: Option<Duration>,
+    pub peak_memory: Option<u64>,
+    /// --max-cpu-time, to tell how close the program came to it.
+    pub cpu_limit: Option<Duration>,
+    pub politeness: Politeness,
+}
+
+/// How well the program kept to the priority it was given, sampled once a
+/// tick while it ran.
+#[derive(Debug, Default)]
+pub struct Politeness {
+    /// How many times we looked at its I/O class, and found it idle.
+    pub io_samples: u32,
+    pub idle_io: u32,
+    /// How many times it was found back above the niceness it was given,
+    /// if we ever looked.
+    pub escapes: Option<u32>,
+    /// How many times nicer lowered it, its threads or its descendants
+    /// again, with --thread-nice or --inherit-priority-to-descendants.
+    pub reasserted: Option<u32>,
+}
+
+impl Stats {
+    /// `peak_memory` overrides what the OS tells us, when we know better.
+    pub fn new(program: String, pid: u32, elapsed: Duration, status: ExitStatus, peak_memory: Option<u64>) -> Stats {
+        let (user, system, peak_rss) = rusage();
+
+        Stats { program, pid, elapsed, status, user, system, peak_memory: peak_memory.or(peak_rss), cpu_limit: None, politeness: Politeness::default() }
+    }
+
+    /// How many CPUs the program kept busy on average.
+    fn cpus(&self) -> Option<f64> {
+        match (self.user, self.system) {
+            (Some(user), Some(system)) if !self.elapsed.is_zero() => Some((user + system).as_secs_f64() / self.elapsed.as_secs_f64()),
+            _ => None,
+        }
+    }
+
+    /// The same summary, for --json.
+    pub fn to_json(&self) -> serde_json::Value {
+        #[cfg(unix)]
+        let signal = {
+            use std::os::unix::process::ExitStatusExt;
+            self.status.signal()
+        };
+        #[cfg(windows)]
+        let signal: Option<i32> = None;
+
+        let millis = |time: Option<Duration>| time.map(|time| time.as_millis() as u64);
+        let politeness = &self.politeness;
+
+        serde_json::json!({
+            "version": crate::priority::JSON_VERSION,
+            "program": self.program,
+            "pid": self.pid,
+            "elapsed_ms": self.elapsed.as_millis() as u64,
+            "code": self.status.code(),
+            "signal": signal,
+            "user_ms": millis(self.user),
+            "system_ms": millis(self.system),
+            "peak_memory": self.peak_memory,
+            "cpus": self.cpus(),
+            "io_samples": politeness.io_samples,
+            "idle_io_samples": politeness.idle_io,
+            "escapes": politeness.escapes,
+            "reasserted": politeness.reasserted,
+        })
+    }
+
+    fn outcome(&self) -> String {
+        if let Some(code) = self.status.code() {
+            return format!("exited with code {}", code);
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+
+            match self.status.signal() {
+                Some(signal) if self.status.core_dumped() => return format!("was killed by signal {} (core dumped)", signal),
+                Some(signal) => return format!("was killed by signal {}", signal),
+                None => {}
+            }
+        }
+
+        "exited".to_string()
+    }
+}
+
+/// CPU time and peak memory of the children we've waited for.
+#[cfg(unix)]
+fn rusage() -> (Option<Duration>, Option<Duration>, Option<u64>) {
+    use nix::libc;
+
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) } != 0 {
+        return (None, None, None);
+    }
+
+    let time = |time: libc::timeval| Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64);
+
+    // ru_maxrss is in kilobytes, except on Darwin where it's in bytes.
+    #[cfg(target_os = "macos")]
+    let scale = 1;
+    #[cfg(not(target_os = "macos"))]
+    let scale = 1024;
+
+    (Some(time(usage.ru_utime)), Some(time(usage.ru_stime)), Some(usage.ru_maxrss as u64 * scale))
+}
+
+#[cfg(windows)]
+fn rusage() -> (Option<Duration>, Option<Duration>, Option<u64>) {
+    (None, None, None)
+}
+
+/// CPU time used by the children we've waited for, user and system together.
+#[cfg(unix)]
+pub fn cpu_time() -> Option<Duration> {
+    match rusage() {
+        (Some(user), Some(system), _) => Some(user + system),
+        _ => None,
+    }
+}
+
+/// User and system CPU time `pid` has used so far.
+#[cfg(target_os = "macos")]
+pub fn cpu_times(pid: u32) -> Option<(Duration, Duration)> {
+    use nix::libc;
+    use std::mem;
+
+    // Not in libc yet, or only deprecated there.
+    #[repr(C)]
+    struct MachTimebaseInfo {
+        numer: u32,
+        denom: u32,
+    }
+
+    extern "C" {
+        fn proc_pidinfo(pid: libc::c_int, flavor: libc::c_int, arg: u64, buffer: *mut libc::c_void, size: libc::c_int) -> libc::c_int;
+        fn mach_timebase_info(info: *mut MachTimebaseInfo) -> libc::c_int;
+    }
+
+    unsafe {
+        let mut info: libc::proc_taskinfo = mem::zeroed();
+        let size = mem::size_of::<libc::proc_taskinfo>() as libc::c_int;
+
+        if proc_pidinfo(pid as libc::c_int, libc::PROC_PIDTASKINFO, 0, &mut info as *mut _ as *mut libc::c_void, size) != size {
+            return None;
+        }
+
+        // Task times are in Mach absolute time units, which are only
+        // nanoseconds on Intel.
+        let mut timebase = MachTimebaseInfo { numer: 1, denom: 1 };
+        mach_timebase_info(&mut timebase);
+        let time = |ticks: u64| Duration::from_nanos(ticks * timebase.numer as u64 / timebase.denom.max(1) as u64);
+
+        Some((time(info.pti_total_user), time(info.pti_total_system)))
+    }
+}
+
+/// User and kernel CPU time `pid` has used so far.
+#[cfg(windows)]
+pub fn cpu_times(pid: u32) -> Option<(Duration, Duration)> {
+    use std::mem;
+    use winapi::shared::minwindef::{FALSE, FILETIME};
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetProcessTimes, OpenProcess};
+    use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+
+    // FILETIMEs count 100 ns ticks.
+    let time = |time: FILETIME| Duration::from_nanos(((time.dwHighDateTime as u64) << 32 | time.dwLowDateTime as u64) * 100);
+
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid);
+
+        if process.is_null() {
+            return None;
+        }
+
+        let (mut creation, mut exit, mut kernel, mut user): (FILETIME, FILETIME, FILETIME, FILETIME) = mem::zeroed();
+        let got = GetProcessTimes(process, &mut creation, &mut exit, &mut kernel, &mut user);
+        CloseHandle(process);
+
+        match got {
+            FALSE => None,
+            _ => Some((time(user), time(kernel))),
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use crate::log::paint;
+        use owo_colors::Style;
+
+        let outcome = match self.status.success() {
+            true => paint(self.outcome(), Style::new().green()),
+            false => paint(self.outcome(), Style::new().red()),
+        };
+
+        write!(f, "nicer: {:?} (pid {}) {} after {}", self.program, paint(self.pid, Style::new().cyan()), outcome, paint(format!("{:.2?}", self.elapsed), Style::new().dimmed()))?;
+
+        if let (Some(user), Some(system)) = (self.user, self.system) {
+            write!(f, "\nnicer:   cpu time: {:.2?} user, {:.2?} system", user, system)?;
+
+            if let Some(limit) = self.cpu_limit {
+                write!(f, " ({:.0}% of the {:?} limit)", (user + system).as_secs_f64() * 100.0 / limit.as_secs_f64(), limit)?;
+            }
+        }
+
+        if let Some(peak) = self.peak_memory {
+            write!(f, "\nnicer:   peak memory: {}", crate::size::format_size(peak))?;
+        }
+
+        let times = |count: u32| match count {
+            1 => "once".to_string(),
+            count => format!("{} times", count),
+        };
+        let politeness = &self.politeness;
+        let mut notes = Vec::new();
+
+        if let Some(cpus) = self.cpus() {
+            notes.push(format!("{:.2} CPUs busy on average", cpus));
+        }
+
+        if politeness.io_samples > 0 {
+            notes.push(format!("idle I/O in {} of {} samples", politeness.idle_io, politeness.io_samples));
+        }
+
+        if let Some(escapes) = politeness.escapes {
+            notes.push(match escapes {
+                0 => "kept to its priority".to_string(),
+                escapes => format!("raised its priority {}", times(escapes)),
+            });
+        }
+
+        if let Some(reasserted) = politeness.reasserted {
+            notes.push(format!("lowered again {}", times(reasserted)));
+        }
+
+        if !notes.is_empty() {
+            write!(f, "\nnicer:   politeness: {}", notes.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
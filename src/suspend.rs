@@ -0,0 +1,105 @@
+use crate::ready;
+use anyhow::{bail, Context, Result};
+use std::process::Child;
+use std::thread;
+use std::time::Duration;
+
+/// How often we look for a reason to resume the program.
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// What sh runs to stop itself as soon as it starts, and become the program
+/// once it's continued, for --start-suspended. `$0` is the program.
+#[cfg(unix)]
+pub const SCRIPT: &str = "kill -STOP $$; exec \"$0\" \"$@\"";
+
+/// Waits for the program to have stopped itself, so that continuing it
+/// can't come too early. It's left waitable either way, for whoever reaps it.
+#[cfg(unix)]
+fn wait_until_stopped(pid: u32) -> Result<()> {
+    use nix::libc;
+
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+
+    loop {
+        match unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, libc::WSTOPPED | libc::WEXITED | libc::WNOWAIT) } {
+            0 => break,
+            _ => {
+                let error = std::io::Error::last_os_error();
+
+                if error.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(anyhow::Error::new(error).context("Unable to wait for the program to stop"));
+                }
+            }
+        }
+    }
+
+    match info.si_code {
+        libc::CLD_STOPPED => Ok(()),
+        _ => bail!("The program exited before it was resumed"),
+    }
+}
+
+/// Waits for a reason to resume the suspended program: something accepting
+/// connections on `port`, or (on Unix) the resume signal, `resume` on the
+/// control socket, or a signal relayed to the program. Returns which it was.
+pub fn wait(port: Option<&str>, child: &mut Child) -> Result<String> {
+    #[cfg(unix)]
+    wait_until_stopped(child.id())?;
+
+    let addresses = port.map(ready::resolve).transpose()?;
+    #[cfg(unix)]
+    let relayed = crate::signals::relayed();
+
+    loop {
+        if let Some(status) = child.try_wait().context("Unable to check on the program")? {
+            bail!("The program exited ({}) before it was resumed", status);
+        }
+
+        if let (Some(port), Some(addresses)) = (port, &addresses) {
+            if ready::accepting(addresses) {
+                return Ok(format!("{} accepts connections", port));
+            }
+        }
+
+        #[cfg(unix)]
+        if crate::signals::take_resume() {
+            return Ok("asked to".to_string());
+        }
+
+        #[cfg(unix)]
+        if crate::signals::relayed() != relayed {
+            return Ok("a signal for it arrived".to_string());
+        }
+
+        thread::sleep(INTERVAL);
+    }
+}
+
+/// Lets the suspended program run.
+#[cfg(unix)]
+pub fn resume(child: &Child) -> Result<()> {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGCONT).context("Unable to resume the program")
+}
+
+/// Lets the program, started with CREATE_SUSPENDED, run.
+#[cfg(windows)]
+pub fn resume(child: &Child) -> Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::shared::ntdef::NTSTATUS;
+    use winapi::um::winnt::HANDLE;
+
+    // Undocumented, but there since XP; std keeps the main thread's handle
+    // to itself, so ResumeThread is out.
+    #[link(name = "ntdll")]
+    extern "system" {
+        fn NtResumeProcess(ProcessHandle: HANDLE) -> NTSTATUS;
+    }
+
+    match unsafe { NtResumeProcess(child.as_raw_handle() as HANDLE) } {
+        0 => Ok(()),
+        status => bail!("NtResumeProcess failed with status {:#x}", status),
+    }
+}
@@ -0,0 +1,92 @@
+#[cfg(target_os = "macos")]
+use anyhow::Result;
+use nix::libc;
+use std::io;
+use std::process::Command;
+
+/// Has the kernel kill the program as soon as we die, of whatever cause.
+///
+/// The death signal is forgotten whenever credentials change, entering a
+/// user namespace included, so this has to come after anything that does.
+#[cfg(target_os = "linux")]
+pub fn tie(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    let parent = std::process::id() as libc::pid_t;
+
+    unsafe {
+        command.pre_exec(move || {
+            if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // We may have died before it was set.
+            match libc::getppid() == parent {
+                true => Ok(()),
+                false => Err(io::Error::from_raw_os_error(libc::ESRCH)),
+            }
+        });
+    }
+}
+
+/// Leaves a watcher (a second, hidden nicer) behind that kills `pid` should
+/// we die before it. It goes on its own once `pid` exits.
+#[cfg(target_os = "macos")]
+pub fn tie(pid: u32) -> Result<()> {
+    use anyhow::Context;
+    use std::os::unix::process::CommandExt;
+    use std::process::Stdio;
+
+    let mut watcher = Command::new(std::env::current_exe().context("Unable to find nicer itself")?)
+        .arg("__tie")
+        .arg(std::process::id().to_string())
+        .arg(pid.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // Out of reach of Ctrl-C and the like, which we live through.
+        .process_group(0)
+        .spawn()
+        .context("Unable to start the watcher for --tie-lifetime")?;
+
+    verbose!("killing {} should nicer die first, from a watcher (pid {})", pid, watcher.id());
+
+    std::thread::spawn(move || watcher.wait());
+    Ok(())
+}
+
+/// Waits for `parent` or `pid` to exit, and kills `pid` if `parent` went
+/// first; the watcher --tie-lifetime leaves behind on macOS.
+#[cfg(target_os = "macos")]
+pub fn watch(parent: u32, pid: u32) -> Result<i32> {
+    let queue = unsafe { libc::kqueue() };
+
+    if queue < 0 {
+        return Err(anyhow::Error::new(io::Error::last_os_error()).context("Unable to create a kqueue"));
+    }
+
+    let exit = |ident: u32| libc::kevent { ident: ident as libc::uintptr_t, filter: libc::EVFILT_PROC, flags: libc::EV_ADD | libc::EV_ONESHOT, fflags: libc::NOTE_EXIT, data: 0, udata: std::ptr::null_mut() };
+    let register = |ident: u32| unsafe { libc::kevent(queue, &exit(ident), 1, std::ptr::null_mut(), 0, std::ptr::null()) } == 0;
+
+    // Either of them may be gone already.
+    if !register(pid) {
+        return Ok(0);
+    }
+
+    let mut event = exit(0);
+    let orphaned = !register(parent) || loop {
+        match unsafe { libc::kevent(queue, std::ptr::null(), 0, &mut event, 1, std::ptr::null()) } {
+            1 => break event.ident == parent as libc::uintptr_t,
+            _ if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+            _ => return Err(anyhow::Error::new(io::Error::last_os_error()).context("Unable to wait for nicer and the program")),
+        }
+    };
+
+    if orphaned {
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+
+    Ok(0)
+}
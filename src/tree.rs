@@ -0,0 +1,173 @@
+#[cfg(target_os = "linux")]
+use nix::libc;
+#[cfg(any(target_os = "linux", windows))]
+use std::collections::HashMap;
+#[cfg(target_os = "linux")]
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::os::unix::process::ExitStatusExt;
+#[cfg(target_os = "linux")]
+use std::process::ExitStatus;
+
+/// Parent of every process we can see in /proc.
+#[cfg(target_os = "linux")]
+fn parents() -> HashMap<u32, u32> {
+    let mut parents = HashMap::new();
+
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return parents,
+    };
+
+    for entry in entries.flatten() {
+        let pid = match entry.file_name().to_str().and_then(|name| name.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+
+        // The command name in field 2 may contain anything, spaces and
+        // parentheses included, so fields are counted from its closing paren.
+        let stat = match fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+
+        let ppid = stat.rfind(')').and_then(|end| stat[end + 1..].split_whitespace().nth(1)).and_then(|ppid| ppid.parse().ok());
+
+        if let Some(ppid) = ppid {
+            parents.insert(pid, ppid);
+        }
+    }
+
+    parents
+}
+
+/// Parent of every process in a Toolhelp snapshot.
+#[cfg(windows)]
+fn parents() -> HashMap<u32, u32> {
+    use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+    use winapi::um::tlhelp32::{CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS};
+
+    let mut parents = HashMap::new();
+
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+
+        if snapshot == INVALID_HANDLE_VALUE {
+            return parents;
+        }
+
+        let mut entry: PROCESSENTRY32W = std::mem::zeroed();
+        entry.dwSize = std::mem::size_of::<PROCESSENTRY32W>() as u32;
+        let mut more = Process32FirstW(snapshot, &mut entry);
+
+        while more != 0 {
+            // Parent pids aren't cleared when the parent exits and its pid
+            // gets reused, so this can rarely adopt a stranger's children.
+            parents.insert(entry.th32ProcessID, entry.th32ParentProcessID);
+            more = Process32NextW(snapshot, &mut entry);
+        }
+
+        CloseHandle(snapshot);
+    }
+
+    parents
+}
+
+/// Every process below `root`, children before grandchildren.
+#[cfg(any(target_os = "linux", windows))]
+pub fn descendants(root: u32) -> Vec<u32> {
+    descendants_except(root, &[])
+}
+
+/// Every process below `root`, children before grandchildren, leaving out
+/// `pruned` and everything below them.
+#[cfg(any(target_os = "linux", windows))]
+pub fn descendants_except(root: u32, pruned: &[u32]) -> Vec<u32> {
+    let parents = parents();
+    let mut found = vec![root];
+    let mut next = 0;
+
+    while next < found.len() {
+        let parent = found[next];
+        found.extend(parents.iter().filter(|(pid, ppid)| **ppid == parent && !pruned.contains(pid)).map(|(pid, _)| *pid));
+        next += 1;
+    }
+
+    found.remove(0);
+    found
+}
+
+/// Every process below `root`, children before grandchildren.
+#[cfg(target_os = "macos")]
+pub fn descendants(root: u32) -> Vec<u32> {
+    use nix::libc;
+
+    extern "C" {
+        fn proc_listchildpids(ppid: libc::pid_t, buffer: *mut libc::c_void, buffersize: libc::c_int) -> libc::c_int;
+    }
+
+    let mut found = vec![root];
+    let mut next = 0;
+
+    while next < found.len() {
+        let mut children: Vec<libc::pid_t> = vec![0; 256];
+
+        // The count it returns is capped by the buffer, so grow until it fits.
+        let count = loop {
+            let count = unsafe { proc_listchildpids(found[next] as libc::pid_t, children.as_mut_ptr() as _, (children.len() * std::mem::size_of::<libc::pid_t>()) as libc::c_int) };
+
+            match count {
+                count if count < 0 => break 0,
+                count if count as usize >= children.len() => children.resize(children.len() * 2, 0),
+                count => break count as usize,
+            }
+        };
+
+        found.extend(children[..count].iter().map(|pid| *pid as u32));
+        next += 1;
+    }
+
+    found.remove(0);
+    found
+}
+
+/// Every thread of `pid`, itself included.
+#[cfg(target_os = "linux")]
+pub fn threads(pid: u32) -> Vec<u32> {
+    match fs::read_dir(format!("/proc/{}/task", pid)) {
+        Ok(entries) => entries.flatten().filter_map(|entry| entry.file_name().to_str().and_then(|name| name.parse().ok())).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Our own children, as the kernel lists them under each of our threads
+/// (an orphan goes to whichever of them is still alive).
+#[cfg(target_os = "linux")]
+fn own_children() -> Vec<u32> {
+    let entries = match fs::read_dir("/proc/self/task") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| fs::read_to_string(entry.path().join("children")).ok())
+        .flat_map(|children| children.split_whitespace().filter_map(|pid| pid.parse().ok()).collect::<Vec<u32>>())
+        .collect()
+}
+
+/// Reaps exited processes that were reparented to us as a subreaper, so they
+/// don't pile up as zombies. `ours`, the program and whatever else nicer
+/// started itself, are left alone for whoever waits on them.
+#[cfg(target_os = "linux")]
+pub fn reap_orphans(ours: &[u32]) {
+    for pid in own_children().into_iter().filter(|pid| !ours.contains(pid)) {
+        let mut status = 0;
+
+        // WNOHANG leaves the ones still running be.
+        if unsafe { libc::waitpid(pid as libc::pid_t, &mut status, libc::WNOHANG) } == pid as libc::pid_t {
+            verbose!("reaped orphan {} ({})", pid, ExitStatus::from_raw(status));
+        }
+    }
+}
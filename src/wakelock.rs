@@ -0,0 +1,303 @@
+use anyhow::Result;
+#[cfg(any(windows, target_os = "macos"))]
+use std::time::{Duration, Instant};
+
+/// What a wakelock keeps awake, for --caffeinate-type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Assertion {
+    /// The system, though the display may still sleep.
+    System,
+    /// The display, which keeps the system awake too.
+    Display,
+    /// The system and the display.
+    Full,
+    /// The disks (macOS).
+    Disk,
+}
+
+/// The names --caffeinate-type takes.
+pub const NAMES: &[&str] = &["system", "display", "full", "disk"];
+
+impl std::str::FromStr for Assertion {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Assertion> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "system" => Ok(Assertion::System),
+            "display" => Ok(Assertion::Display),
+            "full" => Ok(Assertion::Full),
+            "disk" => Ok(Assertion::Disk),
+            _ => Err(anyhow::anyhow!("Unknown caffeinate type {:?}, expected one of {}", text, NAMES.join(", "))),
+        }
+    }
+}
+
+impl Assertion {
+    pub fn name(self) -> &'static str {
+        match self {
+            Assertion::System => "system",
+            Assertion::Display => "display",
+            Assertion::Full => "full",
+            Assertion::Disk => "disk",
+        }
+    }
+}
+
+/// Keeps the system awake for as long as it's alive.
+#[cfg_attr(all(unix, not(target_os = "macos")), allow(dead_code))]
+pub struct WakeLock {
+    #[cfg(all(unix, target_os = "macos"))]
+    ids: Vec<u32>,
+}
+
+/// The IOKit assertion types behind `assertions`, without repeats.
+#[cfg(all(unix, target_os = "macos"))]
+fn assertion_types(assertions: &[Assertion]) -> Vec<&'static str> {
+    let mut types = Vec::new();
+
+    for assertion in assertions {
+        let names: &[&str] = match assertion {
+            Assertion::System => &["PreventUserIdleSystemSleep"],
+            Assertion::Display => &["PreventUserIdleDisplaySleep"],
+            Assertion::Full => &["PreventUserIdleSystemSleep", "PreventUserIdleDisplaySleep"],
+            Assertion::Disk => &["PreventDiskIdle"],
+        };
+
+        for name in names {
+            if !types.contains(name) {
+                types.push(*name);
+            }
+        }
+    }
+
+    types
+}
+
+#[cfg(all(unix,  target_os= "macos"))]
+pub fn wakelock(process: &str, pid: u32, assertions: &[Assertion]) -> Result<WakeLock> {
+    unsafe {
+        use core_foundation::string::{CFStringRef, CFStringCreateWithCString};
+        use core_foundation::date::{CFTimeInterval};
+        use nix::libc::{c_int};
+        use std::ffi::CString;
+
+        let named: CString = CString::new("nicer").unwrap();
+        let detailsd: CString = CString::new(format!("Hi from Rust! We're keeping your Mac awake on behalf of {:?} (pid {})", process, pid)).unwrap();
+        // let localizedd: CString = CString::new("Hello from Rust!").unwrap();
+
+        #[allow(non_snake_case, unused_variables)]
+        let kIOPMAssertionLevelOn : u32 = 255;
+        #[allow(non_snake_case, unused_variables)]
+        let kIOPMAssertionLevelOff: u32 = 0;
+        #[allow(non_snake_case)]
+        let kCFStringEncodingASCII: u32= 0x0600;
+        let name: CFStringRef = CFStringCreateWithCString(std::ptr::null(), named.as_ptr(), kCFStringEncodingASCII);
+        let details: CFStringRef = CFStringCreateWithCString(std::ptr::null(), detailsd.as_ptr(), kCFStringEncodingASCII);
+        // let localized: CFStringRef = CFStringCreateWithCString(std::ptr::null(), localizedd.as_ptr(), kCFStringEncodingASCII);
+
+        #[link(name = "IOKit", kind = "framework")]
+        extern "C" {
+            #[allow(dead_code)]
+            fn IOPMAssertionCreateWithName(AssertionType: CFStringRef, AssertionLevel: u32, AssertionName: CFStringRef, AssertionID: *mut u32) -> c_int;
+
+            fn IOPMAssertionCreateWithDescription(AssertionType: CFStringRef,  Name: CFStringRef, Details: CFStringRef,  HumanReadableReason: CFStringRef, LocalizationBundlePath: CFStringRef, Timeout: CFTimeInterval, TimeoutAction: CFStringRef, AssertionID: *mut u32) -> c_int;
+        }
+
+        // Whatever was created already is released if a later one fails.
+        let mut lock = WakeLock { ids: Vec::new() };
+
+        for kind in assertion_types(assertions) {
+            let kind_name: CString = CString::new(kind).unwrap();
+            let assertion_type: CFStringRef = CFStringCreateWithCString(std::ptr::null(), kind_name.as_ptr(), kCFStringEncodingASCII);
+
+            let mut id : u32 = 0;
+            // HumanReadableReason is ignored if non localizable
+            let status = IOPMAssertionCreateWithDescription(assertion_type, name, details, std::ptr::null(), std::ptr::null(), 0.0, std::ptr::null(), &mut id);
+
+            match status {
+                // kIOReturnSuccess
+                0 => lock.ids.push(id),
+                _ => return Err(anyhow::anyhow!("IOPMAssertionCreateWithDescription failed for {} with IOReturn {:#x}", kind, status))
+            }
+        }
+
+        Ok(lock)
+    }
+}
+
+#[cfg(all(unix,  target_os= "macos"))]
+impl Drop for WakeLock {
+    fn drop(&mut self) {
+        unsafe {
+            use nix::libc::{c_int};
+
+            #[link(name = "IOKit", kind = "framework")]
+            extern "C" {
+                fn IOPMAssertionRelease(AssertionID: u32) -> c_int;
+            }
+
+            for id in &self.ids {
+                IOPMAssertionRelease(*id);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+pub fn wakelock(_process: &str, _pid: u32, assertions: &[Assertion]) -> Result<WakeLock> {
+    unsafe {
+        use winapi::um::winbase::{SetThreadExecutionState};
+        use winapi::um::winnt::{ES_CONTINUOUS, ES_DISPLAY_REQUIRED, ES_SYSTEM_REQUIRED};
+
+        let mut state = ES_CONTINUOUS;
+
+        for assertion in assertions {
+            state |= match assertion {
+                Assertion::System => ES_SYSTEM_REQUIRED,
+                Assertion::Display | Assertion::Full => ES_SYSTEM_REQUIRED | ES_DISPLAY_REQUIRED,
+                Assertion::Disk => {
+                    warning!("Windows has no way to keep just the disks awake, skipping that");
+                    0
+                }
+            };
+        }
+
+        match SetThreadExecutionState(state) {
+            0 => Err(anyhow::Error::new(std::io::Error::last_os_error())),
+            _ => Ok(WakeLock {})
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for WakeLock {
+    fn drop(&mut self) {
+        unsafe {
+            use winapi::um::winbase::{SetThreadExecutionState};
+            use winapi::um::winnt::{ES_CONTINUOUS};
+
+            SetThreadExecutionState(ES_CONTINUOUS);
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn wakelock(_process: &str, _pid: u32, _assertions: &[Assertion]) -> Result<WakeLock> {
+    Err(anyhow::anyhow!("Linux has no caffeine, sadly."))
+}
+
+/// Keeps the system awake until `pid` exits, as the holder --detach leaves
+/// behind for --caffeinate. The first line on stdout tells nicer whether
+/// the wakelock was taken.
+pub fn hold(process: &str, pid: u32, assertions: &[Assertion]) -> Result<i32> {
+    let _lock = match wakelock(process, pid, assertions) {
+        Ok(lock) => lock,
+        Err(error) => {
+            println!("error: {:#}", error);
+            return Ok(1);
+        }
+    };
+
+    println!("held");
+    wait_for_exit(pid);
+    Ok(0)
+}
+
+/// Waits for a process that isn't our child, so can't be waited on.
+#[cfg(unix)]
+fn wait_for_exit(pid: u32) {
+    use nix::libc;
+
+    loop {
+        let gone = unsafe { libc::kill(pid as libc::pid_t, 0) } != 0 && std::io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH);
+
+        if gone {
+            return;
+        }
+
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+}
+
+#[cfg(windows)]
+fn wait_for_exit(pid: u32) {
+    unsafe {
+        use winapi::um::handleapi::CloseHandle;
+        use winapi::um::processthreadsapi::OpenProcess;
+        use winapi::um::synchapi::WaitForSingleObject;
+        use winapi::um::winbase::INFINITE;
+        use winapi::um::winnt::SYNCHRONIZE;
+
+        let process = OpenProcess(SYNCHRONIZE, 0, pid);
+
+        // Already gone.
+        if process.is_null() {
+            return;
+        }
+
+        WaitForSingleObject(process, INFINITE);
+        CloseHandle(process);
+    }
+}
+
+/// How long the program has to sit idle before --caffeinate-when-busy lets
+/// the system sleep.
+#[cfg(any(windows, target_os = "macos"))]
+const IDLE_GRACE: Duration = Duration::from_secs(10);
+
+/// A wakelock held only while the program keeps a CPU at least `threshold`
+/// percent busy.
+#[cfg(any(windows, target_os = "macos"))]
+pub struct BusyWakeLock {
+    process: String,
+    pid: u32,
+    threshold: u32,
+    assertions: Vec<Assertion>,
+    last: Option<(Duration, Instant)>,
+    idle_since: Option<Instant>,
+    lock: Option<WakeLock>,
+    failed: bool,
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+impl BusyWakeLock {
+    pub fn new(process: &str, pid: u32, threshold: u32, assertions: &[Assertion]) -> BusyWakeLock {
+        BusyWakeLock { process: process.to_string(), pid, threshold, assertions: assertions.to_vec(), last: None, idle_since: None, lock: None, failed: false }
+    }
+
+    /// Takes or lets go of the wakelock, given the CPU time the program has
+    /// used so far.
+    pub fn sample(&mut self, cpu: Duration) {
+        let now = Instant::now();
+        let last = self.last.replace((cpu, now));
+
+        let (last_cpu, last_at) = match last {
+            Some(last) => last,
+            None => return,
+        };
+
+        let busy = cpu.saturating_sub(last_cpu).as_secs_f64() * 100.0 >= now.duration_since(last_at).as_secs_f64() * self.threshold as f64;
+
+        if busy {
+            self.idle_since = None;
+        }
+
+        match (busy, &self.lock) {
+            (true, None) if !self.failed => match wakelock(&self.process, self.pid, &self.assertions) {
+                Ok(lock) => {
+                    verbose!("{:?} is busy, keeping the system awake", self.process);
+                    self.lock = Some(lock);
+                }
+                Err(error) => {
+                    warning!("unable to keep the system awake: {}", error);
+                    self.failed = true;
+                }
+            },
+            (false, Some(_)) if now.duration_since(*self.idle_since.get_or_insert(now)) >= IDLE_GRACE => {
+                verbose!("{:?} is idle, letting the system sleep", self.process);
+                self.lock = None;
+            }
+            _ => {}
+        }
+    }
+}
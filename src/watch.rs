@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How long the watched files have to stay unchanged before a change counts,
+/// so that saving a dozen files or a checkout counts once.
+const SETTLE: Duration = Duration::from_millis(300);
+
+/// What we know of one file: when it was last modified, and its size.
+type Stamp = (Option<SystemTime>, u64);
+
+/// The files under the --watch paths, noticing when any of them are added,
+/// removed or modified.
+///
+/// It polls modification times rather than asking the OS for events, which
+/// is plenty for source trees and works the same everywhere, network
+/// filesystems included. Symlinks aren't followed, and files and directories
+/// whose name starts with a dot (`.git`, editor swap files) are skipped.
+pub struct Watch {
+    paths: Vec<PathBuf>,
+    stamps: BTreeMap<PathBuf, Stamp>,
+}
+
+impl Watch {
+    pub fn new(paths: &[PathBuf]) -> Watch {
+        Watch { paths: paths.to_vec(), stamps: scan(paths) }
+    }
+
+    /// Whether anything changed since we last looked, waiting for the
+    /// files to settle down if so.
+    pub fn changed(&mut self) -> bool {
+        if !self.rescan() {
+            return false;
+        }
+
+        loop {
+            thread::sleep(SETTLE);
+
+            if !self.rescan() {
+                return true;
+            }
+        }
+    }
+
+    fn rescan(&mut self) -> bool {
+        let stamps = scan(&self.paths);
+        let changed = stamps != self.stamps;
+        self.stamps = stamps;
+        changed
+    }
+}
+
+fn scan(paths: &[PathBuf]) -> BTreeMap<PathBuf, Stamp> {
+    let mut stamps = BTreeMap::new();
+
+    for path in paths {
+        visit(path, &mut stamps);
+    }
+
+    stamps
+}
+
+fn visit(path: &Path, stamps: &mut BTreeMap<PathBuf, Stamp>) {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return,
+    };
+
+    if metadata.is_dir() {
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for entry in entries.flatten() {
+            if !entry.file_name().to_string_lossy().starts_with('.') {
+                visit(&entry.path(), stamps);
+            }
+        }
+    } else {
+        stamps.insert(path.to_path_buf(), (metadata.modified().ok(), metadata.len()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notices_changes() {
+        let dir = std::env::temp_dir().join(format!("nicer-watch-{}", std::process::id()));
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join("main.rs"), "fn main() {}").unwrap();
+
+        let mut watch = Watch::new(std::slice::from_ref(&dir));
+        assert!(!watch.changed());
+
+        fs::write(dir.join(".git").join("index"), "ignored").unwrap();
+        assert!(!watch.changed());
+
+        fs::write(dir.join("main.rs"), "fn main() { println!(); }").unwrap();
+        assert!(watch.changed());
+        assert!(!watch.changed());
+
+        fs::write(dir.join("lib.rs"), "").unwrap();
+        assert!(watch.changed());
+
+        fs::remove_file(dir.join("lib.rs")).unwrap();
+        assert!(watch.changed());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,512 @@
+//! Runs nicer against the nicer-fixture program and checks what it saw.
+
+use std::fs;
+use std::process::{Command, Output};
+
+fn nicer() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_nicer"))
+}
+
+const FIXTURE: &str = env!("CARGO_BIN_EXE_nicer-fixture");
+
+fn run(args: &[&str]) -> Output {
+    nicer().args(args).output().expect("unable to run nicer")
+}
+
+fn stdout(output: &Output) -> Vec<String> {
+    String::from_utf8_lossy(&output.stdout).lines().map(String::from).collect()
+}
+
+#[test]
+fn passes_exit_codes_through() {
+    assert_eq!(run(&[FIXTURE, "exit", "0"]).status.code(), Some(0));
+    assert_eq!(run(&[FIXTURE, "exit", "3"]).status.code(), Some(3));
+}
+
+#[test]
+fn rewrites_exit_codes() {
+    assert_eq!(run(&["--map-exit", "4=0,5=2", FIXTURE, "exit", "4"]).status.code(), Some(0));
+    assert_eq!(run(&["--map-exit", "4=0,5=2", FIXTURE, "exit", "5"]).status.code(), Some(2));
+    assert_eq!(run(&["--map-exit", "4=0", FIXTURE, "exit", "6"]).status.code(), Some(6));
+    assert_eq!(run(&["--exit-zero", FIXTURE, "exit", "7"]).status.code(), Some(0));
+}
+
+#[test]
+fn restarts_failing_programs() {
+    let output = run(&["--restart-on-failure", "--max-restarts", "1", FIXTURE, "ready", "exit", "3"]);
+
+    assert_eq!(stdout(&output), vec!["ready", "ready"]);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn restarts_unhealthy_programs() {
+    let output = run(&["--health-check", "exit 1", "--health-interval", "1s", "--unhealthy-threshold", "1", "--max-restarts", "1", FIXTURE, "ready", "sleep", "30"]);
+
+    assert_eq!(stdout(&output), vec!["ready", "ready"]);
+    assert_eq!(output.status.code(), Some(125));
+}
+
+#[test]
+fn leaves_the_program_arguments_alone() {
+    let output = run(&[FIXTURE, "echo", "-v", "--cwd", "x"]);
+
+    assert_eq!(stdout(&output), vec!["-v", "--cwd", "x"]);
+}
+
+#[test]
+fn prefixes_the_program_output() {
+    let output = run(&["--prefix", "job", FIXTURE, "echo", "one", "two"]);
+
+    assert_eq!(stdout(&output), vec!["[job] one", "[job] two"]);
+}
+
+#[test]
+fn discards_the_program_output() {
+    let output = run(&["--quiet-child", "--stats", FIXTURE, "echo", "noise"]);
+
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("exited with code 0"));
+}
+
+#[test]
+fn reports_stats_as_json() {
+    let output = run(&["--stats", "--json", FIXTURE, "sleep", "1.5", "exit", "3"]);
+    let stats: serde_json::Value = serde_json::from_str(String::from_utf8_lossy(&output.stderr).lines().last().unwrap()).unwrap();
+
+    assert_eq!(stats["version"], 1);
+    assert_eq!(stats["code"], 3);
+    #[cfg(unix)]
+    assert_eq!(stats["escapes"], 0);
+}
+
+#[test]
+fn tees_the_program_output() {
+    let path = std::env::temp_dir().join(format!("nicer-test-{}.log", std::process::id()));
+    let output = run(&["--tee", path.to_str().unwrap(), FIXTURE, "echo", "one", "two"]);
+    let teed = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(stdout(&output), vec!["one", "two"]);
+    assert_eq!(teed, "one\ntwo\n");
+}
+
+#[test]
+fn runs_the_program_in_cwd() {
+    let cwd = std::env::temp_dir().canonicalize().unwrap();
+    let output = run(&["--cwd", cwd.to_str().unwrap(), FIXTURE, "cwd"]);
+
+    assert_eq!(stdout(&output), vec![cwd.display().to_string()]);
+}
+
+#[cfg(unix)]
+#[test]
+fn renames_the_program() {
+    assert_eq!(stdout(&run(&["--argv0", "-fixture", FIXTURE, "argv0"])), vec!["-fixture"]);
+}
+
+#[test]
+fn runs_batches() {
+    let path = std::env::temp_dir().join(format!("nicer-test-{}.batch", std::process::id()));
+    fs::write(&path, format!("# two at a time\n\"{0}\" sleep 0.2 echo slow\n\"{0}\" echo fast\n\"{0}\" exit 2\n", FIXTURE)).unwrap();
+
+    let output = run(&["--batch", path.to_str().unwrap(), "--concurrency", "2"]);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(stdout(&output), vec!["fast", "slow"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("batch of 3: 2 succeeded, 1 failed"));
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn runs_job_files() {
+    let path = std::env::temp_dir().join(format!("nicer-test-{}.json", std::process::id()));
+    let job = format!(r#"{{"program": {:?}, "args": ["env", "NICER_TEST", "exit", "4"], "env": {{"NICER_TEST": "hello"}}}}"#, FIXTURE);
+    fs::write(&path, job).unwrap();
+
+    let output = run(&["--job-file", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(stdout(&output), vec!["hello"]);
+    assert_eq!(output.status.code(), Some(4));
+}
+
+#[cfg(unix)]
+#[test]
+fn dies_of_the_signal_that_ended_the_program() {
+    use std::os::unix::process::ExitStatusExt;
+
+    assert_eq!(run(&[FIXTURE, "raise", "11"]).status.signal(), Some(11));
+    assert_eq!(run(&[FIXTURE, "raise", "15"]).status.signal(), Some(15));
+
+    // Unless asked to pass it on as an exit code.
+    assert_eq!(run(&["--map-exit", "143=0", FIXTURE, "raise", "15"]).status.code(), Some(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_scripts_without_a_shebang_under_sh() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("nicer-test-{}.sh", std::process::id()));
+    fs::write(&path, "echo \"$@\"\n").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let refused = run(&[path.to_str().unwrap(), "hello"]);
+    let output = run(&["--exec-fallback", path.to_str().unwrap(), "hello"]);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(refused.status.code(), Some(126));
+    assert_eq!(stdout(&output), vec!["hello"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn backgrounds_the_program() {
+    assert_eq!(stdout(&run(&[FIXTURE, "nice"])), vec!["19"]);
+
+    let ours = unsafe { nix::libc::getpriority(nix::libc::PRIO_PROCESS as _, 0) };
+    assert_eq!(stdout(&run(&["--no-nice", FIXTURE, "nice"])), vec![ours.to_string()]);
+}
+
+#[cfg(unix)]
+#[test]
+fn backgrounds_the_program_past_the_wrapper() {
+    assert_eq!(stdout(&run(&["--wrapper-nice", "10", FIXTURE, "nice"])), vec!["19"]);
+    assert_eq!(stdout(&run(&["--child-only", FIXTURE, "nice"])), vec!["19"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn renices_to_the_requested_niceness() {
+    assert_eq!(stdout(&run(&["--nice", "10", FIXTURE, "nice"])), vec!["10"]);
+    assert!(!run(&["--nice", "20", FIXTURE, "nice"]).status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn renices_to_the_requested_priority_class() {
+    assert_eq!(stdout(&run(&["--priority", "below-normal", FIXTURE, "nice"])), vec!["10"]);
+    assert!(!run(&["--priority", "below-normal", "--nice", "5", FIXTURE, "nice"]).status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn renices_relative_to_ours() {
+    let ours = unsafe { nix::libc::getpriority(nix::libc::PRIO_PROCESS as _, 0) };
+    let expected = (ours + 5).clamp(-20, 19);
+
+    assert_eq!(stdout(&run(&["--renice-by", "5", FIXTURE, "nice"])), vec![expected.to_string()]);
+}
+
+#[cfg(unix)]
+#[test]
+fn never_raises_the_priority_with_preserve_nice() {
+    let inner = env!("CARGO_BIN_EXE_nicer");
+
+    assert_eq!(stdout(&run(&[inner, "--preserve-nice", "--renice-by", "-5", FIXTURE, "nice"])), vec!["19"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn copies_the_priority_of_another_process() {
+    let mut reference = Command::new(FIXTURE).args(["sleep", "30"]).spawn().unwrap();
+    let ours = unsafe { nix::libc::getpriority(nix::libc::PRIO_PROCESS as _, 0) };
+    let nice = (ours + 7).min(19);
+
+    unsafe {
+        nix::libc::setpriority(nix::libc::PRIO_PROCESS as _, reference.id() as _, nice);
+    }
+
+    let output = run(&["--priority-from-pid", &reference.id().to_string(), FIXTURE, "nice"]);
+    reference.kill().unwrap();
+    reference.wait().unwrap();
+
+    assert_eq!(stdout(&output), vec![nice.to_string()]);
+}
+
+#[test]
+fn reports_each_adjustment_as_json() {
+    let mut target = Command::new(FIXTURE).args(["sleep", "30"]).spawn().unwrap();
+    let output = run(&["renice", "--json", &target.id().to_string()]);
+    target.kill().unwrap();
+    target.wait().unwrap();
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["version"], 1);
+    assert_eq!(report["adjusted"], 1);
+
+    let nice = report["adjustments"].as_array().unwrap().iter().find(|adjustment| ["nice", "background", "priority_class"].iter().any(|knob| adjustment["knob"] == *knob));
+    let nice = nice.unwrap();
+    assert_eq!(nice["outcome"], "applied");
+    #[cfg(unix)]
+    assert_eq!(nice["actual"], nice["requested"]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn puts_the_program_in_the_idle_io_class() {
+    const IOPRIO_CLASS_IDLE: &str = "3";
+
+    assert_eq!(stdout(&run(&[FIXTURE, "ioprio"])), vec![IOPRIO_CLASS_IDLE]);
+    assert_ne!(stdout(&run(&["--only-cpu", FIXTURE, "ioprio"])), vec![IOPRIO_CLASS_IDLE]);
+    assert_eq!(stdout(&run(&["--ioprio-from-nice", FIXTURE, "ioprio"])), vec!["0"]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn puts_the_program_in_the_requested_io_class() {
+    assert_eq!(stdout(&run(&["--io-class", "best-effort", "--io-level", "5", FIXTURE, "ioprio"])), vec!["2"]);
+    assert_eq!(stdout(&run(&["--io-class", "none", FIXTURE, "ioprio"])), vec!["0"]);
+    assert!(!run(&["--io-class", "idle", "--io-level", "5", FIXTURE, "ioprio"]).status.success());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn switches_the_scheduling_policy() {
+    const SCHED_BATCH: &str = "3";
+    const SCHED_IDLE: &str = "5";
+
+    assert_eq!(stdout(&run(&["--sched", "batch", FIXTURE, "sched"])), vec![SCHED_BATCH]);
+    assert_eq!(stdout(&run(&["--lowest", FIXTURE, "sched"])), vec![SCHED_IDLE]);
+    assert_eq!(stdout(&run(&["--lowest", "--sched", "batch", FIXTURE, "sched"])), vec![SCHED_BATCH]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn adjusts_the_oom_score() {
+    assert_eq!(stdout(&run(&["--oom-score-adj", "500", FIXTURE, "oom"])), vec!["500"]);
+    assert_eq!(stdout(&run(&["--lowest", FIXTURE, "oom"])), vec!["1000"]);
+    assert!(!run(&["--oom-score-adj", "1001", FIXTURE, "oom"]).status.success());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn pins_the_program_to_cpus() {
+    assert_eq!(stdout(&run(&["--cpus", "0", FIXTURE, "affinity"])), vec!["0"]);
+    assert!(!run(&["--cpus", "1-0", FIXTURE, "affinity"]).status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn caps_the_number_of_processes() {
+    assert_eq!(stdout(&run(&["--max-procs", "4096", FIXTURE, "nproc"])), vec!["4096"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn forwards_signals() {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+    use std::os::unix::process::ExitStatusExt;
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+    use std::thread;
+    use std::time::Duration;
+
+    for signal in [Signal::SIGTERM, Signal::SIGUSR2] {
+        let mut child = nicer().args([FIXTURE, "ready", "sleep", "10"]).stdout(Stdio::piped()).spawn().unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+        assert_eq!(line, "ready\n");
+
+        // Give nicer a moment to set up its handlers after spawning.
+        thread::sleep(Duration::from_millis(200));
+        kill(Pid::from_raw(child.id() as i32), signal).unwrap();
+
+        // nicer itself survives the signal, then dies of it once the program has.
+        assert_eq!(child.wait().unwrap().signal(), Some(signal as i32));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn forwards_signals_to_the_whole_process_group() {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+    use std::os::unix::process::ExitStatusExt;
+    use std::io::{BufRead, BufReader, Read};
+    use std::process::Stdio;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // The fixture is the shell's child, which a signal for the shell alone
+    // would leave running, holding stdout open.
+    let mut child = nicer().args(["sh", "-c", "\"$0\" ready sleep 30 & wait", FIXTURE]).stdin(Stdio::null()).stdout(Stdio::piped()).spawn().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line, "ready\n");
+
+    thread::sleep(Duration::from_millis(200));
+    let started = Instant::now();
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM).unwrap();
+
+    stdout.read_to_string(&mut line).unwrap();
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert_eq!(child.wait().unwrap().signal(), Some(15));
+}
+
+#[cfg(unix)]
+#[test]
+fn relays_ctrl_c_in_the_foreground_only_once() {
+    use nix::libc;
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+    use std::fs::File;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::io::FromRawFd;
+    use std::os::unix::process::CommandExt;
+    use std::process::Stdio;
+    use std::thread;
+    use std::time::Duration;
+
+    // nicer leads a session on a terminal of its own, so it runs in the
+    // foreground and the program shares its process group.
+    let pty = nix::pty::openpty(None, None).unwrap();
+    let mut command = nicer();
+    command.args([FIXTURE, "count-interrupts", "ready", "sleep", "1", "interrupts", "sleep", "1", "interrupts"]);
+    command.stdin(unsafe { Stdio::from_raw_fd(pty.slave) }).stdout(Stdio::piped()).stderr(Stdio::null());
+
+    unsafe {
+        command.pre_exec(|| match libc::setsid() != -1 && libc::ioctl(0, libc::TIOCSCTTY as _, 0) != -1 {
+            true => Ok(()),
+            false => Err(std::io::Error::last_os_error()),
+        });
+    }
+
+    let mut child = command.spawn().unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    assert_eq!(lines.next().unwrap().unwrap(), "ready");
+
+    thread::sleep(Duration::from_millis(200));
+    let mut terminal = unsafe { File::from_raw_fd(pty.master) };
+    terminal.write_all(b"\x03").unwrap();
+
+    assert_eq!(lines.next().unwrap().unwrap(), "1");
+
+    // One sent to nicer alone still has to be relayed.
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+    assert_eq!(lines.next().unwrap().unwrap(), "2");
+    assert!(child.wait().unwrap().success());
+}
+
+#[cfg(unix)]
+#[test]
+fn stops_the_whole_process_group_at_the_timeout() {
+    use std::time::{Duration, Instant};
+
+    // As above, the fixture would otherwise hold stdout open for 30s.
+    let started = Instant::now();
+    let output = run(&["--timeout", "1s", "sh", "-c", "\"$0\" ready sleep 30 & wait", FIXTURE]);
+    assert_eq!(stdout(&output), vec!["ready"]);
+    assert_eq!(output.status.code(), Some(124));
+    assert!(started.elapsed() < Duration::from_secs(10));
+}
+
+#[cfg(unix)]
+#[test]
+fn restarts_the_program_when_files_change() {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+    use std::os::unix::process::ExitStatusExt;
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let dir = std::env::temp_dir().join(format!("nicer-test-{}-watch", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("input"), "before").unwrap();
+
+    let mut child = nicer().arg("--watch").arg(&dir).args([FIXTURE, "ready", "sleep", "10"]).stdout(Stdio::piped()).spawn().unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    assert_eq!(lines.next().unwrap().unwrap(), "ready");
+
+    fs::write(dir.join("input"), "after, longer").unwrap();
+    assert_eq!(lines.next().unwrap().unwrap(), "ready");
+
+    // SIGTERM stops the program, and the watching.
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM).unwrap();
+    assert_eq!(child.wait().unwrap().signal(), Some(15));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn runs_once_at_a_time() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let path = std::env::temp_dir().join(format!("nicer-test-{}.lock", std::process::id()));
+    let lock = path.to_str().unwrap();
+
+    let mut first = nicer().args(["--once", lock, FIXTURE, "ready", "sleep", "2"]).stdout(Stdio::piped()).spawn().unwrap();
+    let mut line = String::new();
+    BufReader::new(first.stdout.take().unwrap()).read_line(&mut line).unwrap();
+    assert_eq!(line, "ready\n");
+
+    let second = run(&["--once", lock, FIXTURE, "echo", "second"]);
+    assert_eq!(second.status.code(), Some(64));
+    assert!(stdout(&second).is_empty());
+    assert!(String::from_utf8_lossy(&second.stderr).contains(&format!("(pid {})", first.id())));
+
+    assert!(first.wait().unwrap().success());
+    assert_eq!(stdout(&run(&["--once", lock, FIXTURE, "echo", "third"])), vec!["third"]);
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn resumes_suspended_programs_on_a_signal() {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+    use std::process::Stdio;
+    use std::thread;
+    use std::time::Duration;
+
+    let child = nicer().args(["--start-suspended", "--resume-on-signal", "USR2", FIXTURE, "echo", "resumed"]).stdout(Stdio::piped()).spawn().unwrap();
+
+    thread::sleep(Duration::from_millis(500));
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGUSR2).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(stdout(&output), vec!["resumed"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn stops_the_program_at_its_timeout() {
+    use std::time::{Duration, Instant};
+
+    let started = Instant::now();
+    assert_eq!(run(&["--timeout", "1s", FIXTURE, "ready", "sleep", "30"]).status.code(), Some(124));
+
+    // Ignoring SIGTERM only lasts until --kill-after.
+    let output = run(&["--timeout", "1s", "--kill-after", "1s", "sh", "-c", "trap '' TERM; exec \"$0\" ready sleep 30", FIXTURE]);
+    assert_eq!(stdout(&output), vec!["ready"]);
+    assert_eq!(output.status.code(), Some(124));
+    assert!(started.elapsed() < Duration::from_secs(10));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn takes_the_program_down_with_it() {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+    use std::thread;
+    use std::time::Duration;
+
+    let mut child = nicer().args(["--tie-lifetime", "--print-pid=stdout", FIXTURE, "sleep", "30"]).stdout(Stdio::piped()).spawn().unwrap();
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+    let program = Pid::from_raw(line.trim().parse().unwrap());
+
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGKILL).unwrap();
+    child.wait().unwrap();
+
+    // Gone, or at most a zombie nobody has reaped yet.
+    thread::sleep(Duration::from_millis(500));
+    let state = fs::read_to_string(format!("/proc/{}/stat", program)).unwrap_or_default();
+    assert!(state.is_empty() || state.rsplit(") ").next().unwrap().starts_with('Z'), "{}", state);
+}